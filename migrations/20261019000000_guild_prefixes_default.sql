@@ -0,0 +1,9 @@
+-- The bot has always created guild_prefixes itself with a default of 'td!', which is
+-- what it falls back to in code too. Rebuild the table so the migrated schema agrees.
+CREATE TABLE guild_prefixes_new (
+    guild_id INTEGER PRIMARY KEY,
+    prefix VARCHAR(10) NOT NULL DEFAULT 'td!'
+);
+INSERT INTO guild_prefixes_new (guild_id, prefix) SELECT guild_id, prefix FROM guild_prefixes;
+DROP TABLE guild_prefixes;
+ALTER TABLE guild_prefixes_new RENAME TO guild_prefixes;
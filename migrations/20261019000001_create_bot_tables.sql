@@ -0,0 +1,260 @@
+-- Every table the bot used to create inline at startup. IF NOT EXISTS so databases
+-- that already have them from before migrations were run keep their data.
+
+CREATE TABLE IF NOT EXISTS mod_cases (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    moderator_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE TABLE IF NOT EXISTS automod_config (
+    guild_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    max_messages INTEGER NOT NULL DEFAULT 8,
+    window_secs INTEGER NOT NULL DEFAULT 10,
+    max_duplicates INTEGER NOT NULL DEFAULT 4,
+    max_mentions INTEGER NOT NULL DEFAULT 6,
+    action TEXT NOT NULL DEFAULT 'timeout',
+    timeout_secs INTEGER NOT NULL DEFAULT 600
+);
+
+CREATE TABLE IF NOT EXISTS filter_rules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    pattern TEXT NOT NULL,
+    action TEXT NOT NULL DEFAULT 'delete'
+);
+
+CREATE TABLE IF NOT EXISTS filter_exemptions (
+    guild_id INTEGER NOT NULL,
+    target_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    PRIMARY KEY (guild_id, target_id)
+);
+
+CREATE TABLE IF NOT EXISTS antiraid_config (
+    guild_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    join_threshold INTEGER NOT NULL DEFAULT 10,
+    window_secs INTEGER NOT NULL DEFAULT 10,
+    min_account_age_secs INTEGER NOT NULL DEFAULT 604800,
+    new_account_action TEXT NOT NULL DEFAULT 'timeout',
+    timeout_secs INTEGER NOT NULL DEFAULT 3600,
+    slowmode_secs INTEGER NOT NULL DEFAULT 30,
+    alert_channel_id INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS lockdowns (
+    guild_id INTEGER PRIMARY KEY,
+    started_at INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    previous_verification INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS lockdown_channels (
+    guild_id INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    previous_slowmode INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, channel_id)
+);
+
+CREATE TABLE IF NOT EXISTS channel_locks (
+    channel_id INTEGER PRIMARY KEY,
+    guild_id INTEGER NOT NULL,
+    had_overwrite BOOLEAN NOT NULL,
+    allow INTEGER NOT NULL,
+    deny INTEGER NOT NULL,
+    locked_by INTEGER NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    locked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS auto_slowmode (
+    channel_id INTEGER PRIMARY KEY,
+    guild_id INTEGER NOT NULL,
+    min_secs INTEGER NOT NULL DEFAULT 0,
+    max_secs INTEGER NOT NULL DEFAULT 30,
+    target_rate INTEGER NOT NULL DEFAULT 30,
+    current_secs INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS link_filter_config (
+    guild_id INTEGER PRIMARY KEY,
+    invites BOOLEAN NOT NULL DEFAULT 0,
+    masked BOOLEAN NOT NULL DEFAULT 0,
+    mode TEXT NOT NULL DEFAULT 'off',
+    log_channel_id INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS link_domains (
+    guild_id INTEGER NOT NULL,
+    domain TEXT NOT NULL,
+    list TEXT NOT NULL,
+    PRIMARY KEY (guild_id, domain)
+);
+
+CREATE TABLE IF NOT EXISTS link_exempt_roles (
+    guild_id INTEGER NOT NULL,
+    role_id INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, role_id)
+);
+
+CREATE TABLE IF NOT EXISTS attachment_rules (
+    channel_id INTEGER NOT NULL,
+    guild_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    PRIMARY KEY (channel_id, kind)
+);
+
+CREATE TABLE IF NOT EXISTS attachment_config (
+    guild_id INTEGER PRIMARY KEY,
+    action TEXT NOT NULL DEFAULT 'delete'
+);
+
+CREATE TABLE IF NOT EXISTS alt_detection_config (
+    guild_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    min_account_age_secs INTEGER NOT NULL DEFAULT 604800,
+    flag_default_avatar BOOLEAN NOT NULL DEFAULT 1,
+    timeout_secs INTEGER NOT NULL DEFAULT 3600,
+    alert_channel_id INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS raider_patterns (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    pattern TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS antinuke_config (
+    guild_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    window_secs INTEGER NOT NULL DEFAULT 60,
+    max_channel_deletes INTEGER NOT NULL DEFAULT 3,
+    max_role_deletes INTEGER NOT NULL DEFAULT 3,
+    max_bans INTEGER NOT NULL DEFAULT 5,
+    max_webhook_creates INTEGER NOT NULL DEFAULT 3
+);
+
+CREATE TABLE IF NOT EXISTS antinuke_trusted (
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS nickname_policy (
+    guild_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    dehoist BOOLEAN NOT NULL DEFAULT 1,
+    replacement TEXT NOT NULL DEFAULT 'Dehoisted',
+    log_channel_id INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS nickname_words (
+    guild_id INTEGER NOT NULL,
+    word TEXT NOT NULL,
+    PRIMARY KEY (guild_id, word)
+);
+
+CREATE TABLE IF NOT EXISTS mod_notes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    author_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE TABLE IF NOT EXISTS report_config (
+    guild_id INTEGER PRIMARY KEY,
+    channel_id INTEGER,
+    timeout_secs INTEGER NOT NULL DEFAULT 3600
+);
+
+CREATE TABLE IF NOT EXISTS reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    reporter_id INTEGER NOT NULL,
+    target_id INTEGER NOT NULL,
+    channel_id INTEGER,
+    message_id INTEGER,
+    reason TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'open',
+    action TEXT,
+    handled_by INTEGER,
+    queue_channel_id INTEGER,
+    queue_message_id INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    resolved_at INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS modmail_config (
+    guild_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    channel_id INTEGER,
+    log_channel_id INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS modmail_tickets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    thread_id INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'open',
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    closed_at INTEGER,
+    closed_by INTEGER,
+    close_reason TEXT
+);
+
+CREATE TABLE IF NOT EXISTS modmail_messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ticket_id INTEGER NOT NULL,
+    author_id INTEGER NOT NULL,
+    author_name TEXT NOT NULL,
+    from_staff BOOLEAN NOT NULL,
+    anonymous BOOLEAN NOT NULL DEFAULT 0,
+    content TEXT NOT NULL,
+    attachments TEXT NOT NULL DEFAULT '',
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE TABLE IF NOT EXISTS appeal_config (
+    guild_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    channel_id INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS ban_appeals (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    ban_reason TEXT NOT NULL,
+    appeal TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    response TEXT,
+    handled_by INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    resolved_at INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS verification_config (
+    guild_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    unverified_role_id INTEGER,
+    member_role_id INTEGER,
+    challenge BOOLEAN NOT NULL DEFAULT 0,
+    kick_after_secs INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS verification_pending (
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    joined_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    PRIMARY KEY (guild_id, user_id)
+);
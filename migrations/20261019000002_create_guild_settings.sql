@@ -0,0 +1,11 @@
+-- One row per setting a guild has changed from its default, replacing the table per
+-- setting. The prefix is the only setting so far.
+CREATE TABLE guild_settings (
+    guild_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (guild_id, key)
+);
+INSERT INTO guild_settings (guild_id, key, value)
+SELECT guild_id, 'prefix', prefix FROM guild_prefixes;
+DROP TABLE guild_prefixes;
@@ -0,0 +1,8 @@
+-- The prefix setting holds a space separated list now.
+UPDATE guild_settings SET key = 'prefixes' WHERE key = 'prefix';
+
+-- Prefixes users set for their own DMs with the bot, in the same format.
+CREATE TABLE user_prefixes (
+    user_id INTEGER PRIMARY KEY,
+    prefixes TEXT NOT NULL
+);
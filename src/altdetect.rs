@@ -0,0 +1,815 @@
+use crate::antinuke;
+use crate::moderation::{self, Hierarchy, ModAction, reply_ephemeral};
+use crate::wordfilter::normalize;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use regex::{RegexBuilder, RegexSet, RegexSetBuilder};
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Prefix of the custom ids on alert buttons, routed here by the event handler.
+pub const BUTTON_PREFIX: &str = "alt:";
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// How many name patterns a guild can have.
+const MAX_PATTERNS: usize = 25;
+/// How long a name pattern can be, in characters.
+const MAX_PATTERN_CHARS: usize = 200;
+/// Embed fields hold at most 1024 characters.
+const FIELD_LIMIT: usize = 1024;
+
+/// Why a new member looks suspicious.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Flag {
+    YoungAccount(i64),
+    DefaultAvatar,
+    /// The matching pattern's id and the pattern itself.
+    NamePattern(i64, String),
+}
+
+impl std::fmt::Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Flag::YoungAccount(age) => write!(
+                f,
+                "Account is only {} old",
+                moderation::format_duration(*age as u64)
+            ),
+            Flag::DefaultAvatar => f.write_str("Default avatar"),
+            Flag::NamePattern(id, pattern) => write!(
+                f,
+                "Name matches pattern #{} `{}`",
+                id,
+                moderation::shorten(&pattern.replace('`', "'"), 100)
+            ),
+        }
+    }
+}
+
+/// A guild's alt detection settings, as stored in `alt_detection_config` and
+/// `raider_patterns`.
+pub struct AltSettings {
+    pub enabled: bool,
+    pub min_account_age_secs: i64,
+    pub flag_default_avatar: bool,
+    pub timeout_secs: i64,
+    pub alert_channel_id: Option<serenity::ChannelId>,
+    /// `(id, pattern)`, in the same order as `set`.
+    pub patterns: Vec<(i64, String)>,
+    set: RegexSet,
+}
+
+// Helper struct to map the query result
+struct AltConfigRow {
+    enabled: bool,
+    min_account_age_secs: i64,
+    flag_default_avatar: bool,
+    timeout_secs: i64,
+    alert_channel_id: Option<i64>,
+}
+
+impl AltSettings {
+    /// Everything suspicious about a new member.
+    pub fn flags(&self, member: &serenity::Member) -> Vec<Flag> {
+        self.flags_at(member, serenity::Timestamp::now().unix_timestamp())
+    }
+
+    fn flags_at(&self, member: &serenity::Member, now: i64) -> Vec<Flag> {
+        let mut flags = Vec::new();
+        let age = now - member.user.id.created_at().unix_timestamp();
+        if age < self.min_account_age_secs {
+            flags.push(Flag::YoungAccount(age.max(0)));
+        }
+        if self.flag_default_avatar && member.user.avatar.is_none() {
+            flags.push(Flag::DefaultAvatar);
+        }
+
+        let names = [
+            Some(member.user.name.as_str()),
+            member.user.global_name.as_deref(),
+            member.nick.as_deref(),
+        ];
+        for name in names.into_iter().flatten() {
+            let matched = self
+                .set
+                .matches(name)
+                .into_iter()
+                .chain(self.set.matches(&normalize(name)))
+                .next();
+            if let Some(i) = matched {
+                let (id, pattern) = &self.patterns[i];
+                flags.push(Flag::NamePattern(*id, pattern.clone()));
+                break;
+            }
+        }
+        flags
+    }
+}
+
+/// Alt detection state shared between the event handler and the commands.
+#[derive(Default)]
+pub struct AltDetect {
+    guilds: RwLock<HashMap<serenity::GuildId, Arc<AltSettings>>>,
+}
+
+impl AltDetect {
+    /// The guild's settings, loaded from the database when not cached.
+    pub async fn settings(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Arc<AltSettings>, Error> {
+        if let Some(settings) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(settings.clone());
+        }
+        let settings = Arc::new(load_settings(pool, guild_id).await?);
+        self.guilds
+            .write()
+            .unwrap()
+            .insert(guild_id, settings.clone());
+        Ok(settings)
+    }
+
+    /// Drops the cached settings so the next join reloads them.
+    pub fn invalidate(&self, guild_id: serenity::GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+}
+
+async fn load_settings(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<AltSettings, Error> {
+    let guild_id = guild_id.get() as i64;
+    let config = sqlx::query_as!(
+        AltConfigRow,
+        "SELECT enabled, min_account_age_secs, flag_default_avatar, timeout_secs, alert_channel_id
+         FROM alt_detection_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(AltConfigRow {
+        enabled: false,
+        min_account_age_secs: 7 * 24 * 60 * 60,
+        flag_default_avatar: true,
+        timeout_secs: 60 * 60,
+        alert_channel_id: None,
+    });
+    let patterns: Vec<(i64, String)> = sqlx::query!(
+        "SELECT id, pattern FROM raider_patterns WHERE guild_id = ? ORDER BY id",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.pattern))
+    .collect();
+    let set = RegexSetBuilder::new(patterns.iter().map(|(_, pattern)| pattern))
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT * 4)
+        .build()?;
+
+    Ok(AltSettings {
+        enabled: config.enabled,
+        min_account_age_secs: config.min_account_age_secs,
+        flag_default_avatar: config.flag_default_avatar,
+        timeout_secs: config.timeout_secs,
+        alert_channel_id: config
+            .alert_channel_id
+            .map(|id| serenity::ChannelId::new(id as u64)),
+        patterns,
+        set,
+    })
+}
+
+/// Checks a new member and posts an alert with quick actions if they look suspicious.
+pub async fn member_joined(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    altdetect: &AltDetect,
+    member: &serenity::Member,
+) -> Result<(), Error> {
+    if member.user.bot {
+        return Ok(());
+    }
+    let settings = altdetect.settings(pool, member.guild_id).await?;
+    let Some(alert_channel_id) = settings.alert_channel_id.filter(|_| settings.enabled) else {
+        return Ok(());
+    };
+    let flags = settings.flags(member);
+    if flags.is_empty() {
+        return Ok(());
+    }
+
+    let flags = flags
+        .iter()
+        .map(|flag| format!("• {}", flag))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let user_id = member.user.id;
+    let button = |action: &str, label: &str, style| {
+        serenity::CreateButton::new(format!("{}{}:{}", BUTTON_PREFIX, action, user_id))
+            .label(label)
+            .style(style)
+    };
+    alert_channel_id
+        .send_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .embed(
+                    serenity::CreateEmbed::new()
+                        .title("⚠️ Suspicious join")
+                        .thumbnail(member.user.face())
+                        .description(format!(
+                            "{} ({})\nAccount created <t:{}:R>",
+                            member.user.mention(),
+                            member.user.tag(),
+                            user_id.created_at().unix_timestamp()
+                        ))
+                        .field("Flags", flags, false)
+                        .footer(serenity::CreateEmbedFooter::new(format!(
+                            "User ID: {}",
+                            user_id
+                        )))
+                        .timestamp(serenity::Timestamp::now())
+                        .color(serenity::Color::ORANGE),
+                )
+                .components(vec![serenity::CreateActionRow::Buttons(vec![
+                    button("kick", "Kick", serenity::ButtonStyle::Danger),
+                    button("ban", "Ban", serenity::ButtonStyle::Danger),
+                    button("timeout", "Timeout", serenity::ButtonStyle::Primary),
+                    button("ignore", "Ignore", serenity::ButtonStyle::Secondary),
+                ])]),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Handles the kick/ban/timeout/ignore buttons on an alert.
+pub async fn handle_button(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    altdetect: &AltDetect,
+    antinuke: &antinuke::AntiNuke,
+    interaction: &serenity::ComponentInteraction,
+) -> Result<(), Error> {
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+    let Some((action, user_id)) = interaction
+        .data
+        .custom_id
+        .strip_prefix(BUTTON_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+    else {
+        return Ok(());
+    };
+    let Ok(user_id) = user_id.parse::<serenity::UserId>() else {
+        return Ok(());
+    };
+
+    let permissions = interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .unwrap_or_default();
+    let allowed = match action {
+        "kick" => permissions.kick_members(),
+        "ban" => permissions.ban_members(),
+        "timeout" => permissions.moderate_members(),
+        _ => {
+            permissions.kick_members()
+                || permissions.ban_members()
+                || permissions.moderate_members()
+        }
+    };
+    if !allowed {
+        return reply_ephemeral(ctx, interaction, "❌ You don't have permission to do that.").await;
+    }
+
+    let moderator = &interaction.user;
+    if action != "ignore" {
+        let hierarchy = Hierarchy::load(ctx, guild_id, moderator.id).await?;
+        if let Err(why) = hierarchy.check(ctx, user_id).await? {
+            return reply_ephemeral(ctx, interaction, format!("❌ {}", why)).await;
+        }
+    }
+
+    let reason = format!("Flagged on join, actioned by {}", moderator.tag());
+    let outcome = match action {
+        "kick" => {
+            guild_id.kick_with_reason(ctx, user_id, &reason).await?;
+            moderation::record_case(
+                pool,
+                guild_id,
+                user_id,
+                moderator.id,
+                ModAction::Kick,
+                &reason,
+            )
+            .await?;
+            "👢 Kicked"
+        }
+        "ban" => {
+            guild_id.ban_with_reason(ctx, user_id, 0, &reason).await?;
+            // The ban went through either way, don't report it as failed.
+            if let Err(e) = antinuke::record_ban(ctx, pool, antinuke, guild_id, moderator.id).await
+            {
+                eprintln!("Anti-nuke error in guild {}: {}", guild_id, e);
+            }
+            moderation::record_case(
+                pool,
+                guild_id,
+                user_id,
+                moderator.id,
+                ModAction::Ban,
+                &reason,
+            )
+            .await?;
+            "🔨 Banned"
+        }
+        "timeout" => {
+            let settings = altdetect.settings(pool, guild_id).await?;
+            let until = serenity::Timestamp::from_unix_timestamp(
+                serenity::Timestamp::now().unix_timestamp() + settings.timeout_secs,
+            )?;
+            guild_id
+                .edit_member(
+                    ctx,
+                    user_id,
+                    serenity::EditMember::new()
+                        .disable_communication_until_datetime(until)
+                        .audit_log_reason(&reason),
+                )
+                .await?;
+            moderation::record_case(
+                pool,
+                guild_id,
+                user_id,
+                moderator.id,
+                ModAction::Timeout,
+                &reason,
+            )
+            .await?;
+            "🔇 Timed out"
+        }
+        _ => "✅ Ignored",
+    };
+
+    // Keep the alert for the record, but take the buttons away so nobody acts twice.
+    let mut embed = interaction
+        .message
+        .embeds
+        .first()
+        .cloned()
+        .map(serenity::CreateEmbed::from)
+        .unwrap_or_default();
+    embed = embed.field(
+        "Handled",
+        format!("{} by {}", outcome, moderator.mention()),
+        false,
+    );
+    interaction
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::UpdateMessage(
+                serenity::CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+// Changes the guild's config row, creating it with the defaults if needed.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut AltConfigRow),
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let settings = data.altdetect.settings(&data.db_pool, guild_id).await?;
+    let mut config = AltConfigRow {
+        enabled: settings.enabled,
+        min_account_age_secs: settings.min_account_age_secs,
+        flag_default_avatar: settings.flag_default_avatar,
+        timeout_secs: settings.timeout_secs,
+        alert_channel_id: settings.alert_channel_id.map(|id| id.get() as i64),
+    };
+    change(&mut config);
+
+    let guild_id_db = guild_id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO alt_detection_config
+            (guild_id, enabled, min_account_age_secs, flag_default_avatar, timeout_secs, alert_channel_id)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            min_account_age_secs = excluded.min_account_age_secs,
+            flag_default_avatar = excluded.flag_default_avatar,
+            timeout_secs = excluded.timeout_secs,
+            alert_channel_id = excluded.alert_channel_id",
+        guild_id_db,
+        config.enabled,
+        config.min_account_age_secs,
+        config.flag_default_avatar,
+        config.timeout_secs,
+        config.alert_channel_id
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.altdetect.invalidate(guild_id);
+    Ok(())
+}
+
+// Lists the patterns for the settings embed, leaving off whatever doesn't fit in a field.
+fn patterns_field(patterns: &[(i64, String)]) -> String {
+    if patterns.is_empty() {
+        return "None".to_string();
+    }
+    let mut field = String::new();
+    for (shown, (id, pattern)) in patterns.iter().enumerate() {
+        let line = format!(
+            "**#{}** `{}`",
+            id,
+            moderation::shorten(&pattern.replace('`', "'"), 100)
+        );
+        let more = format!("\n…and {} more", patterns.len() - shown);
+        let needed = field.chars().count() + 1 + line.chars().count();
+        // Keep room for the "and more" line unless this is the last pattern.
+        let room = if shown + 1 == patterns.len() {
+            FIELD_LIMIT
+        } else {
+            FIELD_LIMIT - more.chars().count()
+        };
+        if needed > room {
+            field.push_str(&more);
+            break;
+        }
+        if !field.is_empty() {
+            field.push('\n');
+        }
+        field.push_str(&line);
+    }
+    field
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let settings = data.altdetect.settings(&data.db_pool, guild_id).await?;
+    let patterns = patterns_field(&settings.patterns);
+    let embed = serenity::CreateEmbed::new()
+        .title("Alt detection")
+        .field(
+            "Status",
+            if settings.enabled {
+                "Enabled"
+            } else {
+                "Disabled"
+            },
+            true,
+        )
+        .field(
+            "Alerts",
+            settings
+                .alert_channel_id
+                .map(|id| id.mention().to_string())
+                .unwrap_or_else(|| "Not set, nothing will be posted".to_string()),
+            true,
+        )
+        .field(
+            "Young accounts",
+            format!(
+                "Younger than {}",
+                moderation::format_duration(settings.min_account_age_secs as u64)
+            ),
+            false,
+        )
+        .field(
+            "Default avatars",
+            if settings.flag_default_avatar {
+                "Flagged"
+            } else {
+                "Ignored"
+            },
+            true,
+        )
+        .field(
+            "Timeout button",
+            moderation::format_duration(settings.timeout_secs as u64),
+            true,
+        )
+        .field("Name patterns", patterns, false)
+        .color(serenity::Color::DARK_RED);
+    let mut reply = poise::CreateReply::default().embed(embed);
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Flag suspicious new members for the moderators.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands(
+        "show",
+        "enable",
+        "disable",
+        "alerts",
+        "age",
+        "avatar",
+        "timeout",
+        "addpattern",
+        "removepattern"
+    )
+)]
+pub async fn altdetect(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the alt detection settings.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Start flagging suspicious new members.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn enable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = true).await?;
+    show_inner(ctx, "Alt detection is now enabled.").await
+}
+
+/// Stop flagging new members.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = false).await?;
+    ctx.say("Alt detection is now disabled.").await?;
+    Ok(())
+}
+
+/// Choose the moderator channel alerts are posted in.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn alerts(
+    ctx: Context<'_>,
+    #[description = "Channel for join alerts"] channel: serenity::GuildChannel,
+) -> Result<(), Error> {
+    let channel_id = channel.id.get() as i64;
+    update_config(ctx, |config| config.alert_channel_id = Some(channel_id)).await?;
+    show_inner(ctx, "Alert channel updated.").await
+}
+
+/// Flag accounts younger than this.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn age(
+    ctx: Context<'_>,
+    #[description = "Minimum account age (e.g. 7d), 0 to stop flagging by age"] age: String,
+) -> Result<(), Error> {
+    let Some(age) = moderation::parse_duration(&age) else {
+        ctx.say("❌ That isn't a valid age, e.g. `7d`.").await?;
+        return Ok(());
+    };
+    let age = age.as_secs() as i64;
+    update_config(ctx, |config| config.min_account_age_secs = age).await?;
+    show_inner(ctx, "Account age updated.").await
+}
+
+/// Choose whether members with a default avatar are flagged.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn avatar(
+    ctx: Context<'_>,
+    #[description = "Flag default avatars?"] flag: bool,
+) -> Result<(), Error> {
+    update_config(ctx, |config| config.flag_default_avatar = flag).await?;
+    show_inner(ctx, "Default avatar setting updated.").await
+}
+
+/// Set how long the Timeout button times members out for.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn timeout(
+    ctx: Context<'_>,
+    #[description = "Timeout length (e.g. 1h)"] duration: String,
+) -> Result<(), Error> {
+    let secs = match moderation::parse_duration(&duration) {
+        // Discord caps timeouts at 28 days.
+        Some(d) if d.as_secs() > 0 && d.as_secs() <= 28 * 24 * 60 * 60 => d.as_secs() as i64,
+        _ => {
+            ctx.say("❌ Timeouts must be between 1 second and 28 days, e.g. `1h`.")
+                .await?;
+            return Ok(());
+        }
+    };
+    update_config(ctx, |config| config.timeout_secs = secs).await?;
+    show_inner(ctx, "Timeout length updated.").await
+}
+
+/// Flag members whose names match a regular expression.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn addpattern(
+    ctx: Context<'_>,
+    #[description = "Regular expression, matched case-insensitively"]
+    #[rest]
+    pattern: String,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pattern = pattern.trim().to_string();
+    if pattern.is_empty() {
+        ctx.say("❌ The pattern can't be empty.").await?;
+        return Ok(());
+    }
+    if pattern.chars().count() > MAX_PATTERN_CHARS {
+        ctx.say(format!(
+            "❌ Patterns can be at most {} characters long.",
+            MAX_PATTERN_CHARS
+        ))
+        .await?;
+        return Ok(());
+    }
+    match RegexBuilder::new(&pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+    {
+        // Something like `.*` would flag everyone who joins.
+        Ok(regex) if regex.is_match("") => {
+            ctx.say("❌ That pattern matches every name.").await?;
+            return Ok(());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            ctx.say(format!("❌ That pattern is invalid: {}", e))
+                .await?;
+            return Ok(());
+        }
+    }
+    let data = ctx.data();
+    let guild_id_db = guild_id.get() as i64;
+    let count = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM raider_patterns WHERE guild_id = ?",
+        guild_id_db
+    )
+    .fetch_one(&data.db_pool)
+    .await?
+    .count;
+    if count as usize >= MAX_PATTERNS {
+        ctx.say(format!(
+            "❌ This server already has {} name patterns.",
+            MAX_PATTERNS
+        ))
+        .await?;
+        return Ok(());
+    }
+    let id = sqlx::query!(
+        "INSERT INTO raider_patterns (guild_id, pattern) VALUES (?, ?)",
+        guild_id_db,
+        pattern
+    )
+    .execute(&data.db_pool)
+    .await?
+    .last_insert_rowid();
+    data.altdetect.invalidate(guild_id);
+    ctx.say(format!("Added name pattern #{}.", id)).await?;
+    Ok(())
+}
+
+/// Stop flagging names matching a pattern.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn removepattern(
+    ctx: Context<'_>,
+    #[description = "Pattern number, see altdetect show"] id: i64,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let guild_id_db = guild_id.get() as i64;
+    let removed = sqlx::query!(
+        "DELETE FROM raider_patterns WHERE id = ? AND guild_id = ?",
+        id,
+        guild_id_db
+    )
+    .execute(&data.db_pool)
+    .await?
+    .rows_affected();
+    data.altdetect.invalidate(guild_id);
+    if removed == 0 {
+        ctx.say(format!("❌ There's no name pattern #{}.", id))
+            .await?;
+    } else {
+        ctx.say(format!("Removed name pattern #{}.", id)).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn settings(patterns: &[&str]) -> AltSettings {
+        let patterns: Vec<(i64, String)> = patterns
+            .iter()
+            .enumerate()
+            .map(|(i, pattern)| (i as i64 + 1, pattern.to_string()))
+            .collect();
+        AltSettings {
+            enabled: true,
+            min_account_age_secs: 7 * DAY,
+            flag_default_avatar: true,
+            timeout_secs: 3600,
+            alert_channel_id: None,
+            set: RegexSetBuilder::new(patterns.iter().map(|(_, pattern)| pattern))
+                .case_insensitive(true)
+                .build()
+                .unwrap(),
+            patterns,
+        }
+    }
+
+    // A member whose account was made `age` seconds before `NOW`.
+    fn member(name: &str, age: i64, avatar: bool) -> serenity::Member {
+        let created_ms = (NOW - age) as u64 * 1000 - 1_420_070_400_000;
+        let mut member = serenity::Member::default();
+        member.user.id = serenity::UserId::new(created_ms << 22);
+        member.user.name = name.to_string();
+        if avatar {
+            member.user.avatar = Some("0123456789abcdef0123456789abcdef".parse().unwrap());
+        }
+        member
+    }
+
+    #[test]
+    fn flags_young_accounts() {
+        let settings = settings(&[]);
+        assert_eq!(
+            settings.flags_at(&member("new", DAY, true), NOW),
+            vec![Flag::YoungAccount(DAY)]
+        );
+        assert!(
+            settings
+                .flags_at(&member("old", 30 * DAY, true), NOW)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn flags_default_avatars_only_when_asked() {
+        let mut settings = settings(&[]);
+        let plain = member("plain", 30 * DAY, false);
+        assert_eq!(settings.flags_at(&plain, NOW), vec![Flag::DefaultAvatar]);
+        settings.flag_default_avatar = false;
+        assert!(settings.flags_at(&plain, NOW).is_empty());
+    }
+
+    #[test]
+    fn flags_the_first_matching_name() {
+        let settings = settings(&["^raider\\d+$", "spam"]);
+        let mut raider = member("Raider42", 30 * DAY, true);
+        assert_eq!(
+            settings.flags_at(&raider, NOW),
+            vec![Flag::NamePattern(1, "^raider\\d+$".to_string())]
+        );
+        // Display names and nicknames count too, and are normalized before matching.
+        raider.user.name = "someone".to_string();
+        raider.nick = Some("5p4m".to_string());
+        assert_eq!(
+            settings.flags_at(&raider, NOW),
+            vec![Flag::NamePattern(2, "spam".to_string())]
+        );
+        raider.nick = None;
+        assert!(settings.flags_at(&raider, NOW).is_empty());
+    }
+
+    #[test]
+    fn shows_long_patterns_shortened() {
+        let flag = Flag::NamePattern(7, "x".repeat(MAX_PATTERN_CHARS)).to_string();
+        assert!(flag.starts_with("Name matches pattern #7 `x"), "{}", flag);
+        assert!(flag.chars().count() < 150, "{}", flag);
+    }
+
+    #[test]
+    fn lists_patterns_within_a_field() {
+        assert_eq!(patterns_field(&[]), "None");
+        assert_eq!(patterns_field(&[(3, "a`b".to_string())]), "**#3** `a'b`");
+        let long: Vec<(i64, String)> = (1..=MAX_PATTERNS as i64)
+            .map(|id| (id, "x".repeat(200)))
+            .collect();
+        let field = patterns_field(&long);
+        assert!(field.chars().count() <= FIELD_LIMIT, "{}", field.len());
+        assert!(field.ends_with("more"), "{}", field);
+    }
+}
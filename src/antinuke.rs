@@ -0,0 +1,625 @@
+use crate::moderation;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use serenity::model::guild::audit_log::{
+    Action, ChannelAction, MemberAction, RoleAction, WebhookAction,
+};
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// The destructive actions anti-nuke keeps count of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NukeAction {
+    ChannelDelete,
+    RoleDelete,
+    Ban,
+    WebhookCreate,
+}
+
+impl NukeAction {
+    fn from_audit_log(action: &Action) -> Option<Self> {
+        match action {
+            Action::Channel(ChannelAction::Delete) => Some(NukeAction::ChannelDelete),
+            Action::Role(RoleAction::Delete) => Some(NukeAction::RoleDelete),
+            Action::Member(MemberAction::BanAdd) => Some(NukeAction::Ban),
+            Action::Webhook(WebhookAction::Create) => Some(NukeAction::WebhookCreate),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for NukeAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NukeAction::ChannelDelete => "channel deletions",
+            NukeAction::RoleDelete => "role deletions",
+            NukeAction::Ban => "bans",
+            NukeAction::WebhookCreate => "webhook creations",
+        })
+    }
+}
+
+/// Permissions that make a role dangerous in the wrong hands.
+fn dangerous_permissions() -> serenity::Permissions {
+    serenity::Permissions::ADMINISTRATOR
+        | serenity::Permissions::MANAGE_GUILD
+        | serenity::Permissions::MANAGE_ROLES
+        | serenity::Permissions::MANAGE_CHANNELS
+        | serenity::Permissions::MANAGE_WEBHOOKS
+        | serenity::Permissions::BAN_MEMBERS
+        | serenity::Permissions::KICK_MEMBERS
+}
+
+/// A guild's anti-nuke settings, as stored in `antinuke_config` and `antinuke_trusted`.
+#[derive(Debug, Clone)]
+pub struct AntiNukeSettings {
+    pub enabled: bool,
+    pub window_secs: i64,
+    pub max_channel_deletes: i64,
+    pub max_role_deletes: i64,
+    pub max_bans: i64,
+    pub max_webhook_creates: i64,
+    pub trusted: HashSet<serenity::UserId>,
+}
+
+impl Default for AntiNukeSettings {
+    fn default() -> Self {
+        AntiNukeSettings {
+            enabled: false,
+            window_secs: 60,
+            max_channel_deletes: 3,
+            max_role_deletes: 3,
+            max_bans: 5,
+            max_webhook_creates: 3,
+            trusted: HashSet::new(),
+        }
+    }
+}
+
+impl AntiNukeSettings {
+    /// How many of `action` one actor may do inside the window, 0 means unlimited.
+    fn limit(&self, action: NukeAction) -> i64 {
+        match action {
+            NukeAction::ChannelDelete => self.max_channel_deletes,
+            NukeAction::RoleDelete => self.max_role_deletes,
+            NukeAction::Ban => self.max_bans,
+            NukeAction::WebhookCreate => self.max_webhook_creates,
+        }
+    }
+}
+
+// Helper struct to map the query result
+struct AntiNukeRow {
+    enabled: bool,
+    window_secs: i64,
+    max_channel_deletes: i64,
+    max_role_deletes: i64,
+    max_bans: i64,
+    max_webhook_creates: i64,
+}
+
+type ActionKey = (serenity::GuildId, serenity::UserId, NukeAction);
+
+/// Anti-nuke state shared between the event handler and the commands.
+#[derive(Default)]
+pub struct AntiNuke {
+    guilds: RwLock<HashMap<serenity::GuildId, Arc<AntiNukeSettings>>>,
+    actions: Mutex<HashMap<ActionKey, VecDeque<Instant>>>,
+}
+
+impl AntiNuke {
+    /// The guild's settings, loaded from the database when not cached.
+    pub async fn settings(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Arc<AntiNukeSettings>, Error> {
+        if let Some(settings) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(settings.clone());
+        }
+        let settings = Arc::new(load_settings(pool, guild_id).await?);
+        self.guilds
+            .write()
+            .unwrap()
+            .insert(guild_id, settings.clone());
+        Ok(settings)
+    }
+
+    /// Drops the cached settings so the next audit log entry reloads them.
+    pub fn invalidate(&self, guild_id: serenity::GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+
+    /// Counts an action and returns how many the actor did inside the window if that
+    /// went over the limit.
+    fn record(&self, key: ActionKey, settings: &AntiNukeSettings) -> Option<usize> {
+        let limit = settings.limit(key.2);
+        if limit <= 0 {
+            return None;
+        }
+        let now = Instant::now();
+        let window = Duration::from_secs(settings.window_secs.max(1) as u64);
+        let mut actions = self.actions.lock().unwrap();
+        // Drop anything that has gone quiet so the map doesn't grow forever.
+        actions.retain(|_, times| {
+            times
+                .back()
+                .is_some_and(|last| now.duration_since(*last) <= window)
+        });
+        let times = actions.entry(key).or_default();
+        times.push_back(now);
+        while let Some(first) = times.front() {
+            if now.duration_since(*first) > window {
+                times.pop_front();
+            } else {
+                break;
+            }
+        }
+        let count = times.len();
+        if count as i64 > limit {
+            actions.remove(&key);
+            Some(count)
+        } else {
+            None
+        }
+    }
+}
+
+async fn load_settings(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<AntiNukeSettings, Error> {
+    let guild_id = guild_id.get() as i64;
+    let row = sqlx::query_as!(
+        AntiNukeRow,
+        "SELECT enabled, window_secs, max_channel_deletes, max_role_deletes, max_bans,
+                max_webhook_creates
+         FROM antinuke_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let trusted = sqlx::query!(
+        "SELECT user_id FROM antinuke_trusted WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| serenity::UserId::new(row.user_id as u64))
+    .collect();
+
+    let mut settings = AntiNukeSettings {
+        trusted,
+        ..Default::default()
+    };
+    if let Some(row) = row {
+        settings.enabled = row.enabled;
+        settings.window_secs = row.window_secs;
+        settings.max_channel_deletes = row.max_channel_deletes;
+        settings.max_role_deletes = row.max_role_deletes;
+        settings.max_bans = row.max_bans;
+        settings.max_webhook_creates = row.max_webhook_creates;
+    }
+    Ok(settings)
+}
+
+/// Counts destructive audit log entries per actor and stops anyone who goes too far.
+pub async fn audit_log_entry(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    antinuke: &AntiNuke,
+    guild_id: serenity::GuildId,
+    entry: &serenity::AuditLogEntry,
+) -> Result<(), Error> {
+    let Some(action) = NukeAction::from_audit_log(&entry.action) else {
+        return Ok(());
+    };
+    // The bot's own actions are counted against the moderator who asked for them, by
+    // `record_ban`.
+    if entry.user_id == ctx.cache.current_user().id {
+        return Ok(());
+    }
+    count(ctx, pool, antinuke, guild_id, entry.user_id, action).await?;
+    Ok(())
+}
+
+/// Counts a ban the bot carried out for `moderator`, whose name isn't on the audit log
+/// entry. Returns `true` if that took them over the limit and they were dealt with.
+pub async fn record_ban(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    antinuke: &AntiNuke,
+    guild_id: serenity::GuildId,
+    moderator: serenity::UserId,
+) -> Result<bool, Error> {
+    count(ctx, pool, antinuke, guild_id, moderator, NukeAction::Ban).await
+}
+
+// Counts one action by `actor`, stripping their roles and telling the owner if they
+// went over the limit.
+async fn count(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    antinuke: &AntiNuke,
+    guild_id: serenity::GuildId,
+    actor: serenity::UserId,
+    action: NukeAction,
+) -> Result<bool, Error> {
+    let settings = antinuke.settings(pool, guild_id).await?;
+    let owner_id = ctx.cache.guild(guild_id).map(|guild| guild.owner_id);
+    if !settings.enabled || settings.trusted.contains(&actor) || Some(actor) == owner_id {
+        return Ok(false);
+    }
+    let Some(count) = antinuke.record((guild_id, actor, action), &settings) else {
+        return Ok(false);
+    };
+
+    let why = format!(
+        "{} {} in {}",
+        count,
+        action,
+        moderation::format_duration(settings.window_secs as u64)
+    );
+    let stripped = strip_dangerous_roles(ctx, guild_id, actor, &why).await;
+    let outcome = match &stripped {
+        Ok(roles) if roles.is_empty() => {
+            "They have no dangerous roles I can remove, deal with them by hand.".to_string()
+        }
+        Ok(roles) => format!(
+            "Removed {}.",
+            roles
+                .iter()
+                .map(|role| role.mention().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Err(e) => format!("Couldn't remove their roles: {}", e),
+    };
+    alert_owner(ctx, guild_id, actor, &why, &outcome).await?;
+    Ok(true)
+}
+
+// Takes every role with dangerous permissions away from `user_id` that the bot can
+// manage. Managed roles belong to an integration and can't be removed, so they stay.
+async fn strip_dangerous_roles(
+    ctx: &serenity::Context,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+    why: &str,
+) -> Result<Vec<serenity::RoleId>, Error> {
+    let member = guild_id.member(ctx, user_id).await?;
+    let bot_id = ctx.cache.current_user().id;
+    let bot = guild_id.member(ctx, bot_id).await?;
+    let roles = guild_id.roles(ctx).await?;
+    let bot_top = bot
+        .roles
+        .iter()
+        .filter_map(|id| roles.get(id))
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0);
+
+    let (dangerous, kept): (Vec<_>, Vec<_>) = member.roles.iter().partition(|id| {
+        roles.get(id).is_some_and(|role| {
+            role.permissions.intersects(dangerous_permissions())
+                && role.position < bot_top
+                && !role.managed
+        })
+    });
+    if dangerous.is_empty() {
+        return Ok(Vec::new());
+    }
+    guild_id
+        .edit_member(
+            ctx,
+            user_id,
+            serenity::EditMember::new()
+                .roles(kept)
+                .audit_log_reason(&format!("Anti-nuke: {}", why)),
+        )
+        .await?;
+    Ok(dangerous)
+}
+
+async fn alert_owner(
+    ctx: &serenity::Context,
+    guild_id: serenity::GuildId,
+    actor: serenity::UserId,
+    why: &str,
+    outcome: &str,
+) -> Result<(), Error> {
+    let guild = guild_id.to_partial_guild(ctx).await?;
+    let embed = serenity::CreateEmbed::new()
+        .title(format!("🚨 Anti-nuke triggered in {}", guild.name))
+        .description(format!("{} ({}) made {}.", actor.mention(), actor, why))
+        .field("Action taken", outcome, false)
+        .timestamp(serenity::Timestamp::now())
+        .color(serenity::Color::RED);
+    guild
+        .owner_id
+        .direct_message(ctx, serenity::CreateMessage::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+/// Only the server owner can change anti-nuke settings, since admins are who it
+/// protects against.
+async fn owner_only(ctx: Context<'_>) -> Result<bool, Error> {
+    let owner_id = ctx.guild().map(|guild| guild.owner_id);
+    if owner_id == Some(ctx.author().id) {
+        return Ok(true);
+    }
+    ctx.say("❌ Only the server owner can change anti-nuke settings.")
+        .await?;
+    Ok(false)
+}
+
+fn describe(settings: &AntiNukeSettings) -> serenity::CreateEmbed {
+    let limit = |n: i64| {
+        if n > 0 {
+            n.to_string()
+        } else {
+            "Unlimited".to_string()
+        }
+    };
+    let trusted = if settings.trusted.is_empty() {
+        "Only the owner".to_string()
+    } else {
+        settings
+            .trusted
+            .iter()
+            .map(|id| id.mention().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    serenity::CreateEmbed::new()
+        .title("Anti-nuke")
+        .field(
+            "Status",
+            if settings.enabled {
+                "Enabled"
+            } else {
+                "Disabled"
+            },
+            false,
+        )
+        .field(
+            format!(
+                "Limits per {}",
+                moderation::format_duration(settings.window_secs as u64)
+            ),
+            format!(
+                "Channel deletions: {}\nRole deletions: {}\nBans: {}\nWebhook creations: {}",
+                limit(settings.max_channel_deletes),
+                limit(settings.max_role_deletes),
+                limit(settings.max_bans),
+                limit(settings.max_webhook_creates)
+            ),
+            false,
+        )
+        .field("Trusted", trusted, false)
+        .color(serenity::Color::DARK_RED)
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let settings = data.antinuke.settings(&data.db_pool, guild_id).await?;
+    let mut reply = poise::CreateReply::default().embed(describe(&settings));
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+// Loads the current settings (or the defaults), applies `change`, and saves them back.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut AntiNukeSettings),
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let mut settings = (*data.antinuke.settings(&data.db_pool, guild_id).await?).clone();
+    change(&mut settings);
+
+    let guild_id_db = guild_id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO antinuke_config
+            (guild_id, enabled, window_secs, max_channel_deletes, max_role_deletes, max_bans,
+             max_webhook_creates)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            window_secs = excluded.window_secs,
+            max_channel_deletes = excluded.max_channel_deletes,
+            max_role_deletes = excluded.max_role_deletes,
+            max_bans = excluded.max_bans,
+            max_webhook_creates = excluded.max_webhook_creates",
+        guild_id_db,
+        settings.enabled,
+        settings.window_secs,
+        settings.max_channel_deletes,
+        settings.max_role_deletes,
+        settings.max_bans,
+        settings.max_webhook_creates
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.antinuke.invalidate(guild_id);
+    Ok(())
+}
+
+/// Stop a compromised or rogue admin from wrecking the server.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    check = "owner_only",
+    subcommands("show", "enable", "disable", "limits", "trust", "untrust")
+)]
+pub async fn antinuke(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the anti-nuke settings.
+#[poise::command(slash_command, prefix_command, guild_only, check = "owner_only")]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Start watching the audit log.
+#[poise::command(slash_command, prefix_command, guild_only, check = "owner_only")]
+pub async fn enable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |settings| settings.enabled = true).await?;
+    show_inner(
+        ctx,
+        "Anti-nuke is now enabled. Keep my role above your admin roles so I can act.",
+    )
+    .await
+}
+
+/// Stop watching the audit log.
+#[poise::command(slash_command, prefix_command, guild_only, check = "owner_only")]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |settings| settings.enabled = false).await?;
+    ctx.say("Anti-nuke is now disabled.").await?;
+    Ok(())
+}
+
+/// Change how many destructive actions one person may take. 0 means unlimited.
+#[poise::command(slash_command, prefix_command, guild_only, check = "owner_only")]
+pub async fn limits(
+    ctx: Context<'_>,
+    #[description = "Window length (e.g. 1m)"] window: Option<String>,
+    #[description = "Channel deletions allowed per window"]
+    #[min = 0]
+    channels: Option<u32>,
+    #[description = "Role deletions allowed per window"]
+    #[min = 0]
+    roles: Option<u32>,
+    #[description = "Bans allowed per window"]
+    #[min = 0]
+    bans: Option<u32>,
+    #[description = "Webhook creations allowed per window"]
+    #[min = 0]
+    webhooks: Option<u32>,
+) -> Result<(), Error> {
+    let window = match window {
+        Some(window) => match moderation::parse_duration(&window) {
+            Some(d) if d.as_secs() > 0 && d.as_secs() <= 24 * 60 * 60 => Some(d.as_secs() as i64),
+            _ => {
+                ctx.say("❌ The window must be between 1 second and 1 day, e.g. `1m`.")
+                    .await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    update_config(ctx, |settings| {
+        if let Some(window) = window {
+            settings.window_secs = window;
+        }
+        if let Some(channels) = channels {
+            settings.max_channel_deletes = channels as i64;
+        }
+        if let Some(roles) = roles {
+            settings.max_role_deletes = roles as i64;
+        }
+        if let Some(bans) = bans {
+            settings.max_bans = bans as i64;
+        }
+        if let Some(webhooks) = webhooks {
+            settings.max_webhook_creates = webhooks as i64;
+        }
+    })
+    .await?;
+    show_inner(ctx, "Anti-nuke limits updated.").await
+}
+
+/// Let someone (or a bot) take destructive actions without limits.
+#[poise::command(slash_command, prefix_command, guild_only, check = "owner_only")]
+pub async fn trust(
+    ctx: Context<'_>,
+    #[description = "User or bot to trust"] user: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let guild_id_db = guild_id.get() as i64;
+    let user_id = user.id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO antinuke_trusted (guild_id, user_id) VALUES (?, ?)
+         ON CONFLICT (guild_id, user_id) DO NOTHING",
+        guild_id_db,
+        user_id
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.antinuke.invalidate(guild_id);
+    show_inner(ctx, &format!("{} is now trusted.", user.tag())).await
+}
+
+/// Put someone back under the anti-nuke limits.
+#[poise::command(slash_command, prefix_command, guild_only, check = "owner_only")]
+pub async fn untrust(
+    ctx: Context<'_>,
+    #[description = "User or bot to stop trusting"] user: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let guild_id_db = guild_id.get() as i64;
+    let user_id = user.id.get() as i64;
+    sqlx::query!(
+        "DELETE FROM antinuke_trusted WHERE guild_id = ? AND user_id = ?",
+        guild_id_db,
+        user_id
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.antinuke.invalidate(guild_id);
+    show_inner(ctx, &format!("{} is no longer trusted.", user.tag())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_trips_past_the_limit() {
+        let antinuke = AntiNuke::default();
+        let settings = AntiNukeSettings {
+            max_bans: 3,
+            max_webhook_creates: 0,
+            ..Default::default()
+        };
+        let key = (
+            serenity::GuildId::new(1),
+            serenity::UserId::new(2),
+            NukeAction::Ban,
+        );
+        // The limit is how many are allowed, so only the one after it trips.
+        for _ in 0..3 {
+            assert_eq!(antinuke.record(key, &settings), None);
+        }
+        assert_eq!(antinuke.record(key, &settings), Some(4));
+        // The count starts over once the actor has been dealt with.
+        assert_eq!(antinuke.record(key, &settings), None);
+
+        let webhooks = (key.0, key.1, NukeAction::WebhookCreate);
+        for _ in 0..10 {
+            assert_eq!(antinuke.record(webhooks, &settings), None);
+        }
+    }
+}
@@ -0,0 +1,1041 @@
+use crate::moderation::{self, ModAction};
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// What happens to accounts younger than the age limit that join during a lockdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum NewAccountAction {
+    #[name = "none"]
+    Nothing,
+    #[name = "timeout"]
+    Timeout,
+    #[name = "kick"]
+    Kick,
+}
+
+impl NewAccountAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NewAccountAction::Nothing => "none",
+            NewAccountAction::Timeout => "timeout",
+            NewAccountAction::Kick => "kick",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "none" => NewAccountAction::Nothing,
+            "kick" => NewAccountAction::Kick,
+            _ => NewAccountAction::Timeout,
+        }
+    }
+}
+
+/// A guild's anti-raid settings, as stored in `antiraid_config`.
+#[derive(Debug, Clone)]
+pub struct AntiRaidConfig {
+    /// Whether join spikes start a lockdown automatically. Manual lockdowns always work.
+    pub enabled: bool,
+    /// This many joins inside the window starts a lockdown.
+    pub join_threshold: i64,
+    pub window_secs: i64,
+    /// Accounts younger than this are dealt with while locked down.
+    pub min_account_age_secs: i64,
+    pub new_account_action: NewAccountAction,
+    pub timeout_secs: i64,
+    /// Slowmode applied to every text channel while locked down, 0 leaves channels alone.
+    pub slowmode_secs: i64,
+    pub alert_channel_id: Option<i64>,
+}
+
+impl Default for AntiRaidConfig {
+    fn default() -> Self {
+        AntiRaidConfig {
+            enabled: false,
+            join_threshold: 10,
+            window_secs: 10,
+            min_account_age_secs: 7 * 24 * 60 * 60,
+            new_account_action: NewAccountAction::Timeout,
+            timeout_secs: 60 * 60,
+            slowmode_secs: 30,
+            alert_channel_id: None,
+        }
+    }
+}
+
+// Helper struct to map the query result
+struct AntiRaidRow {
+    enabled: bool,
+    join_threshold: i64,
+    window_secs: i64,
+    min_account_age_secs: i64,
+    new_account_action: String,
+    timeout_secs: i64,
+    slowmode_secs: i64,
+    alert_channel_id: Option<i64>,
+}
+
+impl From<AntiRaidRow> for AntiRaidConfig {
+    fn from(row: AntiRaidRow) -> Self {
+        AntiRaidConfig {
+            enabled: row.enabled,
+            join_threshold: row.join_threshold,
+            window_secs: row.window_secs,
+            min_account_age_secs: row.min_account_age_secs,
+            new_account_action: NewAccountAction::from_db(&row.new_account_action),
+            timeout_secs: row.timeout_secs,
+            slowmode_secs: row.slowmode_secs,
+            alert_channel_id: row.alert_channel_id,
+        }
+    }
+}
+
+// Helper struct to map the query result
+struct LockdownRow {
+    started_at: i64,
+    reason: String,
+    previous_verification: i64,
+}
+
+// Helper struct to map the query result
+struct LockdownChannelRow {
+    channel_id: i64,
+    previous_slowmode: i64,
+}
+
+/// Anti-raid state shared between the event handler and the commands.
+#[derive(Default)]
+pub struct AntiRaid {
+    // `None` caches "this guild never configured anti-raid" so we don't ask the database again.
+    configs: RwLock<HashMap<serenity::GuildId, Option<AntiRaidConfig>>>,
+    joins: Mutex<HashMap<serenity::GuildId, VecDeque<Instant>>>,
+    // Mirrors the `lockdowns` table, which is what survives a restart.
+    locked: RwLock<HashMap<serenity::GuildId, bool>>,
+}
+
+impl AntiRaid {
+    /// The guild's config, loaded from the database the first time it's needed.
+    pub async fn config(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Option<AntiRaidConfig>, Error> {
+        if let Some(cached) = self.configs.read().unwrap().get(&guild_id) {
+            return Ok(cached.clone());
+        }
+        let config = load_config(pool, guild_id).await?;
+        self.configs
+            .write()
+            .unwrap()
+            .insert(guild_id, config.clone());
+        Ok(config)
+    }
+
+    fn set_cached(&self, guild_id: serenity::GuildId, config: AntiRaidConfig) {
+        self.configs.write().unwrap().insert(guild_id, Some(config));
+    }
+
+    /// Whether the guild is locked down right now.
+    pub async fn is_locked(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<bool, Error> {
+        if let Some(locked) = self.locked.read().unwrap().get(&guild_id) {
+            return Ok(*locked);
+        }
+        let guild_id_db = guild_id.get() as i64;
+        let locked = sqlx::query!(
+            "SELECT guild_id FROM lockdowns WHERE guild_id = ?",
+            guild_id_db
+        )
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+        self.locked.write().unwrap().insert(guild_id, locked);
+        Ok(locked)
+    }
+
+    fn set_locked(&self, guild_id: serenity::GuildId, locked: bool) {
+        self.locked.write().unwrap().insert(guild_id, locked);
+    }
+
+    /// Counts a join and returns how many happened inside the window if that crossed
+    /// the threshold.
+    pub fn record_join(
+        &self,
+        guild_id: serenity::GuildId,
+        config: &AntiRaidConfig,
+    ) -> Option<usize> {
+        self.record_join_at(guild_id, config, Instant::now())
+    }
+
+    fn record_join_at(
+        &self,
+        guild_id: serenity::GuildId,
+        config: &AntiRaidConfig,
+        now: Instant,
+    ) -> Option<usize> {
+        let window = Duration::from_secs(config.window_secs.max(1) as u64);
+        let mut joins = self.joins.lock().unwrap();
+        let recent = joins.entry(guild_id).or_default();
+        recent.push_back(now);
+        while let Some(joined) = recent.front() {
+            if now.duration_since(*joined) > window {
+                recent.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let count = recent.len();
+        if config.join_threshold > 0 && count as i64 >= config.join_threshold {
+            recent.clear();
+            Some(count)
+        } else {
+            None
+        }
+    }
+}
+
+/// How old an account created at `created_at` is, if that's too young to get in
+/// unchallenged during a lockdown.
+fn young_account_age(config: &AntiRaidConfig, created_at: i64, now: i64) -> Option<i64> {
+    let age = now - created_at;
+    (age < config.min_account_age_secs).then_some(age)
+}
+
+async fn load_config(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<Option<AntiRaidConfig>, Error> {
+    let guild_id = guild_id.get() as i64;
+    let row = sqlx::query_as!(
+        AntiRaidRow,
+        "SELECT enabled, join_threshold, window_secs, min_account_age_secs, new_account_action,
+                timeout_secs, slowmode_secs, alert_channel_id
+         FROM antiraid_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(AntiRaidConfig::from))
+}
+
+async fn save_config(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    config: &AntiRaidConfig,
+) -> Result<(), Error> {
+    let guild_id = guild_id.get() as i64;
+    let action = config.new_account_action.as_str();
+    sqlx::query!(
+        "INSERT INTO antiraid_config
+            (guild_id, enabled, join_threshold, window_secs, min_account_age_secs,
+             new_account_action, timeout_secs, slowmode_secs, alert_channel_id)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            join_threshold = excluded.join_threshold,
+            window_secs = excluded.window_secs,
+            min_account_age_secs = excluded.min_account_age_secs,
+            new_account_action = excluded.new_account_action,
+            timeout_secs = excluded.timeout_secs,
+            slowmode_secs = excluded.slowmode_secs,
+            alert_channel_id = excluded.alert_channel_id",
+        guild_id,
+        config.enabled,
+        config.join_threshold,
+        config.window_secs,
+        config.min_account_age_secs,
+        action,
+        config.timeout_secs,
+        config.slowmode_secs,
+        config.alert_channel_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// What starting or ending a lockdown actually managed to change.
+#[derive(Default)]
+pub struct LockdownReport {
+    pub verification_changed: bool,
+    pub channels: usize,
+    pub failed: usize,
+}
+
+impl LockdownReport {
+    fn summary(&self) -> String {
+        let mut summary = format!(
+            "Verification level {}, slowmode updated in {} channel(s).",
+            if self.verification_changed {
+                "changed"
+            } else {
+                "unchanged"
+            },
+            self.channels
+        );
+        if self.failed > 0 {
+            summary.push_str(&format!(
+                " {} change(s) failed, check my permissions.",
+                self.failed
+            ));
+        }
+        summary
+    }
+}
+
+/// Locks the guild down: raises the verification level and puts every text channel in
+/// slowmode, remembering the old settings so [`end_lockdown`] can put them back.
+/// Returns `None` if the guild was already locked down.
+pub async fn start_lockdown(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    antiraid: &AntiRaid,
+    guild_id: serenity::GuildId,
+    reason: &str,
+) -> Result<Option<LockdownReport>, Error> {
+    if antiraid.is_locked(pool, guild_id).await? {
+        return Ok(None);
+    }
+    let config = antiraid.config(pool, guild_id).await?.unwrap_or_default();
+    let guild = guild_id.to_partial_guild(ctx).await?;
+    let previous = u8::from(guild.verification_level);
+    let audit_reason = format!("Lockdown: {}", reason);
+
+    // Record the lockdown before touching anything, so a crash halfway still leaves
+    // enough behind to undo it. Joins arriving together can all get past the check
+    // above, so whichever inserts the row is the one that locks down.
+    let guild_id_db = guild_id.get() as i64;
+    let started_at = serenity::Timestamp::now().unix_timestamp();
+    let previous_db = previous as i64;
+    let inserted = sqlx::query!(
+        "INSERT INTO lockdowns (guild_id, started_at, reason, previous_verification)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO NOTHING",
+        guild_id_db,
+        started_at,
+        reason,
+        previous_db
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    antiraid.set_locked(guild_id, true);
+    if inserted == 0 {
+        return Ok(None);
+    }
+
+    let mut report = LockdownReport::default();
+    let high = u8::from(serenity::VerificationLevel::High);
+    if previous < high {
+        match guild_id
+            .edit(
+                ctx,
+                serenity::EditGuild::new()
+                    .verification_level(serenity::VerificationLevel::High)
+                    .audit_log_reason(&audit_reason),
+            )
+            .await
+        {
+            Ok(_) => report.verification_changed = true,
+            Err(_) => report.failed += 1,
+        }
+    }
+
+    if config.slowmode_secs > 0 {
+        let slowmode = config.slowmode_secs.min(21_600) as u16;
+        for (channel_id, channel) in guild_id.channels(ctx).await? {
+            if channel.kind != serenity::ChannelType::Text {
+                continue;
+            }
+            let before = channel.rate_limit_per_user.unwrap_or(0);
+            if before >= slowmode {
+                continue;
+            }
+            let channel_id_db = channel_id.get() as i64;
+            let before_db = before as i64;
+            sqlx::query!(
+                "INSERT INTO lockdown_channels (guild_id, channel_id, previous_slowmode)
+                 VALUES (?, ?, ?)
+                 ON CONFLICT (guild_id, channel_id) DO NOTHING",
+                guild_id_db,
+                channel_id_db,
+                before_db
+            )
+            .execute(pool)
+            .await?;
+            match channel_id
+                .edit(
+                    ctx,
+                    serenity::EditChannel::new()
+                        .rate_limit_per_user(slowmode)
+                        .audit_log_reason(&audit_reason),
+                )
+                .await
+            {
+                Ok(_) => report.channels += 1,
+                Err(_) => report.failed += 1,
+            }
+        }
+    }
+    Ok(Some(report))
+}
+
+/// Lifts a lockdown, restoring the verification level and slowmode it replaced.
+/// Returns `None` if the guild wasn't locked down.
+pub async fn end_lockdown(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    antiraid: &AntiRaid,
+    guild_id: serenity::GuildId,
+) -> Result<Option<LockdownReport>, Error> {
+    let guild_id_db = guild_id.get() as i64;
+    let Some(lockdown) = sqlx::query_as!(
+        LockdownRow,
+        "SELECT started_at, reason, previous_verification FROM lockdowns WHERE guild_id = ?",
+        guild_id_db
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        antiraid.set_locked(guild_id, false);
+        return Ok(None);
+    };
+
+    let mut report = LockdownReport::default();
+    let previous = serenity::VerificationLevel::from(lockdown.previous_verification as u8);
+    let guild = guild_id.to_partial_guild(ctx).await?;
+    if guild.verification_level != previous {
+        match guild_id
+            .edit(
+                ctx,
+                serenity::EditGuild::new()
+                    .verification_level(previous)
+                    .audit_log_reason("Lockdown lifted"),
+            )
+            .await
+        {
+            Ok(_) => report.verification_changed = true,
+            Err(_) => report.failed += 1,
+        }
+    }
+
+    let channels = sqlx::query_as!(
+        LockdownChannelRow,
+        "SELECT channel_id, previous_slowmode FROM lockdown_channels WHERE guild_id = ?",
+        guild_id_db
+    )
+    .fetch_all(pool)
+    .await?;
+    for channel in channels {
+        let channel_id = serenity::ChannelId::new(channel.channel_id as u64);
+        // A channel deleted during the lockdown has nothing left to restore.
+        match channel_id
+            .edit(
+                ctx,
+                serenity::EditChannel::new()
+                    .rate_limit_per_user(channel.previous_slowmode as u16)
+                    .audit_log_reason("Lockdown lifted"),
+            )
+            .await
+        {
+            Ok(_) => report.channels += 1,
+            Err(_) => report.failed += 1,
+        }
+    }
+
+    sqlx::query!(
+        "DELETE FROM lockdown_channels WHERE guild_id = ?",
+        guild_id_db
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query!("DELETE FROM lockdowns WHERE guild_id = ?", guild_id_db)
+        .execute(pool)
+        .await?;
+    antiraid.set_locked(guild_id, false);
+    Ok(Some(report))
+}
+
+/// Posts to the guild's anti-raid alert channel, if one is set.
+pub async fn alert(
+    ctx: &serenity::Context,
+    config: &AntiRaidConfig,
+    embed: serenity::CreateEmbed,
+) -> Result<(), Error> {
+    let Some(channel_id) = config.alert_channel_id else {
+        return Ok(());
+    };
+    serenity::ChannelId::new(channel_id as u64)
+        .send_message(ctx, serenity::CreateMessage::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+/// Watches joins for a raid, and deals with young accounts while locked down.
+pub async fn member_joined(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    antiraid: &AntiRaid,
+    member: &serenity::Member,
+) -> Result<(), Error> {
+    if member.user.bot {
+        return Ok(());
+    }
+    let guild_id = member.guild_id;
+    let config = antiraid.config(pool, guild_id).await?.unwrap_or_default();
+
+    if config.enabled
+        && let Some(joins) = antiraid.record_join(guild_id, &config)
+    {
+        let reason = format!(
+            "{} joins in {}",
+            joins,
+            moderation::format_duration(config.window_secs as u64)
+        );
+        if let Some(report) = start_lockdown(ctx, pool, antiraid, guild_id, &reason).await? {
+            alert(
+                ctx,
+                &config,
+                serenity::CreateEmbed::new()
+                    .title("🚨 Raid detected, server locked down")
+                    .description(format!(
+                        "{}\n{}\nRun `lockdown off` once it's over.",
+                        reason,
+                        report.summary()
+                    ))
+                    .color(serenity::Color::RED),
+            )
+            .await?;
+        }
+    }
+
+    if !antiraid.is_locked(pool, guild_id).await? {
+        return Ok(());
+    }
+    let Some(age) = young_account_age(
+        &config,
+        member.user.id.created_at().unix_timestamp(),
+        serenity::Timestamp::now().unix_timestamp(),
+    ) else {
+        return Ok(());
+    };
+
+    let bot_id = ctx.cache.current_user().id;
+    let reason = format!(
+        "Lockdown: account is {} old",
+        moderation::format_duration(age.max(0) as u64)
+    );
+    match config.new_account_action {
+        NewAccountAction::Nothing => {}
+        NewAccountAction::Timeout => {
+            let until = serenity::Timestamp::from_unix_timestamp(
+                serenity::Timestamp::now().unix_timestamp() + config.timeout_secs,
+            )?;
+            guild_id
+                .edit_member(
+                    ctx,
+                    member.user.id,
+                    serenity::EditMember::new()
+                        .disable_communication_until_datetime(until)
+                        .audit_log_reason(&reason),
+                )
+                .await?;
+            moderation::record_case(
+                pool,
+                guild_id,
+                member.user.id,
+                bot_id,
+                ModAction::Timeout,
+                &reason,
+            )
+            .await?;
+        }
+        NewAccountAction::Kick => {
+            guild_id
+                .kick_with_reason(ctx, member.user.id, &reason)
+                .await?;
+            moderation::record_case(
+                pool,
+                guild_id,
+                member.user.id,
+                bot_id,
+                ModAction::Kick,
+                &reason,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+fn describe(config: &AntiRaidConfig, locked: bool) -> serenity::CreateEmbed {
+    serenity::CreateEmbed::new()
+        .title("Anti-raid")
+        .field(
+            "Automatic lockdown",
+            if config.enabled {
+                "Enabled"
+            } else {
+                "Disabled"
+            },
+            true,
+        )
+        .field(
+            "Lockdown",
+            if locked { "🔒 Active" } else { "Not active" },
+            true,
+        )
+        .field(
+            "Trigger",
+            format!(
+                "{} joins in {}",
+                config.join_threshold,
+                moderation::format_duration(config.window_secs as u64)
+            ),
+            false,
+        )
+        .field(
+            "New accounts during lockdown",
+            match config.new_account_action {
+                NewAccountAction::Nothing => "Left alone".to_string(),
+                NewAccountAction::Timeout => format!(
+                    "Younger than {}: timeout for {}",
+                    moderation::format_duration(config.min_account_age_secs as u64),
+                    moderation::format_duration(config.timeout_secs as u64)
+                ),
+                NewAccountAction::Kick => format!(
+                    "Younger than {}: kick",
+                    moderation::format_duration(config.min_account_age_secs as u64)
+                ),
+            },
+            false,
+        )
+        .field(
+            "Slowmode during lockdown",
+            if config.slowmode_secs > 0 {
+                moderation::format_duration(config.slowmode_secs as u64)
+            } else {
+                "Off".to_string()
+            },
+            true,
+        )
+        .field(
+            "Alerts",
+            config
+                .alert_channel_id
+                .map(|id| serenity::ChannelId::new(id as u64).mention().to_string())
+                .unwrap_or_else(|| "Not set".to_string()),
+            true,
+        )
+        .color(serenity::Color::DARK_RED)
+}
+
+// Loads the current config (or the defaults), applies `change`, and saves it back.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut AntiRaidConfig),
+) -> Result<AntiRaidConfig, Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let mut config = data
+        .antiraid
+        .config(&data.db_pool, guild_id)
+        .await?
+        .unwrap_or_default();
+    change(&mut config);
+    save_config(&data.db_pool, guild_id, &config).await?;
+    data.antiraid.set_cached(guild_id, config.clone());
+    Ok(config)
+}
+
+async fn reply_with_config(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let config = data
+        .antiraid
+        .config(&data.db_pool, guild_id)
+        .await?
+        .unwrap_or_default();
+    let locked = data.antiraid.is_locked(&data.db_pool, guild_id).await?;
+    let mut reply = poise::CreateReply::default().embed(describe(&config, locked));
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Configure join-rate raid detection.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands(
+        "show",
+        "enable",
+        "disable",
+        "trigger",
+        "newaccounts",
+        "slowmode",
+        "alerts"
+    )
+)]
+pub async fn antiraid(ctx: Context<'_>) -> Result<(), Error> {
+    reply_with_config(ctx, "").await
+}
+
+/// Show the current anti-raid settings.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    reply_with_config(ctx, "").await
+}
+
+/// Lock the server down automatically when too many members join at once.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn enable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = true).await?;
+    reply_with_config(ctx, "Automatic lockdowns are now enabled.").await
+}
+
+/// Stop locking down automatically. Manual lockdowns still work.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = false).await?;
+    ctx.say("Automatic lockdowns are now disabled.").await?;
+    Ok(())
+}
+
+/// Set how many joins in how long count as a raid.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn trigger(
+    ctx: Context<'_>,
+    #[description = "Joins that start a lockdown"]
+    #[min = 2]
+    joins: u32,
+    #[description = "Window length (e.g. 10s, 1m)"] window: Option<String>,
+) -> Result<(), Error> {
+    let window = match window {
+        Some(window) => match moderation::parse_duration(&window) {
+            Some(duration) if duration.as_secs() > 0 && duration.as_secs() <= 3600 => {
+                Some(duration.as_secs() as i64)
+            }
+            _ => {
+                ctx.say("❌ The window must be between 1 second and 1 hour, e.g. `10s`.")
+                    .await?;
+                return Ok(());
+            }
+        },
+        _none => None,
+    };
+    update_config(ctx, |config| {
+        config.join_threshold = joins.max(2) as i64;
+        if let Some(window) = window {
+            config.window_secs = window;
+        }
+    })
+    .await?;
+    reply_with_config(ctx, "Raid trigger updated.").await
+}
+
+/// Choose what happens to young accounts that join during a lockdown.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn newaccounts(
+    ctx: Context<'_>,
+    #[description = "What to do with them"] action: NewAccountAction,
+    #[description = "Accounts younger than this are affected (e.g. 7d)"] min_age: Option<String>,
+    #[description = "How long timeouts last (e.g. 1h)"] duration: Option<String>,
+) -> Result<(), Error> {
+    let min_age = match min_age {
+        Some(min_age) => match moderation::parse_duration(&min_age) {
+            Some(d) if d.as_secs() > 0 => Some(d.as_secs() as i64),
+            _ => {
+                ctx.say("❌ That isn't a valid account age, e.g. `7d`.")
+                    .await?;
+                return Ok(());
+            }
+        },
+        _none => None,
+    };
+    let timeout = match duration {
+        Some(duration) => match moderation::parse_duration(&duration) {
+            // Discord caps timeouts at 28 days.
+            Some(d) if d.as_secs() > 0 && d.as_secs() <= 28 * 24 * 60 * 60 => {
+                Some(d.as_secs() as i64)
+            }
+            _ => {
+                ctx.say("❌ Timeouts must be between 1 second and 28 days, e.g. `1h`.")
+                    .await?;
+                return Ok(());
+            }
+        },
+        _none => None,
+    };
+    update_config(ctx, |config| {
+        config.new_account_action = action;
+        if let Some(min_age) = min_age {
+            config.min_account_age_secs = min_age;
+        }
+        if let Some(timeout) = timeout {
+            config.timeout_secs = timeout;
+        }
+    })
+    .await?;
+    reply_with_config(ctx, "New account handling updated.").await
+}
+
+/// Set the slowmode applied to text channels during a lockdown.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn slowmode(
+    ctx: Context<'_>,
+    #[description = "Delay between messages (e.g. 30s), 0 to leave channels alone"] delay: String,
+) -> Result<(), Error> {
+    // Discord's slowmode tops out at 6 hours.
+    let delay = match moderation::parse_duration(&delay) {
+        Some(d) if d.as_secs() <= 21_600 => d.as_secs() as i64,
+        _ => {
+            ctx.say("❌ Slowmode must be between 0 seconds and 6 hours, e.g. `30s`.")
+                .await?;
+            return Ok(());
+        }
+    };
+    update_config(ctx, |config| config.slowmode_secs = delay).await?;
+    reply_with_config(ctx, "Lockdown slowmode updated.").await
+}
+
+/// Choose where raid alerts are posted. Leave it out to stop alerts.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn alerts(
+    ctx: Context<'_>,
+    #[description = "Channel for raid alerts"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let channel_id = channel.map(|c| c.id.get() as i64);
+    update_config(ctx, |config| config.alert_channel_id = channel_id).await?;
+    reply_with_config(ctx, "Alert channel updated.").await
+}
+
+/// Lock the server down by hand, or lift a lockdown.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("on", "off", "status")
+)]
+pub async fn lockdown(ctx: Context<'_>) -> Result<(), Error> {
+    status_inner(ctx).await
+}
+
+async fn status_inner(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let guild_id_db = guild_id.get() as i64;
+    let lockdown = sqlx::query_as!(
+        LockdownRow,
+        "SELECT started_at, reason, previous_verification FROM lockdowns WHERE guild_id = ?",
+        guild_id_db
+    )
+    .fetch_optional(&ctx.data().db_pool)
+    .await?;
+    match lockdown {
+        Some(lockdown) => {
+            ctx.say(format!(
+                "🔒 Locked down since <t:{}:R>: {}",
+                lockdown.started_at, lockdown.reason
+            ))
+            .await?
+        }
+        None => ctx.say("The server isn't locked down.").await?,
+    };
+    Ok(())
+}
+
+/// Show whether the server is locked down.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn status(ctx: Context<'_>) -> Result<(), Error> {
+    status_inner(ctx).await
+}
+
+/// Raise verification, slow down every channel and deal with new accounts as they join.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn on(
+    ctx: Context<'_>,
+    #[description = "Why the server is being locked down"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    ctx.defer().await?;
+    let reason = format!(
+        "{} by {}",
+        reason.as_deref().unwrap_or("Manual lockdown"),
+        ctx.author().tag()
+    );
+    let data = ctx.data();
+    let Some(report) = start_lockdown(
+        ctx.serenity_context(),
+        &data.db_pool,
+        &data.antiraid,
+        guild_id,
+        &reason,
+    )
+    .await?
+    else {
+        ctx.say("The server is already locked down.").await?;
+        return Ok(());
+    };
+
+    let config = data
+        .antiraid
+        .config(&data.db_pool, guild_id)
+        .await?
+        .unwrap_or_default();
+    alert(
+        ctx.serenity_context(),
+        &config,
+        serenity::CreateEmbed::new()
+            .title("🔒 Server locked down")
+            .description(format!("{}\n{}", reason, report.summary()))
+            .color(serenity::Color::RED),
+    )
+    .await?;
+    ctx.say(format!("🔒 Server locked down. {}", report.summary()))
+        .await?;
+    Ok(())
+}
+
+/// Lift the lockdown and put the old settings back.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn off(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    ctx.defer().await?;
+    let data = ctx.data();
+    let Some(report) = end_lockdown(
+        ctx.serenity_context(),
+        &data.db_pool,
+        &data.antiraid,
+        guild_id,
+    )
+    .await?
+    else {
+        ctx.say("The server isn't locked down.").await?;
+        return Ok(());
+    };
+
+    let config = data
+        .antiraid
+        .config(&data.db_pool, guild_id)
+        .await?
+        .unwrap_or_default();
+    alert(
+        ctx.serenity_context(),
+        &config,
+        serenity::CreateEmbed::new()
+            .title("🔓 Lockdown lifted")
+            .description(format!(
+                "Lifted by {}.\n{}",
+                ctx.author().tag(),
+                report.summary()
+            ))
+            .color(serenity::Color::DARK_GREEN),
+    )
+    .await?;
+    ctx.say(format!("🔓 Lockdown lifted. {}", report.summary()))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: serenity::GuildId = serenity::GuildId::new(1);
+    const OTHER_GUILD: serenity::GuildId = serenity::GuildId::new(2);
+
+    fn config() -> AntiRaidConfig {
+        AntiRaidConfig {
+            enabled: true,
+            join_threshold: 3,
+            window_secs: 10,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn triggers_at_the_threshold_then_starts_over() {
+        let antiraid = AntiRaid::default();
+        let start = Instant::now();
+        let join = |guild, secs| {
+            antiraid.record_join_at(guild, &config(), start + Duration::from_secs(secs))
+        };
+        assert_eq!(join(GUILD, 0), None);
+        assert_eq!(join(GUILD, 1), None);
+        // Joins elsewhere don't count.
+        assert_eq!(join(OTHER_GUILD, 1), None);
+        assert_eq!(join(GUILD, 2), Some(3));
+        assert_eq!(join(GUILD, 3), None);
+        assert_eq!(join(GUILD, 4), None);
+        assert_eq!(join(GUILD, 5), Some(3));
+    }
+
+    #[test]
+    fn forgets_joins_outside_the_window() {
+        let antiraid = AntiRaid::default();
+        let start = Instant::now();
+        let join =
+            |secs| antiraid.record_join_at(GUILD, &config(), start + Duration::from_secs(secs));
+        assert_eq!(join(0), None);
+        assert_eq!(join(5), None);
+        // The first join is now 11 seconds old.
+        assert_eq!(join(11), None);
+        assert_eq!(join(12), Some(3));
+    }
+
+    #[test]
+    fn zero_threshold_never_triggers() {
+        let antiraid = AntiRaid::default();
+        let config = AntiRaidConfig {
+            join_threshold: 0,
+            ..config()
+        };
+        let now = Instant::now();
+        for _ in 0..50 {
+            assert_eq!(antiraid.record_join_at(GUILD, &config, now), None);
+        }
+    }
+
+    #[test]
+    fn only_young_accounts_are_caught() {
+        let config = AntiRaidConfig {
+            min_account_age_secs: 3600,
+            ..config()
+        };
+        let now = 1_000_000;
+        assert_eq!(young_account_age(&config, now - 60, now), Some(60));
+        assert_eq!(young_account_age(&config, now - 3599, now), Some(3599));
+        assert_eq!(young_account_age(&config, now - 3600, now), None);
+        assert_eq!(young_account_age(&config, now - 86_400, now), None);
+        let off = AntiRaidConfig {
+            min_account_age_secs: 0,
+            ..config
+        };
+        assert_eq!(young_account_age(&off, now - 1, now), None);
+    }
+}
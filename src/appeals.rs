@@ -0,0 +1,553 @@
+use crate::moderation::{self, ModAction, modal_on_button, reply_ephemeral};
+use crate::notes;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use sqlx::{Pool, Sqlite};
+use std::time::Duration;
+
+/// Prefix of the custom ids on appeal buttons, routed here by the event handler.
+pub const BUTTON_PREFIX: &str = "appeal:";
+/// How long to wait for someone to fill in an appeal or denial form.
+const MODAL_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+// Helper struct to map the query result
+struct AppealConfigRow {
+    enabled: bool,
+    channel_id: Option<i64>,
+}
+
+// Helper struct to map the query result
+struct AppealRow {
+    id: i64,
+    user_id: i64,
+    status: String,
+}
+
+#[derive(Debug, poise::Modal)]
+#[name = "Appeal your ban"]
+struct AppealModal {
+    #[name = "Why should you be unbanned?"]
+    #[placeholder = "Explain what happened and why it won't happen again"]
+    #[paragraph]
+    #[min_length = 20]
+    #[max_length = 1000]
+    appeal: String,
+}
+
+#[derive(Debug, poise::Modal)]
+#[name = "Deny appeal"]
+struct DenyModal {
+    #[name = "Message to send them"]
+    #[placeholder = "Why the appeal was denied"]
+    #[paragraph]
+    #[max_length = 900]
+    message: String,
+}
+
+async fn load_config(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<AppealConfigRow, Error> {
+    let guild_id = guild_id.get() as i64;
+    let config = sqlx::query_as!(
+        AppealConfigRow,
+        "SELECT enabled, channel_id FROM appeal_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(AppealConfigRow {
+        enabled: false,
+        channel_id: None,
+    });
+    Ok(config)
+}
+
+/// DMs someone who is about to be banned a button to appeal with, if the guild takes
+/// appeals. Has to happen before the ban, since bots can only DM people they share a
+/// server with. Returns the DM if it was delivered, so it can be taken back if the ban
+/// fails.
+pub async fn offer_appeal(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    user: &serenity::User,
+    reason: &str,
+) -> Result<Option<serenity::Message>, Error> {
+    let config = load_config(pool, guild_id).await?;
+    if !config.enabled || config.channel_id.is_none() || user.bot {
+        return Ok(None);
+    }
+    let guild_name = guild_id
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+    let delivered = user
+        .direct_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .embed(
+                    serenity::CreateEmbed::new()
+                        .title(format!("You have been banned from {}", guild_name))
+                        .field("Reason", reason, false)
+                        .description(
+                            "If you think this was a mistake, you can appeal once with the button below.",
+                        )
+                        .color(serenity::Color::DARK_RED),
+                )
+                .button(
+                    serenity::CreateButton::new(format!("{}open:{}", BUTTON_PREFIX, guild_id))
+                        .label("Appeal")
+                        .style(serenity::ButtonStyle::Primary),
+                ),
+        )
+        .await;
+    Ok(delivered.ok())
+}
+
+/// Handles the Appeal button in DMs and the Accept/Deny buttons in the moderator channel.
+pub async fn handle_button(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    interaction: &serenity::ComponentInteraction,
+) -> Result<(), Error> {
+    let Some((action, id)) = interaction
+        .data
+        .custom_id
+        .strip_prefix(BUTTON_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+    else {
+        return Ok(());
+    };
+    let Ok(id) = id.parse::<u64>() else {
+        return Ok(());
+    };
+    match action {
+        "open" => submit_appeal(ctx, pool, interaction, serenity::GuildId::new(id)).await,
+        "accept" | "deny" => review_appeal(ctx, pool, interaction, action, id as i64).await,
+        _ => Ok(()),
+    }
+}
+
+// Takes a banned user's appeal through the form and posts it for the moderators.
+async fn submit_appeal(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    interaction: &serenity::ComponentInteraction,
+    guild_id: serenity::GuildId,
+) -> Result<(), Error> {
+    let user = &interaction.user;
+    let guild_name = guild_id
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+    let config = load_config(pool, guild_id).await?;
+    let Some(channel_id) = config
+        .channel_id
+        .filter(|_| config.enabled)
+        .map(|id| serenity::ChannelId::new(id as u64))
+    else {
+        return reply_ephemeral(
+            ctx,
+            interaction,
+            format!("❌ **{}** isn't taking appeals right now.", guild_name),
+        )
+        .await;
+    };
+    let Some(ban) = moderation::fetch_ban(&ctx.http, guild_id, user.id).await? else {
+        return reply_ephemeral(
+            ctx,
+            interaction,
+            format!("You aren't banned from **{}** any more.", guild_name),
+        )
+        .await;
+    };
+    if let Some(why) = appeal_blocked(pool, guild_id, user.id).await? {
+        return reply_ephemeral(ctx, interaction, why).await;
+    }
+
+    let Some((form, submission)) =
+        modal_on_button::<AppealModal>(ctx, interaction, MODAL_TIMEOUT).await?
+    else {
+        return Ok(());
+    };
+    // They might have had the form open twice.
+    if let Some(why) = appeal_blocked(pool, guild_id, user.id).await? {
+        submission
+            .create_response(ctx, update_message(why.to_string(), None))
+            .await?;
+        return Ok(());
+    }
+
+    let guild_id_db = guild_id.get() as i64;
+    let user_id = user.id.get() as i64;
+    let ban_reason = ban
+        .reason
+        .clone()
+        .unwrap_or_else(|| "No reason provided".to_string());
+    let appeal_id = sqlx::query!(
+        "INSERT INTO ban_appeals (guild_id, user_id, ban_reason, appeal) VALUES (?, ?, ?, ?)",
+        guild_id_db,
+        user_id,
+        ban_reason,
+        form.appeal
+    )
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+
+    let mut embeds = vec![
+        serenity::CreateEmbed::new()
+            .title(format!("📨 Ban appeal #{}", appeal_id))
+            .thumbnail(user.face())
+            .description(format!("{} ({})", user.mention(), user.tag()))
+            .field("Ban reason", ban_reason, false)
+            .field("Appeal", form.appeal, false)
+            .footer(serenity::CreateEmbedFooter::new(format!(
+                "User ID: {}",
+                user.id
+            )))
+            .timestamp(serenity::Timestamp::now())
+            .color(serenity::Color::GOLD),
+    ];
+    if let Some(history) = notes::history_pages(pool, guild_id, user)
+        .await?
+        .into_iter()
+        .next()
+    {
+        embeds.push(history);
+    }
+    let button = |action: &str, label: &str, style| {
+        serenity::CreateButton::new(format!("{}{}:{}", BUTTON_PREFIX, action, appeal_id))
+            .label(label)
+            .style(style)
+    };
+    channel_id
+        .send_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .embeds(embeds)
+                .components(vec![serenity::CreateActionRow::Buttons(vec![
+                    button("accept", "Accept & unban", serenity::ButtonStyle::Success),
+                    button("deny", "Deny", serenity::ButtonStyle::Danger),
+                ])]),
+        )
+        .await?;
+
+    // One appeal per ban, so the button has done its job.
+    submission
+        .create_response(
+            ctx,
+            update_message(
+                format!(
+                    "📨 Your appeal has been sent to the moderators of **{}**. I'll let you know what they decide.",
+                    guild_name
+                ),
+                None,
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+// Replaces the message a button was on, dropping its buttons.
+fn update_message(
+    content: String,
+    embeds: Option<Vec<serenity::CreateEmbed>>,
+) -> serenity::CreateInteractionResponse {
+    let mut message = serenity::CreateInteractionResponseMessage::new()
+        .content(content)
+        .components(vec![]);
+    if let Some(embeds) = embeds {
+        message = message.embeds(embeds);
+    }
+    serenity::CreateInteractionResponse::UpdateMessage(message)
+}
+
+// Why the user can't appeal right now: one is already waiting, or their last one for
+// this ban was denied.
+async fn appeal_blocked(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+) -> Result<Option<&'static str>, Error> {
+    let guild_id = guild_id.get() as i64;
+    let user_id = user_id.get() as i64;
+    let latest = sqlx::query!(
+        "SELECT status, created_at,
+            (SELECT MAX(created_at) FROM mod_cases
+             WHERE guild_id = ban_appeals.guild_id AND user_id = ban_appeals.user_id
+                AND action = 'ban') AS \"banned_at: i64\"
+         FROM ban_appeals WHERE guild_id = ? AND user_id = ? ORDER BY id DESC LIMIT 1",
+        guild_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(latest) = latest else {
+        return Ok(None);
+    };
+    Ok(match latest.status.as_str() {
+        "pending" => Some("Your appeal is still waiting for the moderators."),
+        // A denial only counts against the ban it was made for.
+        "denied" if latest.banned_at.is_none_or(|at| at <= latest.created_at) => {
+            Some("Your appeal for this ban has already been denied.")
+        }
+        _ => None,
+    })
+}
+
+// Accepts or denies an appeal from the moderator channel.
+async fn review_appeal(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    interaction: &serenity::ComponentInteraction,
+    action: &str,
+    appeal_id: i64,
+) -> Result<(), Error> {
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+    let can_ban = interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.ban_members());
+    if !can_ban {
+        return reply_ephemeral(ctx, interaction, "❌ You don't have permission to do that.").await;
+    }
+
+    let guild_id_db = guild_id.get() as i64;
+    let appeal = sqlx::query_as!(
+        AppealRow,
+        "SELECT id, user_id, status FROM ban_appeals WHERE id = ? AND guild_id = ?",
+        appeal_id,
+        guild_id_db
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(appeal) = appeal.filter(|appeal| appeal.status == "pending") else {
+        return reply_ephemeral(ctx, interaction, "❌ Someone already handled this appeal.").await;
+    };
+    let moderator = &interaction.user;
+    let user_id = serenity::UserId::new(appeal.user_id as u64);
+    let guild_name = guild_id
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+
+    // Denying needs a message for them first, which answers the button with a form.
+    let (status, response, submission) = if action == "accept" {
+        ("accepted", String::new(), None)
+    } else {
+        let Some((form, submission)) =
+            modal_on_button::<DenyModal>(ctx, interaction, MODAL_TIMEOUT).await?
+        else {
+            return Ok(());
+        };
+        ("denied", form.message, Some(submission))
+    };
+
+    // Claim the appeal first so two moderators can't both decide it.
+    let moderator_id = moderator.id.get() as i64;
+    let claimed = sqlx::query!(
+        "UPDATE ban_appeals
+         SET status = ?, response = ?, handled_by = ?, resolved_at = strftime('%s', 'now')
+         WHERE id = ? AND status = 'pending'",
+        status,
+        response,
+        moderator_id,
+        appeal.id
+    )
+    .execute(pool)
+    .await?;
+    if claimed.rows_affected() == 0 {
+        let refusal = serenity::CreateInteractionResponse::Message(
+            serenity::CreateInteractionResponseMessage::new()
+                .content("❌ Someone already handled this appeal.")
+                .ephemeral(true),
+        );
+        match submission {
+            Some(submission) => submission.create_response(ctx, refusal).await?,
+            None => interaction.create_response(ctx, refusal).await?,
+        }
+        return Ok(());
+    }
+
+    let notice = if action == "accept" {
+        let reason = format!("Ban appeal #{} accepted by {}", appeal.id, moderator.tag());
+        if let Err(e) = guild_id.unban(ctx, user_id).await {
+            sqlx::query!(
+                "UPDATE ban_appeals
+                 SET status = 'pending', handled_by = NULL, resolved_at = NULL WHERE id = ?",
+                appeal.id
+            )
+            .execute(pool)
+            .await?;
+            return reply_ephemeral(ctx, interaction, format!("❌ Couldn't unban them: {}", e))
+                .await;
+        }
+        moderation::record_case(
+            pool,
+            guild_id,
+            user_id,
+            moderator.id,
+            ModAction::Unban,
+            &reason,
+        )
+        .await?;
+        format!(
+            "✅ Your ban appeal for **{}** was accepted and you have been unbanned.",
+            guild_name
+        )
+    } else {
+        format!(
+            "❌ Your ban appeal for **{}** was denied.\n> {}",
+            guild_name,
+            response.replace('\n', "\n> ")
+        )
+    };
+    // Banned users often share no other server with the bot, so this can fail.
+    let delivered = user_id
+        .direct_message(ctx, serenity::CreateMessage::new().content(notice))
+        .await
+        .is_ok();
+
+    let mut outcome = if action == "accept" {
+        format!("✅ Accepted and unbanned by {}", moderator.mention())
+    } else {
+        // Quoting adds to the length, keep the field under its 1024 characters.
+        format!(
+            "❌ Denied by {}\n> {}",
+            moderator.mention(),
+            moderation::shorten(&response.replace('\n', "\n> "), 900)
+        )
+    };
+    if !delivered {
+        outcome.push_str("\n*Couldn't DM them the decision.*");
+    }
+    let mut embeds: Vec<serenity::CreateEmbed> = interaction
+        .message
+        .embeds
+        .iter()
+        .cloned()
+        .map(serenity::CreateEmbed::from)
+        .collect();
+    if let Some(first) = embeds.first_mut() {
+        *first = first.clone().field("Decision", outcome, false);
+    }
+
+    let update = update_message(String::new(), Some(embeds));
+    match submission {
+        Some(submission) => submission.create_response(ctx, update).await?,
+        None => interaction.create_response(ctx, update).await?,
+    }
+    Ok(())
+}
+
+// Changes the guild's config row, creating it with the defaults if needed.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut AppealConfigRow),
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let mut config = load_config(pool, guild_id).await?;
+    change(&mut config);
+
+    let guild_id_db = guild_id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO appeal_config (guild_id, enabled, channel_id) VALUES (?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            channel_id = excluded.channel_id",
+        guild_id_db,
+        config.enabled,
+        config.channel_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let config = load_config(pool, guild_id).await?;
+    let guild_id_db = guild_id.get() as i64;
+    let pending = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM ban_appeals WHERE guild_id = ? AND status = 'pending'",
+        guild_id_db
+    )
+    .fetch_one(pool)
+    .await?
+    .count;
+    let embed = serenity::CreateEmbed::new()
+        .title("Ban appeals")
+        .field(
+            "Status",
+            if config.enabled {
+                "Banned members are offered an appeal by DM"
+            } else {
+                "Disabled"
+            },
+            false,
+        )
+        .field(
+            "Appeals channel",
+            config
+                .channel_id
+                .map(|id| format!("<#{}>", id))
+                .unwrap_or_else(|| "Not set".to_string()),
+            true,
+        )
+        .field("Pending appeals", pending.to_string(), true)
+        .color(serenity::Color::GOLD);
+    let mut reply = poise::CreateReply::default().embed(embed);
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Let banned members appeal by DM.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("show", "enable", "disable")
+)]
+pub async fn appeals(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the ban appeal settings.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Offer an appeal to everyone banned with the ban command.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn enable(
+    ctx: Context<'_>,
+    #[description = "Channel appeals are posted to"] channel: serenity::GuildChannel,
+) -> Result<(), Error> {
+    let channel_id = channel.id.get() as i64;
+    update_config(ctx, |config| {
+        config.enabled = true;
+        config.channel_id = Some(channel_id);
+    })
+    .await?;
+    show_inner(ctx, "Ban appeals are now enabled.").await
+}
+
+/// Stop offering appeals. Pending ones can still be decided.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = false).await?;
+    show_inner(ctx, "Ban appeals are now disabled.").await
+}
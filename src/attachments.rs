@@ -0,0 +1,532 @@
+use crate::automod::{self, AutomodAction};
+use crate::moderation;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::Duration;
+
+/// How much of an attachment is fetched to work out its kind. Every signature
+/// [`sniff`] looks for is well inside this.
+const SNIFF_BYTES: usize = 4096;
+
+static CDN: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Cannot build the attachment client")
+});
+
+/// What an attachment really is, going by its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, poise::ChoiceParameter)]
+pub enum FileKind {
+    #[name = "images"]
+    Image,
+    #[name = "videos"]
+    Video,
+    #[name = "audio"]
+    Audio,
+    #[name = "archives"]
+    Archive,
+    #[name = "executables"]
+    Executable,
+    #[name = "documents"]
+    Document,
+    #[name = "other"]
+    Other,
+}
+
+impl FileKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileKind::Image => "images",
+            FileKind::Video => "videos",
+            FileKind::Audio => "audio",
+            FileKind::Archive => "archives",
+            FileKind::Executable => "executables",
+            FileKind::Document => "documents",
+            FileKind::Other => "other",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "images" => FileKind::Image,
+            "videos" => FileKind::Video,
+            "audio" => FileKind::Audio,
+            "archives" => FileKind::Archive,
+            "executables" => FileKind::Executable,
+            "documents" => FileKind::Document,
+            _ => FileKind::Other,
+        }
+    }
+}
+
+/// Works out a file's kind from its first bytes.
+pub fn sniff(bytes: &[u8]) -> FileKind {
+    let starts = |magic: &[u8]| bytes.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| {
+        bytes
+            .get(offset..offset + magic.len())
+            .is_some_and(|slice| slice == magic)
+    };
+
+    if starts(b"MZ")
+        || starts(b"\x7FELF")
+        || starts(&[0xFE, 0xED, 0xFA, 0xCE])
+        || starts(&[0xFE, 0xED, 0xFA, 0xCF])
+        || starts(&[0xCE, 0xFA, 0xED, 0xFE])
+        || starts(&[0xCF, 0xFA, 0xED, 0xFE])
+        || starts(&[0xCA, 0xFE, 0xBA, 0xBE])
+        || starts(b"#!")
+    {
+        return FileKind::Executable;
+    }
+    if starts(b"PK\x03\x04") {
+        // Office documents, jars and apks are all zips. The first entry usually says which.
+        let name_len = bytes
+            .get(26..28)
+            .map_or(0, |len| u16::from_le_bytes([len[0], len[1]]) as usize);
+        let name = bytes.get(30..30 + name_len).unwrap_or_default();
+        return if name.starts_with(b"[Content_Types].xml") || name.starts_with(b"mimetype") {
+            FileKind::Document
+        } else if name.starts_with(b"META-INF/")
+            || name.starts_with(b"AndroidManifest.xml")
+            || name.starts_with(b"classes.dex")
+        {
+            FileKind::Executable
+        } else {
+            FileKind::Archive
+        };
+    }
+    if starts(b"PK\x05\x06")
+        || starts(b"PK\x07\x08")
+        || starts(b"Rar!\x1A\x07")
+        || starts(&[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C])
+        || starts(&[0x1F, 0x8B])
+        || starts(b"BZh")
+        || starts(&[0xFD, b'7', b'z', b'X', b'Z', 0x00])
+        || at(257, b"ustar")
+    {
+        return FileKind::Archive;
+    }
+    if starts(b"%PDF")
+        || starts(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1])
+        || starts(b"{\\rtf")
+    {
+        return FileKind::Document;
+    }
+    if starts(&[0x89, b'P', b'N', b'G'])
+        || starts(&[0xFF, 0xD8, 0xFF])
+        || starts(b"GIF8")
+        || (starts(b"RIFF") && at(8, b"WEBP"))
+        || (starts(b"BM") && at(6, &[0, 0, 0, 0]))
+    {
+        return FileKind::Image;
+    }
+    if starts(b"ID3")
+        || starts(&[0xFF, 0xFB])
+        || starts(&[0xFF, 0xF3])
+        || starts(&[0xFF, 0xF2])
+        || starts(b"OggS")
+        || starts(b"fLaC")
+        || (starts(b"RIFF") && at(8, b"WAVE"))
+        || (at(4, b"ftyp") && (at(8, b"M4A ") || at(8, b"M4B ")))
+    {
+        return FileKind::Audio;
+    }
+    if at(4, b"ftyp") || starts(&[0x1A, 0x45, 0xDF, 0xA3]) || (starts(b"RIFF") && at(8, b"AVI ")) {
+        return FileKind::Video;
+    }
+    FileKind::Other
+}
+
+/// Fetches the start of an attachment. The CDN may ignore the range and send the
+/// whole file, so stop reading once there's enough.
+async fn head_bytes(url: &str) -> Result<Vec<u8>, Error> {
+    let mut response = CDN
+        .get(url)
+        .header(
+            reqwest::header::RANGE,
+            format!("bytes=0-{}", SNIFF_BYTES - 1),
+        )
+        .send()
+        .await?
+        .error_for_status()?;
+    let mut bytes = Vec::with_capacity(SNIFF_BYTES);
+    while bytes.len() < SNIFF_BYTES
+        && let Some(chunk) = response.chunk().await?
+    {
+        bytes.extend_from_slice(&chunk);
+    }
+    bytes.truncate(SNIFF_BYTES);
+    Ok(bytes)
+}
+
+/// What an attachment is, going only by its contents. One that can't be fetched
+/// counts as [`FileKind::Other`], its name is never trusted.
+async fn attachment_kind(attachment: &serenity::Attachment) -> FileKind {
+    match head_bytes(&attachment.url).await {
+        Ok(bytes) => sniff(&bytes),
+        Err(_) => FileKind::Other,
+    }
+}
+
+/// A guild's attachment rules.
+#[derive(Debug, Clone, Default)]
+pub struct GuildRules {
+    pub action: Option<AutomodAction>,
+    pub blocked: HashMap<serenity::ChannelId, HashSet<FileKind>>,
+}
+
+// Helper struct to map the query result
+struct AttachmentRuleRow {
+    channel_id: i64,
+    kind: String,
+}
+
+/// Attachment filter state shared between the event handler and the commands.
+#[derive(Default)]
+pub struct AttachmentFilter {
+    guilds: RwLock<HashMap<serenity::GuildId, Arc<GuildRules>>>,
+}
+
+impl AttachmentFilter {
+    /// The guild's rules, loaded from the database when not cached.
+    pub async fn rules(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Arc<GuildRules>, Error> {
+        if let Some(rules) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(rules.clone());
+        }
+        let rules = Arc::new(load_rules(pool, guild_id).await?);
+        self.guilds.write().unwrap().insert(guild_id, rules.clone());
+        Ok(rules)
+    }
+
+    /// Drops the cached rules so the next message reloads them.
+    pub fn invalidate(&self, guild_id: serenity::GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+}
+
+async fn load_rules(pool: &Pool<Sqlite>, guild_id: serenity::GuildId) -> Result<GuildRules, Error> {
+    let guild_id = guild_id.get() as i64;
+    let action = sqlx::query!(
+        "SELECT action FROM attachment_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let rows = sqlx::query_as!(
+        AttachmentRuleRow,
+        "SELECT channel_id, kind FROM attachment_rules WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut rules = GuildRules {
+        action: action.map(|row| AutomodAction::from_db(&row.action)),
+        ..Default::default()
+    };
+    for row in rows {
+        rules
+            .blocked
+            .entry(serenity::ChannelId::new(row.channel_id as u64))
+            .or_default()
+            .insert(FileKind::from_db(&row.kind));
+    }
+    Ok(rules)
+}
+
+/// Checks a message's attachments against its channel's rules, returning `true` if it
+/// was acted on.
+pub async fn check_message(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    filter: &AttachmentFilter,
+    automod: &automod::Automod,
+    msg: &serenity::Message,
+) -> Result<bool, Error> {
+    let Some(guild_id) = msg.guild_id else {
+        return Ok(false);
+    };
+    if msg.attachments.is_empty() || msg.author.bot || msg.webhook_id.is_some() {
+        return Ok(false);
+    }
+    let rules = filter.rules(pool, guild_id).await?;
+    // Threads follow the rules of the channel they're in.
+    let parent = ctx.cache.guild(guild_id).and_then(|guild| {
+        guild
+            .threads
+            .iter()
+            .find(|thread| thread.id == msg.channel_id)
+            .and_then(|thread| thread.parent_id)
+    });
+    let Some(blocked) = rules
+        .blocked
+        .get(&msg.channel_id)
+        .or_else(|| parent.and_then(|parent| rules.blocked.get(&parent)))
+    else {
+        return Ok(false);
+    };
+    if moderation::is_moderator(ctx, guild_id, msg.channel_id, msg.author.id) {
+        return Ok(false);
+    }
+
+    let mut found = None;
+    for attachment in &msg.attachments {
+        let kind = attachment_kind(attachment).await;
+        if blocked.contains(&kind) {
+            found = Some(kind);
+            break;
+        }
+    }
+    let Some(kind) = found else {
+        return Ok(false);
+    };
+
+    // Timeouts last as long as the guild's automod timeouts do.
+    let timeout_secs = automod
+        .config(pool, guild_id)
+        .await?
+        .unwrap_or_default()
+        .timeout_secs;
+    automod::punish(
+        ctx,
+        pool,
+        msg,
+        rules.action.unwrap_or(AutomodAction::Delete),
+        timeout_secs,
+        "Attachment filter",
+        &format!("posting {} in this channel", kind.as_str()),
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Restrict which kinds of file can be posted in each channel.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("block", "allow", "list", "action")
+)]
+pub async fn attachments(ctx: Context<'_>) -> Result<(), Error> {
+    list_inner(ctx).await
+}
+
+async fn set_blocked(
+    ctx: Context<'_>,
+    kind: FileKind,
+    channel: Option<serenity::GuildChannel>,
+    block: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let channel_id = match channel {
+        Some(channel) if channel.guild_id == guild_id => channel.id,
+        Some(_) => {
+            ctx.say("❌ That isn't a channel in this server.").await?;
+            return Ok(());
+        }
+        None => ctx.channel_id(),
+    };
+    let data = ctx.data();
+    let channel_id_db = channel_id.get() as i64;
+    let guild_id_db = guild_id.get() as i64;
+    let kind_str = kind.as_str();
+    if block {
+        sqlx::query!(
+            "INSERT INTO attachment_rules (channel_id, guild_id, kind) VALUES (?, ?, ?)
+             ON CONFLICT (channel_id, kind) DO NOTHING",
+            channel_id_db,
+            guild_id_db,
+            kind_str
+        )
+        .execute(&data.db_pool)
+        .await?;
+    } else {
+        sqlx::query!(
+            "DELETE FROM attachment_rules WHERE channel_id = ? AND kind = ?",
+            channel_id_db,
+            kind_str
+        )
+        .execute(&data.db_pool)
+        .await?;
+    }
+    data.attachment_filter.invalidate(guild_id);
+
+    ctx.say(if block {
+        format!(
+            "📎 {} are now blocked in {}.",
+            kind_str,
+            channel_id.mention()
+        )
+    } else {
+        format!(
+            "📎 {} are allowed in {} again.",
+            kind_str,
+            channel_id.mention()
+        )
+    })
+    .await?;
+    Ok(())
+}
+
+/// Block a kind of file in a channel.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn block(
+    ctx: Context<'_>,
+    #[description = "Kind of file to block"] kind: FileKind,
+    #[description = "Channel (default: this one)"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    set_blocked(ctx, kind, channel, true).await
+}
+
+/// Allow a blocked kind of file in a channel again.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn allow(
+    ctx: Context<'_>,
+    #[description = "Kind of file to allow"] kind: FileKind,
+    #[description = "Channel (default: this one)"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    set_blocked(ctx, kind, channel, false).await
+}
+
+async fn list_inner(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let rules = data
+        .attachment_filter
+        .rules(&data.db_pool, guild_id)
+        .await?;
+
+    let mut channels: Vec<_> = rules.blocked.iter().collect();
+    channels.sort_by_key(|(channel_id, _)| **channel_id);
+    let list = if channels.is_empty() {
+        "No channels have attachment rules.".to_string()
+    } else {
+        channels
+            .into_iter()
+            .map(|(channel_id, kinds)| {
+                let mut kinds: Vec<_> = kinds.iter().map(|kind| kind.as_str()).collect();
+                kinds.sort_unstable();
+                format!("{}: no {}", channel_id.mention(), kinds.join(", "))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    ctx.send(
+        poise::CreateReply::default().embed(
+            serenity::CreateEmbed::new()
+                .title("Attachment rules")
+                .description(list)
+                .field(
+                    "Action",
+                    rules.action.unwrap_or(AutomodAction::Delete).as_str(),
+                    false,
+                )
+                .footer(serenity::CreateEmbedFooter::new(
+                    "File types are checked from their contents, not their names.",
+                ))
+                .color(serenity::Color::DARK_RED),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// List the channels with attachment rules.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_inner(ctx).await
+}
+
+/// Choose what happens to someone who posts a blocked file. It's always deleted.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn action(
+    ctx: Context<'_>,
+    #[description = "What to do on top of deleting the message"] action: AutomodAction,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let guild_id_db = guild_id.get() as i64;
+    let action_str = action.as_str();
+    sqlx::query!(
+        "INSERT INTO attachment_config (guild_id, action) VALUES (?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET action = excluded.action",
+        guild_id_db,
+        action_str
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.attachment_filter.invalidate(guild_id);
+    ctx.say(format!("Blocked attachments now get: {}.", action_str))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zip_with_first_entry(name: &[u8]) -> Vec<u8> {
+        let mut bytes = b"PK\x03\x04".to_vec();
+        bytes.resize(26, 0);
+        bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(name);
+        bytes
+    }
+
+    #[test]
+    fn sniffs_common_types() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n"), FileKind::Image);
+        assert_eq!(sniff(b"MZ\x90\x00"), FileKind::Executable);
+        assert_eq!(sniff(b"\x7FELF\x02"), FileKind::Executable);
+        assert_eq!(sniff(b"%PDF-1.7"), FileKind::Document);
+        assert_eq!(sniff(b"Rar!\x1A\x07\x00"), FileKind::Archive);
+        assert_eq!(sniff(b"RIFF\0\0\0\0WAVEfmt "), FileKind::Audio);
+        assert_eq!(sniff(b"\0\0\0\x18ftypmp42"), FileKind::Video);
+        assert_eq!(sniff(b"hello there"), FileKind::Other);
+    }
+
+    #[test]
+    fn sniffs_from_the_fetched_start_alone() {
+        let mut tar = vec![0; 8192];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar[..SNIFF_BYTES]), FileKind::Archive);
+        // A renamed executable is still an executable.
+        let mut exe = b"MZ".to_vec();
+        exe.resize(SNIFF_BYTES, 0);
+        assert_eq!(sniff(&exe), FileKind::Executable);
+        assert_eq!(sniff(&[]), FileKind::Other);
+    }
+
+    #[test]
+    fn tells_zips_apart() {
+        assert_eq!(
+            sniff(&zip_with_first_entry(b"photos/a.jpg")),
+            FileKind::Archive
+        );
+        assert_eq!(
+            sniff(&zip_with_first_entry(b"[Content_Types].xml")),
+            FileKind::Document
+        );
+        assert_eq!(
+            sniff(&zip_with_first_entry(b"META-INF/MANIFEST.MF")),
+            FileKind::Executable
+        );
+    }
+}
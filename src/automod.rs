@@ -0,0 +1,762 @@
+use crate::moderation::{self, ModAction};
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use sqlx::{Pool, Sqlite};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// What automod does to someone who trips a threshold. The message is always deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum AutomodAction {
+    #[name = "delete"]
+    Delete,
+    #[name = "warn"]
+    Warn,
+    #[name = "timeout"]
+    Timeout,
+    #[name = "kick"]
+    Kick,
+}
+
+impl AutomodAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AutomodAction::Delete => "delete",
+            AutomodAction::Warn => "warn",
+            AutomodAction::Timeout => "timeout",
+            AutomodAction::Kick => "kick",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "delete" => AutomodAction::Delete,
+            "warn" => AutomodAction::Warn,
+            "kick" => AutomodAction::Kick,
+            _ => AutomodAction::Timeout,
+        }
+    }
+}
+
+/// A guild's automod thresholds, as stored in `automod_config`.
+#[derive(Debug, Clone)]
+pub struct AutomodConfig {
+    pub enabled: bool,
+    /// More than this many messages inside the window is a flood.
+    pub max_messages: i64,
+    pub window_secs: i64,
+    /// This many copies of the same message inside the window is spam.
+    pub max_duplicates: i64,
+    /// This many mentions in a single message is a mass mention.
+    pub max_mentions: i64,
+    pub action: AutomodAction,
+    pub timeout_secs: i64,
+}
+
+impl Default for AutomodConfig {
+    fn default() -> Self {
+        AutomodConfig {
+            enabled: false,
+            max_messages: 8,
+            window_secs: 10,
+            max_duplicates: 4,
+            max_mentions: 6,
+            action: AutomodAction::Timeout,
+            timeout_secs: 600,
+        }
+    }
+}
+
+// Helper struct to map the query result
+struct AutomodRow {
+    enabled: bool,
+    max_messages: i64,
+    window_secs: i64,
+    max_duplicates: i64,
+    max_mentions: i64,
+    action: String,
+    timeout_secs: i64,
+}
+
+impl From<AutomodRow> for AutomodConfig {
+    fn from(row: AutomodRow) -> Self {
+        AutomodConfig {
+            enabled: row.enabled,
+            max_messages: row.max_messages,
+            window_secs: row.window_secs,
+            max_duplicates: row.max_duplicates,
+            max_mentions: row.max_mentions,
+            action: AutomodAction::from_db(&row.action),
+            timeout_secs: row.timeout_secs,
+        }
+    }
+}
+
+/// Why a message tripped automod.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Flood(usize),
+    Duplicate(usize),
+    MassMention(usize),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Flood(n) => write!(f, "sending messages too fast ({} in a row)", n),
+            Violation::Duplicate(n) => write!(f, "repeating the same message ({} times)", n),
+            Violation::MassMention(n) => write!(f, "mass mentioning ({} mentions)", n),
+        }
+    }
+}
+
+// Recent messages from one user in one guild, oldest first.
+#[derive(Default)]
+struct UserWindow {
+    messages: VecDeque<(Instant, u64)>,
+}
+
+/// Automod state shared between the event handler and the commands.
+#[derive(Default)]
+pub struct Automod {
+    // `None` caches "this guild never configured automod" so we don't ask the database again.
+    configs: RwLock<HashMap<serenity::GuildId, Option<AutomodConfig>>>,
+    windows: Mutex<HashMap<(serenity::GuildId, serenity::UserId), UserWindow>>,
+    seen: AtomicU64,
+}
+
+impl Automod {
+    /// The guild's config, loaded from the database the first time it's needed.
+    pub async fn config(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Option<AutomodConfig>, Error> {
+        if let Some(cached) = self.configs.read().unwrap().get(&guild_id) {
+            return Ok(cached.clone());
+        }
+        let config = load_config(pool, guild_id).await?;
+        self.configs
+            .write()
+            .unwrap()
+            .insert(guild_id, config.clone());
+        Ok(config)
+    }
+
+    fn set_cached(&self, guild_id: serenity::GuildId, config: AutomodConfig) {
+        self.configs.write().unwrap().insert(guild_id, Some(config));
+    }
+
+    /// Adds a message to its author's sliding window and reports a violation if one of
+    /// the thresholds was crossed.
+    pub fn record(
+        &self,
+        guild_id: serenity::GuildId,
+        msg: &serenity::Message,
+        config: &AutomodConfig,
+    ) -> Option<Violation> {
+        self.record_at(guild_id, msg, config, Instant::now())
+    }
+
+    fn record_at(
+        &self,
+        guild_id: serenity::GuildId,
+        msg: &serenity::Message,
+        config: &AutomodConfig,
+        now: Instant,
+    ) -> Option<Violation> {
+        let mentions =
+            msg.mentions.len() + msg.mention_roles.len() + if msg.mention_everyone { 1 } else { 0 };
+        if config.max_mentions > 0 && mentions as i64 >= config.max_mentions {
+            return Some(Violation::MassMention(mentions));
+        }
+
+        let window = Duration::from_secs(config.window_secs.max(1) as u64);
+        let key = (guild_id, msg.author.id);
+
+        let mut hasher = DefaultHasher::new();
+        msg.content.trim().to_lowercase().hash(&mut hasher);
+        let content_hash = hasher.finish();
+
+        let mut windows = self.windows.lock().unwrap();
+        self.prune(&mut windows, now);
+
+        let entry = windows.entry(key).or_default();
+        entry.messages.push_back((now, content_hash));
+        while let Some((sent, _)) = entry.messages.front() {
+            if now.duration_since(*sent) > window {
+                entry.messages.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let count = entry.messages.len();
+        let duplicates = if msg.content.trim().is_empty() {
+            0
+        } else {
+            entry
+                .messages
+                .iter()
+                .filter(|(_, hash)| *hash == content_hash)
+                .count()
+        };
+
+        let violation = if config.max_messages > 0 && count as i64 > config.max_messages {
+            Some(Violation::Flood(count))
+        } else if config.max_duplicates > 0 && duplicates as i64 >= config.max_duplicates {
+            Some(Violation::Duplicate(duplicates))
+        } else {
+            None
+        };
+
+        // Start over after acting, otherwise every following message would trip it again.
+        if violation.is_some() {
+            windows.remove(&key);
+        }
+        violation
+    }
+
+    // Every so often, drop windows of users who have gone quiet so the map doesn't grow forever.
+    fn prune(
+        &self,
+        windows: &mut HashMap<(serenity::GuildId, serenity::UserId), UserWindow>,
+        now: Instant,
+    ) {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) + 1;
+        if !seen.is_multiple_of(1000) {
+            return;
+        }
+        windows.retain(|_, window| {
+            window
+                .messages
+                .back()
+                .is_some_and(|(sent, _)| now.duration_since(*sent) < Duration::from_secs(600))
+        });
+    }
+}
+
+async fn load_config(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<Option<AutomodConfig>, Error> {
+    let guild_id = guild_id.get() as i64;
+    let row = sqlx::query_as!(
+        AutomodRow,
+        "SELECT enabled, max_messages, window_secs, max_duplicates, max_mentions, action, timeout_secs
+         FROM automod_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(AutomodConfig::from))
+}
+
+async fn save_config(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    config: &AutomodConfig,
+) -> Result<(), Error> {
+    let guild_id = guild_id.get() as i64;
+    let action = config.action.as_str();
+    sqlx::query!(
+        "INSERT INTO automod_config
+            (guild_id, enabled, max_messages, window_secs, max_duplicates, max_mentions, action, timeout_secs)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            max_messages = excluded.max_messages,
+            window_secs = excluded.window_secs,
+            max_duplicates = excluded.max_duplicates,
+            max_mentions = excluded.max_mentions,
+            action = excluded.action,
+            timeout_secs = excluded.timeout_secs",
+        guild_id,
+        config.enabled,
+        config.max_messages,
+        config.window_secs,
+        config.max_duplicates,
+        config.max_mentions,
+        action,
+        config.timeout_secs
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Runs automod against a freshly received message.
+pub async fn check_message(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    automod: &Automod,
+    msg: &serenity::Message,
+) -> Result<(), Error> {
+    let Some(guild_id) = msg.guild_id else {
+        return Ok(()); // DMs aren't moderated
+    };
+    if msg.author.bot || msg.webhook_id.is_some() {
+        return Ok(());
+    }
+    let Some(config) = automod.config(pool, guild_id).await? else {
+        return Ok(());
+    };
+    if !config.enabled || moderation::is_moderator(ctx, guild_id, msg.channel_id, msg.author.id) {
+        return Ok(());
+    }
+
+    let Some(violation) = automod.record(guild_id, msg, &config) else {
+        return Ok(());
+    };
+    punish(
+        ctx,
+        pool,
+        msg,
+        config.action,
+        config.timeout_secs,
+        "Automod",
+        &violation.to_string(),
+    )
+    .await
+}
+
+/// Deletes `msg` and applies `action` to its author. `source` names the system acting
+/// (e.g. "Automod") and `why` finishes the sentence "stop ...".
+pub async fn punish(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    msg: &serenity::Message,
+    action: AutomodAction,
+    timeout_secs: i64,
+    source: &str,
+    why: &str,
+) -> Result<(), Error> {
+    let Some(guild_id) = msg.guild_id else {
+        return Ok(());
+    };
+    // The message might already be gone, that's fine.
+    let _ = msg.delete(ctx).await;
+
+    let bot_id = ctx.cache.current_user().id;
+    let reason = format!("{}: {}", source, why);
+    let notice = match action {
+        AutomodAction::Delete => return Ok(()),
+        AutomodAction::Warn => {
+            moderation::record_case(
+                pool,
+                guild_id,
+                msg.author.id,
+                bot_id,
+                ModAction::Warn,
+                &reason,
+            )
+            .await?;
+            format!("⚠️ {}, stop {}.", msg.author.mention(), why)
+        }
+        AutomodAction::Timeout => {
+            let until = serenity::Timestamp::from_unix_timestamp(
+                serenity::Timestamp::now().unix_timestamp() + timeout_secs,
+            )?;
+            guild_id
+                .edit_member(
+                    ctx,
+                    msg.author.id,
+                    serenity::EditMember::new()
+                        .disable_communication_until_datetime(until)
+                        .audit_log_reason(&reason),
+                )
+                .await?;
+            moderation::record_case(
+                pool,
+                guild_id,
+                msg.author.id,
+                bot_id,
+                ModAction::Timeout,
+                &reason,
+            )
+            .await?;
+            format!(
+                "🔇 {} has been timed out for {} seconds for {}.",
+                msg.author.mention(),
+                timeout_secs,
+                why
+            )
+        }
+        AutomodAction::Kick => {
+            guild_id
+                .kick_with_reason(ctx, msg.author.id, &reason)
+                .await?;
+            moderation::record_case(
+                pool,
+                guild_id,
+                msg.author.id,
+                bot_id,
+                ModAction::Kick,
+                &reason,
+            )
+            .await?;
+            format!("👢 {} has been kicked for {}.", msg.author.tag(), why)
+        }
+    };
+    msg.channel_id.say(ctx, notice).await?;
+    Ok(())
+}
+
+fn describe(config: &AutomodConfig) -> serenity::CreateEmbed {
+    serenity::CreateEmbed::new()
+        .title("Automod")
+        .field(
+            "Status",
+            if config.enabled {
+                "Enabled"
+            } else {
+                "Disabled"
+            },
+            false,
+        )
+        .field(
+            "Flood",
+            format!(
+                "More than {} messages in {} seconds",
+                config.max_messages, config.window_secs
+            ),
+            false,
+        )
+        .field(
+            "Duplicates",
+            format!(
+                "{} identical messages in {} seconds",
+                config.max_duplicates, config.window_secs
+            ),
+            false,
+        )
+        .field(
+            "Mass mentions",
+            format!("{} mentions in one message", config.max_mentions),
+            false,
+        )
+        .field(
+            "Action",
+            match config.action {
+                AutomodAction::Timeout => format!("timeout for {} seconds", config.timeout_secs),
+                action => action.as_str().to_string(),
+            },
+            false,
+        )
+        .footer(serenity::CreateEmbedFooter::new(
+            "A threshold of 0 turns that check off.",
+        ))
+        .color(serenity::Color::DARK_RED)
+}
+
+// Loads the current config (or the defaults), applies `change`, and saves it back.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut AutomodConfig),
+) -> Result<AutomodConfig, Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let mut config = data
+        .automod
+        .config(&data.db_pool, guild_id)
+        .await?
+        .unwrap_or_default();
+    change(&mut config);
+    save_config(&data.db_pool, guild_id, &config).await?;
+    data.automod.set_cached(guild_id, config.clone());
+    Ok(config)
+}
+
+/// Configure flood, duplicate and mass mention detection.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("show", "enable", "disable", "thresholds", "action")
+)]
+pub async fn automod(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx).await
+}
+
+async fn show_inner(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let config = data
+        .automod
+        .config(&data.db_pool, guild_id)
+        .await?
+        .unwrap_or_default();
+    ctx.send(poise::CreateReply::default().embed(describe(&config)))
+        .await?;
+    Ok(())
+}
+
+/// Show the current automod settings.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx).await
+}
+
+/// Turn automod on.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn enable(ctx: Context<'_>) -> Result<(), Error> {
+    let config = update_config(ctx, |config| config.enabled = true).await?;
+    ctx.send(
+        poise::CreateReply::default()
+            .content("Automod is now enabled.")
+            .embed(describe(&config)),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Turn automod off.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = false).await?;
+    ctx.say("Automod is now disabled.").await?;
+    Ok(())
+}
+
+/// Change the automod thresholds. Leave one out to keep it as is, 0 turns a check off.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn thresholds(
+    ctx: Context<'_>,
+    #[description = "Messages allowed per window"]
+    #[min = 0]
+    messages: Option<u32>,
+    #[description = "Window length (e.g. 10s, 1m)"] window: Option<String>,
+    #[description = "Identical messages allowed per window"]
+    #[min = 0]
+    duplicates: Option<u32>,
+    #[description = "Mentions allowed in one message"]
+    #[min = 0]
+    mentions: Option<u32>,
+) -> Result<(), Error> {
+    let window = match window {
+        Some(window) => match moderation::parse_duration(&window) {
+            Some(duration) if duration.as_secs() > 0 && duration.as_secs() <= 3600 => {
+                Some(duration.as_secs() as i64)
+            }
+            _ => {
+                ctx.say("❌ The window must be between 1 second and 1 hour, e.g. `10s`.")
+                    .await?;
+                return Ok(());
+            }
+        },
+        _none => None,
+    };
+
+    let config = update_config(ctx, |config| {
+        if let Some(messages) = messages {
+            config.max_messages = messages as i64;
+        }
+        if let Some(window) = window {
+            config.window_secs = window;
+        }
+        if let Some(duplicates) = duplicates {
+            config.max_duplicates = duplicates as i64;
+        }
+        if let Some(mentions) = mentions {
+            config.max_mentions = mentions as i64;
+        }
+    })
+    .await?;
+    ctx.send(
+        poise::CreateReply::default()
+            .content("Automod thresholds updated.")
+            .embed(describe(&config)),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Choose what automod does when someone trips a threshold.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn action(
+    ctx: Context<'_>,
+    #[description = "What to do on top of deleting the message"] action: AutomodAction,
+    #[description = "How long timeouts last (e.g. 10m, 1h)"] duration: Option<String>,
+) -> Result<(), Error> {
+    let timeout = match duration {
+        Some(duration) => match moderation::parse_duration(&duration) {
+            // Discord caps timeouts at 28 days.
+            Some(d) if d.as_secs() > 0 && d.as_secs() <= 28 * 24 * 60 * 60 => {
+                Some(d.as_secs() as i64)
+            }
+            _ => {
+                ctx.say("❌ Timeouts must be between 1 second and 28 days, e.g. `10m`.")
+                    .await?;
+                return Ok(());
+            }
+        },
+        _none => None,
+    };
+
+    let config = update_config(ctx, |config| {
+        config.action = action;
+        if let Some(timeout) = timeout {
+            config.timeout_secs = timeout;
+        }
+    })
+    .await?;
+    ctx.send(
+        poise::CreateReply::default()
+            .content("Automod action updated.")
+            .embed(describe(&config)),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: serenity::GuildId = serenity::GuildId::new(1);
+    const ALICE: serenity::UserId = serenity::UserId::new(2);
+    const BOB: serenity::UserId = serenity::UserId::new(3);
+
+    fn message(author: serenity::UserId, content: &str) -> serenity::Message {
+        let mut msg = serenity::Message::default();
+        msg.author.id = author;
+        msg.content = content.to_string();
+        msg
+    }
+
+    fn config() -> AutomodConfig {
+        AutomodConfig {
+            enabled: true,
+            max_messages: 3,
+            window_secs: 10,
+            max_duplicates: 3,
+            max_mentions: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flags_floods_past_the_limit() {
+        let automod = Automod::default();
+        let start = Instant::now();
+        for (i, content) in ["a", "b", "c"].into_iter().enumerate() {
+            let at = start + Duration::from_secs(i as u64);
+            assert_eq!(
+                automod.record_at(GUILD, &message(ALICE, content), &config(), at),
+                None
+            );
+        }
+        // Someone else's messages count separately.
+        assert_eq!(
+            automod.record_at(GUILD, &message(BOB, "x"), &config(), start),
+            None
+        );
+        assert_eq!(
+            automod.record_at(GUILD, &message(ALICE, "d"), &config(), start),
+            Some(Violation::Flood(4))
+        );
+    }
+
+    #[test]
+    fn forgets_messages_outside_the_window() {
+        let automod = Automod::default();
+        let start = Instant::now();
+        for content in ["a", "b", "c"] {
+            automod.record_at(GUILD, &message(ALICE, content), &config(), start);
+        }
+        let later = start + Duration::from_secs(11);
+        assert_eq!(
+            automod.record_at(GUILD, &message(ALICE, "d"), &config(), later),
+            None
+        );
+    }
+
+    #[test]
+    fn flags_duplicates_ignoring_case_and_blank_messages() {
+        let automod = Automod::default();
+        let config = AutomodConfig {
+            max_messages: 0,
+            ..config()
+        };
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert_eq!(
+                automod.record_at(GUILD, &message(ALICE, "  "), &config, now),
+                None
+            );
+        }
+        assert_eq!(
+            automod.record_at(GUILD, &message(ALICE, "buy nitro"), &config, now),
+            None
+        );
+        assert_eq!(
+            automod.record_at(GUILD, &message(ALICE, "BUY NITRO "), &config, now),
+            None
+        );
+        assert_eq!(
+            automod.record_at(GUILD, &message(ALICE, "Buy Nitro"), &config, now),
+            Some(Violation::Duplicate(3))
+        );
+    }
+
+    #[test]
+    fn flags_mass_mentions() {
+        let automod = Automod::default();
+        let now = Instant::now();
+        let mut msg = message(ALICE, "hey");
+        msg.mentions = vec![serenity::User::default(); 2];
+        msg.mention_roles = vec![serenity::RoleId::new(5)];
+        assert_eq!(automod.record_at(GUILD, &msg, &config(), now), None);
+        msg.mention_everyone = true;
+        assert_eq!(
+            automod.record_at(GUILD, &msg, &config(), now),
+            Some(Violation::MassMention(4))
+        );
+    }
+
+    #[test]
+    fn zero_disables_a_threshold() {
+        let automod = Automod::default();
+        let config = AutomodConfig {
+            max_messages: 0,
+            max_duplicates: 0,
+            max_mentions: 0,
+            ..config()
+        };
+        let now = Instant::now();
+        let mut msg = message(ALICE, "same");
+        msg.mention_everyone = true;
+        for _ in 0..20 {
+            assert_eq!(automod.record_at(GUILD, &msg, &config, now), None);
+        }
+    }
+
+    #[test]
+    fn starts_over_after_a_violation() {
+        let automod = Automod::default();
+        let now = Instant::now();
+        for content in ["a", "b", "c"] {
+            automod.record_at(GUILD, &message(ALICE, content), &config(), now);
+        }
+        assert_eq!(
+            automod.record_at(GUILD, &message(ALICE, "d"), &config(), now),
+            Some(Violation::Flood(4))
+        );
+        for content in ["e", "f", "g"] {
+            assert_eq!(
+                automod.record_at(GUILD, &message(ALICE, content), &config(), now),
+                None
+            );
+        }
+    }
+}
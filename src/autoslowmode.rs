@@ -0,0 +1,453 @@
+use crate::channels::MAX_SLOWMODE_SECS;
+use crate::moderation;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Messages are counted over this rolling window.
+const WINDOW: Duration = Duration::from_secs(60);
+/// How often every channel's rate is looked at.
+const TICK: Duration = Duration::from_secs(15);
+/// A channel's slowmode is changed at most this often, so it can't flap.
+const COOLDOWN: Duration = Duration::from_secs(30);
+/// The slowmode values the bot steps through, one at a time.
+const LADDER: &[u16] = &[
+    0, 2, 5, 10, 15, 30, 60, 120, 300, 600, 900, 1800, 3600, 7200, 21600,
+];
+
+/// A channel's adaptive slowmode settings, as stored in `auto_slowmode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub min_secs: u16,
+    pub max_secs: u16,
+    /// Messages per minute the channel is aimed at. Above this the slowmode goes up,
+    /// below half of it the slowmode comes back down.
+    pub target_rate: u32,
+}
+
+// Helper struct to map the query result
+struct AutoSlowmodeRow {
+    channel_id: i64,
+    guild_id: i64,
+    min_secs: i64,
+    max_secs: i64,
+    target_rate: i64,
+    current_secs: i64,
+}
+
+struct ChannelState {
+    guild_id: serenity::GuildId,
+    settings: Settings,
+    messages: VecDeque<Instant>,
+    current: u16,
+    last_change: Option<Instant>,
+}
+
+/// The slowmode a channel should move to, if any. Moves one step at a time and only
+/// when the rate is clearly above or below target, so a channel hovering around the
+/// target keeps its current slowmode.
+pub fn next_slowmode(current: u16, rate: u32, settings: &Settings) -> Option<u16> {
+    let mut steps: Vec<u16> = LADDER
+        .iter()
+        .copied()
+        .filter(|step| *step > settings.min_secs && *step < settings.max_secs)
+        .collect();
+    steps.push(settings.min_secs);
+    steps.push(settings.max_secs);
+    steps.sort_unstable();
+    steps.dedup();
+
+    let next = if rate > settings.target_rate {
+        steps.into_iter().find(|step| *step > current)
+    } else if rate * 2 < settings.target_rate {
+        steps.into_iter().rev().find(|step| *step < current)
+    } else {
+        None
+    };
+    // Someone may have set the slowmode by hand, pull it back inside the bounds.
+    let clamped = current.clamp(settings.min_secs, settings.max_secs);
+    match next {
+        Some(next) => Some(next),
+        None if clamped != current => Some(clamped),
+        None => None,
+    }
+}
+
+/// Adaptive slowmode state shared between the event handler and the commands.
+#[derive(Default)]
+pub struct AutoSlowmode {
+    channels: Mutex<HashMap<serenity::ChannelId, ChannelState>>,
+    ticking: AtomicBool,
+}
+
+impl AutoSlowmode {
+    /// Loads every configured channel, so the ticker knows about them right away.
+    pub async fn load(pool: &Pool<Sqlite>) -> Result<Self, Error> {
+        let rows = sqlx::query_as!(
+            AutoSlowmodeRow,
+            "SELECT channel_id, guild_id, min_secs, max_secs, target_rate, current_secs
+             FROM auto_slowmode"
+        )
+        .fetch_all(pool)
+        .await?;
+        let autoslowmode = AutoSlowmode::default();
+        for row in rows {
+            autoslowmode.insert(row);
+        }
+        Ok(autoslowmode)
+    }
+
+    fn insert(&self, row: AutoSlowmodeRow) {
+        self.channels.lock().unwrap().insert(
+            serenity::ChannelId::new(row.channel_id as u64),
+            ChannelState {
+                guild_id: serenity::GuildId::new(row.guild_id as u64),
+                settings: Settings {
+                    min_secs: row.min_secs as u16,
+                    max_secs: row.max_secs as u16,
+                    target_rate: row.target_rate as u32,
+                },
+                messages: VecDeque::new(),
+                current: row.current_secs as u16,
+                last_change: None,
+            },
+        );
+    }
+
+    fn remove(&self, channel_id: serenity::ChannelId) {
+        self.channels.lock().unwrap().remove(&channel_id);
+    }
+
+    /// Counts a message towards its channel's rate, if the channel is being watched.
+    pub fn record_message(&self, channel_id: serenity::ChannelId) {
+        if let Some(state) = self.channels.lock().unwrap().get_mut(&channel_id) {
+            state.messages.push_back(Instant::now());
+        }
+    }
+
+    /// Starts the background task that adjusts slowmodes. Only the first call does
+    /// anything, `ready` fires once per shard.
+    pub fn start(self: &Arc<Self>, http: Arc<serenity::Http>, pool: Pool<Sqlite>) {
+        if self.ticking.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let autoslowmode = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TICK);
+            loop {
+                interval.tick().await;
+                for (channel_id, secs) in autoslowmode.due_changes() {
+                    if let Err(e) = autoslowmode.apply(&http, &pool, channel_id, secs).await {
+                        eprintln!("Adaptive slowmode error in channel {}: {}", channel_id, e);
+                    }
+                }
+            }
+        });
+    }
+
+    // Works out which channels need a new slowmode. They are only marked as changed
+    // once the edit goes through, a failed one is retried after the cooldown.
+    fn due_changes(&self) -> Vec<(serenity::ChannelId, u16)> {
+        let now = Instant::now();
+        let mut changes = Vec::new();
+        for (channel_id, state) in self.channels.lock().unwrap().iter_mut() {
+            while let Some(sent) = state.messages.front() {
+                if now.duration_since(*sent) > WINDOW {
+                    state.messages.pop_front();
+                } else {
+                    break;
+                }
+            }
+            if state
+                .last_change
+                .is_some_and(|changed| now.duration_since(changed) < COOLDOWN)
+            {
+                continue;
+            }
+            let rate = state.messages.len() as u32;
+            if let Some(next) = next_slowmode(state.current, rate, &state.settings) {
+                state.last_change = Some(now);
+                changes.push((*channel_id, next));
+            }
+        }
+        changes
+    }
+
+    async fn apply(
+        &self,
+        http: &serenity::Http,
+        pool: &Pool<Sqlite>,
+        channel_id: serenity::ChannelId,
+        secs: u16,
+    ) -> Result<(), Error> {
+        channel_id
+            .edit(
+                http,
+                serenity::EditChannel::new()
+                    .rate_limit_per_user(secs)
+                    .audit_log_reason("Adaptive slowmode"),
+            )
+            .await?;
+        if let Some(state) = self.channels.lock().unwrap().get_mut(&channel_id) {
+            state.current = secs;
+        }
+        let channel_id = channel_id.get() as i64;
+        let secs = secs as i64;
+        sqlx::query!(
+            "UPDATE auto_slowmode SET current_secs = ? WHERE channel_id = ?",
+            secs,
+            channel_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    fn guild_channels(
+        &self,
+        guild_id: serenity::GuildId,
+    ) -> Vec<(serenity::ChannelId, Settings, u16)> {
+        let mut channels: Vec<_> = self
+            .channels
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, state)| state.guild_id == guild_id)
+            .map(|(channel_id, state)| (*channel_id, state.settings, state.current))
+            .collect();
+        channels.sort_by_key(|(channel_id, _, _)| *channel_id);
+        channels
+    }
+}
+
+fn parse_bound(text: Option<String>, default: u16) -> Option<u16> {
+    match text {
+        Some(text) => moderation::parse_duration(&text)
+            .map(|d| d.as_secs())
+            .filter(|secs| *secs <= MAX_SLOWMODE_SECS)
+            .map(|secs| secs as u16),
+        None => Some(default),
+    }
+}
+
+/// Let the bot raise and lower a channel's slowmode with how busy it is.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_CHANNELS",
+    required_bot_permissions = "MANAGE_CHANNELS",
+    subcommands("enable", "disable", "list")
+)]
+pub async fn autoslowmode(ctx: Context<'_>) -> Result<(), Error> {
+    list_inner(ctx).await
+}
+
+/// Turn adaptive slowmode on for a channel.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn enable(
+    ctx: Context<'_>,
+    #[description = "Channel to watch (default: this one)"] channel: Option<serenity::GuildChannel>,
+    #[description = "Messages per minute to aim for (default: 30)"]
+    #[min = 1]
+    target: Option<u32>,
+    #[description = "Lowest slowmode (default: 0s)"] min: Option<String>,
+    #[description = "Highest slowmode (default: 30s)"] max: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let (Some(min_secs), Some(max_secs)) = (parse_bound(min, 0), parse_bound(max, 30)) else {
+        ctx.say("❌ Slowmode bounds must be between 0 seconds and 6 hours, e.g. `30s`.")
+            .await?;
+        return Ok(());
+    };
+    if min_secs >= max_secs {
+        ctx.say("❌ The highest slowmode has to be above the lowest.")
+            .await?;
+        return Ok(());
+    }
+    let target_rate = target.unwrap_or(30).max(1);
+    let channel_id = match channel {
+        Some(channel) if channel.guild_id == guild_id => channel.id,
+        Some(_) => {
+            ctx.say("❌ That isn't a channel in this server.").await?;
+            return Ok(());
+        }
+        None => ctx.channel_id(),
+    };
+
+    let data = ctx.data();
+    let channel_id_db = channel_id.get() as i64;
+    let guild_id_db = guild_id.get() as i64;
+    let min_db = min_secs as i64;
+    let max_db = max_secs as i64;
+    let target_db = target_rate as i64;
+    sqlx::query!(
+        "INSERT INTO auto_slowmode (channel_id, guild_id, min_secs, max_secs, target_rate, current_secs)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (channel_id) DO UPDATE SET
+            min_secs = excluded.min_secs,
+            max_secs = excluded.max_secs,
+            target_rate = excluded.target_rate",
+        channel_id_db,
+        guild_id_db,
+        min_db,
+        max_db,
+        target_db,
+        min_db
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.autoslowmode.insert(AutoSlowmodeRow {
+        channel_id: channel_id_db,
+        guild_id: guild_id_db,
+        min_secs: min_db,
+        max_secs: max_db,
+        target_rate: target_db,
+        current_secs: min_db,
+    });
+    data.autoslowmode
+        .apply(ctx.http(), &data.db_pool, channel_id, min_secs)
+        .await?;
+
+    ctx.say(format!(
+        "🐢 Adaptive slowmode is on in {}: between {} and {}, aiming for {} messages a minute.",
+        channel_id.mention(),
+        moderation::format_duration(min_secs as u64),
+        moderation::format_duration(max_secs as u64),
+        target_rate
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Turn adaptive slowmode off for a channel. Its slowmode goes back to the lowest bound.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn disable(
+    ctx: Context<'_>,
+    #[description = "Channel to stop watching (default: this one)"] channel: Option<
+        serenity::GuildChannel,
+    >,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let channel_id = channel.map_or(ctx.channel_id(), |channel| channel.id);
+    let data = ctx.data();
+    let channel_id_db = channel_id.get() as i64;
+    let guild_id_db = guild_id.get() as i64;
+    let Some(row) = sqlx::query!(
+        "DELETE FROM auto_slowmode WHERE channel_id = ? AND guild_id = ? RETURNING min_secs",
+        channel_id_db,
+        guild_id_db
+    )
+    .fetch_optional(&data.db_pool)
+    .await?
+    else {
+        ctx.say(format!(
+            "Adaptive slowmode isn't on in {}.",
+            channel_id.mention()
+        ))
+        .await?;
+        return Ok(());
+    };
+    data.autoslowmode.remove(channel_id);
+    channel_id
+        .edit(
+            ctx,
+            serenity::EditChannel::new().rate_limit_per_user(row.min_secs as u16),
+        )
+        .await?;
+
+    ctx.say(format!(
+        "Adaptive slowmode is off in {}, slowmode set to {}.",
+        channel_id.mention(),
+        moderation::format_duration(row.min_secs as u64)
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn list_inner(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let channels = ctx.data().autoslowmode.guild_channels(guild_id);
+    if channels.is_empty() {
+        ctx.say("No channels have adaptive slowmode, turn it on with `autoslowmode enable`.")
+            .await?;
+        return Ok(());
+    }
+    let list = channels
+        .iter()
+        .map(|(channel_id, settings, current)| {
+            format!(
+                "{}: {} now, {}–{}, aiming for {}/min",
+                channel_id.mention(),
+                moderation::format_duration(*current as u64),
+                moderation::format_duration(settings.min_secs as u64),
+                moderation::format_duration(settings.max_secs as u64),
+                settings.target_rate
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    ctx.send(
+        poise::CreateReply::default().embed(
+            serenity::CreateEmbed::new()
+                .title("Adaptive slowmode")
+                .description(list)
+                .color(serenity::Color::DARK_RED),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// List the channels with adaptive slowmode.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_inner(ctx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: Settings = Settings {
+        min_secs: 0,
+        max_secs: 30,
+        target_rate: 20,
+    };
+
+    #[test]
+    fn steps_up_when_busy() {
+        assert_eq!(next_slowmode(0, 40, &SETTINGS), Some(2));
+        assert_eq!(next_slowmode(15, 40, &SETTINGS), Some(30));
+        assert_eq!(next_slowmode(30, 40, &SETTINGS), None);
+    }
+
+    #[test]
+    fn steps_down_only_when_clearly_quiet() {
+        assert_eq!(next_slowmode(10, 15, &SETTINGS), None);
+        assert_eq!(next_slowmode(10, 9, &SETTINGS), Some(5));
+        assert_eq!(next_slowmode(0, 0, &SETTINGS), None);
+    }
+
+    #[test]
+    fn respects_bounds() {
+        let settings = Settings {
+            min_secs: 7,
+            max_secs: 45,
+            target_rate: 20,
+        };
+        assert_eq!(next_slowmode(30, 40, &settings), Some(45));
+        assert_eq!(next_slowmode(10, 0, &settings), Some(7));
+        assert_eq!(next_slowmode(120, 20, &settings), Some(45));
+    }
+}
@@ -0,0 +1,277 @@
+use crate::moderation;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+
+/// Discord's slowmode tops out at 6 hours.
+pub const MAX_SLOWMODE_SECS: u64 = 6 * 60 * 60;
+
+/// What @everyone loses in a locked channel.
+fn locked_permissions() -> serenity::Permissions {
+    serenity::Permissions::SEND_MESSAGES
+        | serenity::Permissions::SEND_MESSAGES_IN_THREADS
+        | serenity::Permissions::CREATE_PUBLIC_THREADS
+        | serenity::Permissions::CREATE_PRIVATE_THREADS
+}
+
+// Helper struct to map the query result
+struct ChannelLockRow {
+    had_overwrite: bool,
+    allow: i64,
+    deny: i64,
+}
+
+// The channel a command should act on: the one given, or the one it was run in.
+async fn target_channel(
+    ctx: Context<'_>,
+    channel: Option<serenity::GuildChannel>,
+) -> Result<Option<serenity::GuildChannel>, Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let channel = match channel {
+        Some(channel) => channel,
+        None => match ctx.channel_id().to_channel(ctx).await?.guild() {
+            Some(channel) => channel,
+            None => return Ok(None),
+        },
+    };
+    if channel.guild_id != guild_id {
+        return Ok(None);
+    }
+    Ok(Some(channel))
+}
+
+/// Stop @everyone from talking in a channel.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_CHANNELS",
+    required_bot_permissions = "MANAGE_ROLES"
+)]
+pub async fn lock(
+    ctx: Context<'_>,
+    #[description = "Channel to lock (default: this one)"] channel: Option<serenity::GuildChannel>,
+    #[description = "Why the channel is being locked"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let Some(channel) = target_channel(ctx, channel).await? else {
+        ctx.say("❌ That isn't a channel in this server.").await?;
+        return Ok(());
+    };
+    let everyone = channel.guild_id.everyone_role();
+    let channel_id_db = channel.id.get() as i64;
+    let guild_id_db = channel.guild_id.get() as i64;
+    let data = ctx.data();
+
+    let already_locked = sqlx::query!(
+        "SELECT channel_id FROM channel_locks WHERE channel_id = ?",
+        channel_id_db
+    )
+    .fetch_optional(&data.db_pool)
+    .await?
+    .is_some();
+    if already_locked {
+        ctx.say(format!("{} is already locked.", channel.mention()))
+            .await?;
+        return Ok(());
+    }
+
+    // Remember the overwrite exactly as it was, so unlocking doesn't have to guess.
+    let previous = channel
+        .permission_overwrites
+        .iter()
+        .find(|overwrite| overwrite.kind == serenity::PermissionOverwriteType::Role(everyone));
+    let had_overwrite = previous.is_some();
+    let allow = previous.map_or(serenity::Permissions::empty(), |o| o.allow);
+    let deny = previous.map_or(serenity::Permissions::empty(), |o| o.deny);
+    let allow_db = allow.bits() as i64;
+    let deny_db = deny.bits() as i64;
+    let locked_by = ctx.author().id.get() as i64;
+    let reason = reason.unwrap_or_default();
+    sqlx::query!(
+        "INSERT INTO channel_locks (channel_id, guild_id, had_overwrite, allow, deny, locked_by, reason)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        channel_id_db,
+        guild_id_db,
+        had_overwrite,
+        allow_db,
+        deny_db,
+        locked_by,
+        reason
+    )
+    .execute(&data.db_pool)
+    .await?;
+
+    let result = channel
+        .create_permission(
+            ctx,
+            serenity::PermissionOverwrite {
+                allow: allow - locked_permissions(),
+                deny: deny | locked_permissions(),
+                kind: serenity::PermissionOverwriteType::Role(everyone),
+            },
+        )
+        .await;
+    if let Err(e) = result {
+        // Nothing changed, so there's nothing for unlock to restore.
+        sqlx::query!(
+            "DELETE FROM channel_locks WHERE channel_id = ?",
+            channel_id_db
+        )
+        .execute(&data.db_pool)
+        .await?;
+        return Err(e.into());
+    }
+
+    let notice = if reason.is_empty() {
+        "🔒 This channel has been locked.".to_string()
+    } else {
+        format!("🔒 This channel has been locked: {}", reason)
+    };
+    if channel.id != ctx.channel_id() {
+        let _ = channel
+            .send_message(
+                ctx,
+                serenity::CreateMessage::new()
+                    .content(&notice)
+                    .allowed_mentions(serenity::CreateAllowedMentions::new()),
+            )
+            .await;
+        ctx.say(format!("🔒 Locked {}.", channel.mention())).await?;
+    } else {
+        ctx.send(
+            poise::CreateReply::default()
+                .content(notice)
+                .allowed_mentions(serenity::CreateAllowedMentions::new()),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Let @everyone talk in a locked channel again.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_CHANNELS",
+    required_bot_permissions = "MANAGE_ROLES"
+)]
+pub async fn unlock(
+    ctx: Context<'_>,
+    #[description = "Channel to unlock (default: this one)"] channel: Option<
+        serenity::GuildChannel,
+    >,
+) -> Result<(), Error> {
+    let Some(channel) = target_channel(ctx, channel).await? else {
+        ctx.say("❌ That isn't a channel in this server.").await?;
+        return Ok(());
+    };
+    let everyone = channel.guild_id.everyone_role();
+    let channel_id_db = channel.id.get() as i64;
+    let data = ctx.data();
+
+    let Some(lock) = sqlx::query_as!(
+        ChannelLockRow,
+        "SELECT had_overwrite, allow, deny FROM channel_locks WHERE channel_id = ?",
+        channel_id_db
+    )
+    .fetch_optional(&data.db_pool)
+    .await?
+    else {
+        ctx.say(format!(
+            "{} wasn't locked with `lock`, so there's nothing to restore.",
+            channel.mention()
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    if lock.had_overwrite {
+        channel
+            .create_permission(
+                ctx,
+                serenity::PermissionOverwrite {
+                    allow: serenity::Permissions::from_bits_retain(lock.allow as u64),
+                    deny: serenity::Permissions::from_bits_retain(lock.deny as u64),
+                    kind: serenity::PermissionOverwriteType::Role(everyone),
+                },
+            )
+            .await?;
+    } else {
+        channel
+            .delete_permission(ctx, serenity::PermissionOverwriteType::Role(everyone))
+            .await?;
+    }
+    sqlx::query!(
+        "DELETE FROM channel_locks WHERE channel_id = ?",
+        channel_id_db
+    )
+    .execute(&data.db_pool)
+    .await?;
+
+    if channel.id != ctx.channel_id() {
+        let _ = channel.say(ctx, "🔓 This channel has been unlocked.").await;
+        ctx.say(format!("🔓 Unlocked {}.", channel.mention()))
+            .await?;
+    } else {
+        ctx.say("🔓 This channel has been unlocked.").await?;
+    }
+    Ok(())
+}
+
+/// Set how long members have to wait between messages. Use 0 or `off` to turn it off.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_CHANNELS",
+    required_bot_permissions = "MANAGE_CHANNELS"
+)]
+pub async fn slowmode(
+    ctx: Context<'_>,
+    #[description = "Delay between messages (e.g. 10s, 2m), or off"] duration: String,
+    #[description = "Channel to change (default: this one)"] channel: Option<
+        serenity::GuildChannel,
+    >,
+) -> Result<(), Error> {
+    let secs = if duration.eq_ignore_ascii_case("off") {
+        Some(0)
+    } else {
+        moderation::parse_duration(&duration).map(|d| d.as_secs())
+    };
+    let Some(secs) = secs.filter(|secs| *secs <= MAX_SLOWMODE_SECS) else {
+        ctx.say("❌ Slowmode must be between 0 seconds and 6 hours, e.g. `10s`.")
+            .await?;
+        return Ok(());
+    };
+    let Some(channel) = target_channel(ctx, channel).await? else {
+        ctx.say("❌ That isn't a channel in this server.").await?;
+        return Ok(());
+    };
+
+    channel
+        .id
+        .edit(
+            ctx,
+            serenity::EditChannel::new()
+                .rate_limit_per_user(secs as u16)
+                .audit_log_reason(&format!("Slowmode set by {}", ctx.author().tag())),
+        )
+        .await?;
+
+    if secs == 0 {
+        ctx.say(format!("Slowmode is off in {}.", channel.mention()))
+            .await?;
+    } else {
+        ctx.say(format!(
+            "🐢 Slowmode in {} is now {}.",
+            channel.mention(),
+            moderation::format_duration(secs)
+        ))
+        .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use crate::Error;
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::{Pool, Sqlite};
+
+/// The migrations in `migrations/`, embedded at compile time. Databases remember the
+/// checksum of every migration they ran, so never edit a released one, add a new one.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+// These run against whatever state the database is in, so unlike the rest of the bot
+// they use unchecked queries instead of being checked against the final schema.
+async fn table_exists(pool: &Pool<Sqlite>, name: &str) -> Result<bool, Error> {
+    let count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(name)
+            .fetch_one(pool)
+            .await?;
+    Ok(count > 0)
+}
+
+/// Brings the database up to date with this build. Refuses to touch a database that a
+/// newer build has already migrated, since this one wouldn't know its schema.
+pub async fn migrate(pool: &Pool<Sqlite>) -> Result<(), Error> {
+    // Before migrations ran at startup the bot created its tables inline, and its
+    // guild_prefixes would collide with the migration that creates that table. Move it
+    // aside and copy the prefixes to where they live now once the migrations are done.
+    if !table_exists(pool, "_sqlx_migrations").await?
+        && table_exists(pool, "guild_prefixes").await?
+    {
+        sqlx::query("ALTER TABLE guild_prefixes RENAME TO guild_prefixes_legacy")
+            .execute(pool)
+            .await?;
+    }
+
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let applied = conn.list_applied_migrations().await?;
+    drop(conn);
+    if let Some(unknown) = applied.iter().find(|applied| {
+        !MIGRATOR
+            .iter()
+            .any(|known| known.version == applied.version)
+    }) {
+        return Err(format!(
+            "The database has migration {} applied, which this build doesn't have. \
+             It was migrated by a newer version of the bot, update before running it again.",
+            unknown.version
+        )
+        .into());
+    }
+    MIGRATOR.run(pool).await?;
+
+    // Checked separately so a copy interrupted by a crash is finished on the next start.
+    if table_exists(pool, "guild_prefixes_legacy").await? {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            "INSERT OR REPLACE INTO guild_settings (guild_id, key, value)
+             SELECT guild_id, 'prefixes', prefix FROM guild_prefixes_legacy",
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DROP TABLE guild_prefixes_legacy")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    // Every connection to :memory: is its own database, so keep it to one.
+    async fn memory_pool() -> Pool<Sqlite> {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn migrates_fresh_and_legacy_databases() {
+        let pool = memory_pool().await;
+        migrate(&pool).await.unwrap();
+        // Running again is a no-op.
+        migrate(&pool).await.unwrap();
+        assert!(table_exists(&pool, "guild_settings").await.unwrap());
+
+        // A database the old inline table creation built.
+        let pool = memory_pool().await;
+        for statement in [
+            "CREATE TABLE guild_prefixes (
+                guild_id INTEGER PRIMARY KEY,
+                prefix VARCHAR(10) NOT NULL DEFAULT 'td!'
+            )",
+            "INSERT INTO guild_prefixes (guild_id, prefix) VALUES (1, 'l!')",
+            "CREATE TABLE mod_cases (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                moderator_id INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                reason TEXT NOT NULL DEFAULT '',
+                created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+            "INSERT INTO mod_cases (guild_id, user_id, moderator_id, action) VALUES (1, 2, 3, 'ban')",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+        migrate(&pool).await.unwrap();
+        let prefix: String = sqlx::query_scalar(
+            "SELECT value FROM guild_settings WHERE guild_id = 1 AND key = 'prefixes'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(prefix, "l!");
+        let cases: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM mod_cases")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(cases, 1);
+        assert!(!table_exists(&pool, "guild_prefixes_legacy").await.unwrap());
+        assert!(!table_exists(&pool, "guild_prefixes").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn refuses_databases_from_newer_builds() {
+        let pool = memory_pool().await;
+        migrate(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+             VALUES (99991231000000, 'from the future', 1, x'00', 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let error = migrate(&pool).await.unwrap_err();
+        assert!(error.to_string().contains("99991231000000"), "{}", error);
+    }
+}
@@ -0,0 +1,639 @@
+use crate::moderation::Hierarchy;
+use crate::wordfilter::normalize;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use regex::{RegexSet, RegexSetBuilder};
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Discord's limit on nickname length, in characters.
+const MAX_NICK_CHARS: usize = 32;
+const MAX_WORDS: usize = 100;
+
+/// Why a member was renamed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenameReason {
+    Hoisting,
+    DisallowedWord(String),
+}
+
+impl std::fmt::Display for RenameReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenameReason::Hoisting => f.write_str("Name starts with hoisting characters"),
+            RenameReason::DisallowedWord(word) => write!(f, "Name contains `{}`", word),
+        }
+    }
+}
+
+/// A guild's nickname policy, as stored in `nickname_policy` and `nickname_words`.
+pub struct NicknamePolicy {
+    pub enabled: bool,
+    pub dehoist: bool,
+    pub replacement: String,
+    pub log_channel_id: Option<serenity::ChannelId>,
+    /// Disallowed words, in the same order as `set`.
+    pub words: Vec<String>,
+    set: RegexSet,
+}
+
+impl Default for NicknamePolicy {
+    fn default() -> Self {
+        NicknamePolicy {
+            enabled: false,
+            dehoist: true,
+            replacement: "Dehoisted".to_string(),
+            log_channel_id: None,
+            words: Vec::new(),
+            set: RegexSet::empty(),
+        }
+    }
+}
+
+// Helper struct to map the query result
+struct PolicyRow {
+    enabled: bool,
+    dehoist: bool,
+    replacement: String,
+    log_channel_id: Option<i64>,
+}
+
+/// Characters that push a name to the top of the member list without being part of it.
+fn is_hoisting(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '\u{200B}'..='\u{200F}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' | '\u{00AD}'
+        )
+        || (c.is_ascii_punctuation() && c < 'a')
+}
+
+impl NicknamePolicy {
+    /// The nickname `name` should be changed to under this policy, if any.
+    pub fn fix(&self, name: &str) -> Option<(String, RenameReason)> {
+        let normalized = normalize(name);
+        if let Some(i) = self.set.matches(&normalized).into_iter().next() {
+            let reason = RenameReason::DisallowedWord(self.words[i].clone());
+            return Some((self.replacement.clone(), reason));
+        }
+        if !self.dehoist {
+            return None;
+        }
+        let trimmed = name.trim_start_matches(is_hoisting);
+        if trimmed.len() == name.len() {
+            return None;
+        }
+        let new_name = if trimmed.is_empty() {
+            self.replacement.clone()
+        } else {
+            trimmed.to_string()
+        };
+        Some((new_name, RenameReason::Hoisting))
+    }
+}
+
+fn word_regex(word: &str) -> String {
+    format!(r"(?i)\b{}\b", regex::escape(&normalize(word)))
+}
+
+// A policy that dehoists and disallows `words`, for checking that a replacement name
+// wouldn't itself be renamed.
+fn strict(words: Vec<String>) -> Result<NicknamePolicy, Error> {
+    Ok(NicknamePolicy {
+        dehoist: true,
+        set: RegexSetBuilder::new(words.iter().map(|word| word_regex(word))).build()?,
+        words,
+        ..Default::default()
+    })
+}
+
+/// Nickname policy state shared between the event handler and the commands.
+#[derive(Default)]
+pub struct Dehoist {
+    guilds: RwLock<HashMap<serenity::GuildId, Arc<NicknamePolicy>>>,
+}
+
+impl Dehoist {
+    /// The guild's policy, loaded from the database when not cached.
+    pub async fn policy(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Arc<NicknamePolicy>, Error> {
+        if let Some(policy) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(policy.clone());
+        }
+        let policy = Arc::new(load_policy(pool, guild_id).await?);
+        self.guilds
+            .write()
+            .unwrap()
+            .insert(guild_id, policy.clone());
+        Ok(policy)
+    }
+
+    /// Drops the cached policy so the next member update reloads it.
+    pub fn invalidate(&self, guild_id: serenity::GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+}
+
+async fn load_policy(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<NicknamePolicy, Error> {
+    let guild_id = guild_id.get() as i64;
+    let row = sqlx::query_as!(
+        PolicyRow,
+        "SELECT enabled, dehoist, replacement, log_channel_id
+         FROM nickname_policy WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let words: Vec<String> = sqlx::query!(
+        "SELECT word FROM nickname_words WHERE guild_id = ? ORDER BY word",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.word)
+    .collect();
+    let set = RegexSetBuilder::new(words.iter().map(|word| word_regex(word))).build()?;
+
+    let mut policy = NicknamePolicy {
+        words,
+        set,
+        ..Default::default()
+    };
+    if let Some(row) = row {
+        policy.enabled = row.enabled;
+        policy.dehoist = row.dehoist;
+        policy.replacement = row.replacement;
+        policy.log_channel_id = row
+            .log_channel_id
+            .map(|id| serenity::ChannelId::new(id as u64));
+    }
+    Ok(policy)
+}
+
+/// Renames `member` if their display name breaks the policy, returning the new name.
+///
+/// Members the bot can't rename (the owner, anyone above its role) are left alone.
+async fn enforce(
+    ctx: &serenity::Context,
+    policy: &NicknamePolicy,
+    hierarchy: &Hierarchy,
+    member: &serenity::Member,
+    moderator: Option<&serenity::User>,
+) -> Result<Option<String>, Error> {
+    if member.user.bot {
+        return Ok(None);
+    }
+    let old_name = member.display_name().to_string();
+    let Some((new_name, reason)) = policy.fix(&old_name) else {
+        return Ok(None);
+    };
+    if hierarchy.check(ctx, member.user.id).await?.is_err() {
+        return Ok(None);
+    }
+
+    let audit_reason = match moderator {
+        Some(moderator) => format!("Dehoist by {}: {}", moderator.tag(), reason),
+        None => format!("Nickname policy: {}", reason),
+    };
+    member
+        .guild_id
+        .edit_member(
+            ctx,
+            member.user.id,
+            serenity::EditMember::new()
+                .nickname(&new_name)
+                .audit_log_reason(&audit_reason),
+        )
+        .await?;
+
+    if let Some(log_channel_id) = policy.log_channel_id {
+        let mut embed = serenity::CreateEmbed::new()
+            .title("✏️ Member renamed")
+            .description(format!("{} ({})", member.user.mention(), member.user.tag()))
+            .field("Before", old_name.replace('`', "'"), true)
+            .field("After", new_name.replace('`', "'"), true)
+            .field("Reason", reason.to_string(), false)
+            .footer(serenity::CreateEmbedFooter::new(format!(
+                "User ID: {}",
+                member.user.id
+            )))
+            .timestamp(serenity::Timestamp::now())
+            .color(serenity::Color::BLUE);
+        if let Some(moderator) = moderator {
+            embed = embed.field("Swept by", moderator.mention().to_string(), false);
+        }
+        log_channel_id
+            .send_message(ctx, serenity::CreateMessage::new().embed(embed))
+            .await?;
+    }
+    Ok(Some(new_name))
+}
+
+/// Checks a member who joined or changed their name against the guild's policy.
+pub async fn member_changed(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    dehoist: &Dehoist,
+    member: &serenity::Member,
+) -> Result<(), Error> {
+    if member.user.bot {
+        return Ok(());
+    }
+    let policy = dehoist.policy(pool, member.guild_id).await?;
+    if !policy.enabled || policy.fix(member.display_name()).is_none() {
+        return Ok(());
+    }
+    let bot_id = ctx.cache.current_user().id;
+    let hierarchy = Hierarchy::load(ctx, member.guild_id, bot_id).await?;
+    enforce(ctx, &policy, &hierarchy, member, None).await?;
+    Ok(())
+}
+
+/// Rename every member whose name breaks the nickname policy.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_NICKNAMES",
+    required_bot_permissions = "MANAGE_NICKNAMES"
+)]
+pub async fn dehoist(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    ctx.defer().await?;
+    let data = ctx.data();
+    let policy = data.dehoist.policy(&data.db_pool, guild_id).await?;
+    let bot_id = ctx.cache().current_user().id;
+    let hierarchy = Hierarchy::load(ctx.serenity_context(), guild_id, bot_id).await?;
+
+    let mut checked = 0;
+    let mut renamed = 0;
+    let mut failed = 0;
+    let mut after = None;
+    loop {
+        let members = guild_id.members(ctx, Some(1000), after).await?;
+        let Some(last) = members.last() else {
+            break;
+        };
+        after = Some(last.user.id);
+        for member in &members {
+            checked += 1;
+            match enforce(
+                ctx.serenity_context(),
+                &policy,
+                &hierarchy,
+                member,
+                Some(ctx.author()),
+            )
+            .await
+            {
+                Ok(Some(_)) => renamed += 1,
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Failed to dehoist {}: {}", member.user.id, e);
+                    failed += 1;
+                }
+            }
+        }
+        if members.len() < 1000 {
+            break;
+        }
+    }
+
+    let mut reply = format!("Checked {} members and renamed {}.", checked, renamed);
+    if failed > 0 {
+        reply.push_str(&format!(" {} couldn't be renamed.", failed));
+    }
+    ctx.say(reply).await?;
+    Ok(())
+}
+
+// Changes the guild's policy row, creating it with the defaults if needed.
+async fn update_config(ctx: Context<'_>, change: impl FnOnce(&mut PolicyRow)) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let policy = data.dehoist.policy(&data.db_pool, guild_id).await?;
+    let mut config = PolicyRow {
+        enabled: policy.enabled,
+        dehoist: policy.dehoist,
+        replacement: policy.replacement.clone(),
+        log_channel_id: policy.log_channel_id.map(|id| id.get() as i64),
+    };
+    change(&mut config);
+
+    let guild_id_db = guild_id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO nickname_policy (guild_id, enabled, dehoist, replacement, log_channel_id)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            dehoist = excluded.dehoist,
+            replacement = excluded.replacement,
+            log_channel_id = excluded.log_channel_id",
+        guild_id_db,
+        config.enabled,
+        config.dehoist,
+        config.replacement,
+        config.log_channel_id
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.dehoist.invalidate(guild_id);
+    Ok(())
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let policy = data.dehoist.policy(&data.db_pool, guild_id).await?;
+    let words = if policy.words.is_empty() {
+        "None".to_string()
+    } else {
+        policy
+            .words
+            .iter()
+            .map(|word| format!("`{}`", word.replace('`', "'")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let embed = serenity::CreateEmbed::new()
+        .title("Nickname policy")
+        .field(
+            "Status",
+            if policy.enabled {
+                "Enabled"
+            } else {
+                "Disabled, only `dehoist` sweeps rename"
+            },
+            true,
+        )
+        .field(
+            "Dehoisting",
+            if policy.dehoist { "On" } else { "Off" },
+            true,
+        )
+        .field("Replacement name", policy.replacement.clone(), true)
+        .field(
+            "Log",
+            policy
+                .log_channel_id
+                .map(|id| id.mention().to_string())
+                .unwrap_or_else(|| "Not set".to_string()),
+            true,
+        )
+        .field("Disallowed words", words, false)
+        .color(serenity::Color::BLUE);
+    let mut reply = poise::CreateReply::default().embed(embed);
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Configure how member names are cleaned up.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands(
+        "show",
+        "enable",
+        "disable",
+        "hoisting",
+        "replacement",
+        "addword",
+        "removeword",
+        "log"
+    )
+)]
+pub async fn nickpolicy(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the nickname policy.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Rename members automatically when they join or change their name.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn enable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = true).await?;
+    show_inner(ctx, "Nickname policy is now enforced automatically.").await
+}
+
+/// Stop renaming members automatically.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = false).await?;
+    show_inner(ctx, "Nickname policy is no longer enforced automatically.").await
+}
+
+/// Choose whether names starting with characters like `!` or `.` are cleaned up.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn hoisting(
+    ctx: Context<'_>,
+    #[description = "Strip hoisting characters from names"] enabled: bool,
+) -> Result<(), Error> {
+    update_config(ctx, |config| config.dehoist = enabled).await?;
+    show_inner(ctx, "Dehoisting updated.").await
+}
+
+/// Set the name given to members whose name can't be cleaned up.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn replacement(
+    ctx: Context<'_>,
+    #[description = "Replacement nickname"]
+    #[rest]
+    name: String,
+) -> Result<(), Error> {
+    let name = name.trim().to_string();
+    let chars = name.chars().count();
+    if chars == 0 || chars > MAX_NICK_CHARS {
+        ctx.say(format!(
+            "❌ The replacement must be between 1 and {} characters.",
+            MAX_NICK_CHARS
+        ))
+        .await?;
+        return Ok(());
+    }
+    // A replacement that breaks the policy itself would be renamed again forever.
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let policy = data.dehoist.policy(&data.db_pool, guild_id).await?;
+    if strict(policy.words.clone())?.fix(&name).is_some() {
+        ctx.say("❌ The replacement can't break the nickname policy itself.")
+            .await?;
+        return Ok(());
+    }
+    update_config(ctx, |config| config.replacement = name).await?;
+    show_inner(ctx, "Replacement name updated.").await
+}
+
+/// Disallow a word in member names.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn addword(
+    ctx: Context<'_>,
+    #[description = "Word to disallow"] word: String,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let word = word.trim().to_lowercase();
+    if word.is_empty() {
+        ctx.say("❌ Give me a word to disallow.").await?;
+        return Ok(());
+    }
+    let policy = data.dehoist.policy(&data.db_pool, guild_id).await?;
+    if policy.words.len() >= MAX_WORDS {
+        ctx.say(format!(
+            "❌ This server already has {} disallowed words, remove some first.",
+            MAX_WORDS
+        ))
+        .await?;
+        return Ok(());
+    }
+    let mut words = policy.words.clone();
+    words.push(word.clone());
+    if strict(words)?.fix(&policy.replacement).is_some() {
+        ctx.say("❌ That word is part of the replacement name, change it first.")
+            .await?;
+        return Ok(());
+    }
+
+    let guild_id_db = guild_id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO nickname_words (guild_id, word) VALUES (?, ?)
+         ON CONFLICT (guild_id, word) DO NOTHING",
+        guild_id_db,
+        word
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.dehoist.invalidate(guild_id);
+    show_inner(ctx, &format!("`{}` is now disallowed in names.", word)).await
+}
+
+/// Allow a word in member names again.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn removeword(
+    ctx: Context<'_>,
+    #[description = "Word to allow again"] word: String,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let word = word.trim().to_lowercase();
+    let guild_id_db = guild_id.get() as i64;
+    let result = sqlx::query!(
+        "DELETE FROM nickname_words WHERE guild_id = ? AND word = ?",
+        guild_id_db,
+        word
+    )
+    .execute(&data.db_pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        ctx.say(format!("❌ `{}` isn't a disallowed word.", word))
+            .await?;
+        return Ok(());
+    }
+    data.dehoist.invalidate(guild_id);
+    show_inner(ctx, &format!("`{}` is allowed in names again.", word)).await
+}
+
+/// Choose where renames are logged. Leave it out to stop logging.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn log(
+    ctx: Context<'_>,
+    #[description = "Channel for the rename log"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let channel_id = channel.map(|channel| channel.id.get() as i64);
+    update_config(ctx, |config| config.log_channel_id = channel_id).await?;
+    show_inner(ctx, "Log channel updated.").await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(words: &[&str]) -> NicknamePolicy {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        NicknamePolicy {
+            set: RegexSetBuilder::new(words.iter().map(|word| word_regex(word)))
+                .build()
+                .unwrap(),
+            words,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn strips_hoisting_characters() {
+        let policy = policy(&[]);
+        assert_eq!(
+            policy.fix("!!Alice"),
+            Some(("Alice".to_string(), RenameReason::Hoisting))
+        );
+        assert_eq!(
+            policy.fix("\u{200B}. bob"),
+            Some(("bob".to_string(), RenameReason::Hoisting))
+        );
+        assert_eq!(
+            policy.fix("!!!"),
+            Some(("Dehoisted".to_string(), RenameReason::Hoisting))
+        );
+        assert_eq!(policy.fix("Alice!"), None);
+        assert_eq!(policy.fix("~tilde"), None);
+    }
+
+    #[test]
+    fn replaces_disallowed_words() {
+        let policy = policy(&["badword"]);
+        assert_eq!(
+            policy.fix("xX B4DW0RD Xx"),
+            Some((
+                "Dehoisted".to_string(),
+                RenameReason::DisallowedWord("badword".to_string())
+            ))
+        );
+        assert_eq!(policy.fix("notbadwordy"), None);
+    }
+
+    #[test]
+    fn checks_replacements_the_way_names_are_matched() {
+        let replacement = NicknamePolicy::default().replacement;
+        assert!(
+            strict(vec!["d3hoisted".to_string()])
+                .unwrap()
+                .fix(&replacement)
+                .is_some()
+        );
+        assert!(
+            strict(vec!["hoist".to_string()])
+                .unwrap()
+                .fix(&replacement)
+                .is_none()
+        );
+        assert!(strict(Vec::new()).unwrap().fix("!Hoisted").is_some());
+    }
+}
@@ -0,0 +1,734 @@
+use crate::moderation;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use regex::Regex;
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+
+static INVITE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(?:discord(?:app)?\.com/invite|discord\.gg)\s*/\s*([a-z0-9-]{2,32})")
+        .unwrap()
+});
+static URL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\bhttps?://[^\s<>()\[\]"']+"#).unwrap());
+static MASKED_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\[([^\]]+)\]\(\s*<?(https?://[^\s)>]+)>?(?:\s+[^)]*)?\)").unwrap()
+});
+
+/// How ordinary (non-invite) links are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum LinkMode {
+    /// Links are allowed unless their domain is denied.
+    #[name = "denylist"]
+    Denylist,
+    /// Only links to allowed domains may be posted.
+    #[name = "allowlist"]
+    Allowlist,
+}
+
+impl LinkMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkMode::Denylist => "denylist",
+            LinkMode::Allowlist => "allowlist",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "allowlist" => LinkMode::Allowlist,
+            _ => LinkMode::Denylist,
+        }
+    }
+}
+
+/// Which rule a message broke.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkRule {
+    Invite(String),
+    DeniedDomain(String),
+    NotAllowed(String),
+    MaskedLink(String),
+}
+
+impl fmt::Display for LinkRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkRule::Invite(code) => write!(f, "invite to another server (`{}`)", code),
+            LinkRule::DeniedDomain(host) => write!(f, "denied domain (`{}`)", host),
+            LinkRule::NotAllowed(host) => write!(f, "domain not on the allowlist (`{}`)", host),
+            LinkRule::MaskedLink(host) => write!(f, "masked link (to `{}`)", host),
+        }
+    }
+}
+
+/// A guild's link filter settings, as stored in `link_filter_config` and friends.
+#[derive(Debug, Clone, Default)]
+pub struct LinkSettings {
+    pub invites: bool,
+    pub masked: bool,
+    /// `None` leaves ordinary links alone.
+    pub mode: Option<LinkMode>,
+    pub log_channel_id: Option<serenity::ChannelId>,
+    pub allowed: Vec<String>,
+    pub denied: Vec<String>,
+    pub exempt_roles: HashSet<serenity::RoleId>,
+}
+
+impl LinkSettings {
+    fn is_active(&self) -> bool {
+        self.invites || self.masked || self.mode.is_some()
+    }
+}
+
+// Helper struct to map the query result
+struct LinkConfigRow {
+    invites: bool,
+    masked: bool,
+    mode: String,
+    log_channel_id: Option<i64>,
+}
+
+// Helper struct to map the query result
+struct LinkDomainRow {
+    domain: String,
+    list: String,
+}
+
+/// The lowercased host of a URL, without credentials, port or trailing dot.
+pub fn host_of(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#', '\\']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?.trim_end_matches('.');
+    if host.is_empty() {
+        return None;
+    }
+    Some(host.to_lowercase())
+}
+
+/// Whether `host` is `domain` or one of its subdomains.
+pub fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+// Whether a masked link's label shows its target's host, rather than just mentioning
+// it somewhere like in the path of another URL.
+fn names_host(label: &str, host: &str) -> bool {
+    let bare = host.strip_prefix("www.").unwrap_or(host);
+    label
+        .split_whitespace()
+        .filter_map(host_of)
+        .any(|shown| domain_matches(&shown, host) || domain_matches(&shown, bare))
+}
+
+/// Checks everything that doesn't need Discord's API. Returns the first rule broken,
+/// and the invite codes that still have to be resolved.
+pub fn scan(content: &str, settings: &LinkSettings) -> (Option<LinkRule>, Vec<String>) {
+    if settings.masked {
+        for caps in MASKED_RE.captures_iter(content) {
+            let Some(host) = host_of(&caps[2]) else {
+                continue;
+            };
+            // A masked link that spells out where it goes isn't hiding anything.
+            if !names_host(&caps[1], &host) {
+                return (Some(LinkRule::MaskedLink(host)), Vec::new());
+            }
+        }
+    }
+
+    if let Some(mode) = settings.mode {
+        for url in URL_RE.find_iter(content) {
+            // Invites have their own rule.
+            if INVITE_RE.is_match(url.as_str()) {
+                continue;
+            }
+            let Some(host) = host_of(url.as_str()) else {
+                continue;
+            };
+            if let Some(domain) = settings
+                .denied
+                .iter()
+                .find(|domain| domain_matches(&host, domain))
+            {
+                return (Some(LinkRule::DeniedDomain(domain.clone())), Vec::new());
+            }
+            let allowed = settings
+                .allowed
+                .iter()
+                .any(|domain| domain_matches(&host, domain));
+            if mode == LinkMode::Allowlist && !allowed {
+                return (Some(LinkRule::NotAllowed(host)), Vec::new());
+            }
+        }
+    }
+
+    let invites = if settings.invites {
+        let mut codes: Vec<String> = INVITE_RE
+            .captures_iter(content)
+            .map(|caps| caps[1].to_string())
+            .collect();
+        codes.dedup();
+        codes
+    } else {
+        Vec::new()
+    };
+    (None, invites)
+}
+
+/// Link filter state shared between the event handler and the commands.
+#[derive(Default)]
+pub struct LinkFilter {
+    guilds: RwLock<HashMap<serenity::GuildId, Arc<LinkSettings>>>,
+    // Invite code to the guild it leads to, `None` for invites without a guild.
+    invites: Mutex<HashMap<String, Option<serenity::GuildId>>>,
+}
+
+impl LinkFilter {
+    /// The guild's settings, loaded from the database when not cached.
+    pub async fn settings(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Arc<LinkSettings>, Error> {
+        if let Some(settings) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(settings.clone());
+        }
+        let settings = Arc::new(load_settings(pool, guild_id).await?);
+        self.guilds
+            .write()
+            .unwrap()
+            .insert(guild_id, settings.clone());
+        Ok(settings)
+    }
+
+    /// Drops the cached settings so the next message reloads them.
+    pub fn invalidate(&self, guild_id: serenity::GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+
+    /// The guild an invite code leads to. Vanity and short codes resolve the same way.
+    async fn resolve_invite(&self, http: &serenity::Http, code: &str) -> Option<serenity::GuildId> {
+        if let Some(guild_id) = self.invites.lock().unwrap().get(code) {
+            return *guild_id;
+        }
+        // Don't remember failures, the API might just be having a bad moment.
+        let Ok(invite) = serenity::Invite::get(http, code, false, false, None).await else {
+            return None;
+        };
+        let guild_id = invite.guild.map(|guild| guild.id);
+        let mut invites = self.invites.lock().unwrap();
+        // Codes get reused once they expire, so don't remember them forever.
+        if invites.len() > 5000 {
+            invites.clear();
+        }
+        invites.insert(code.to_string(), guild_id);
+        guild_id
+    }
+}
+
+async fn load_settings(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<LinkSettings, Error> {
+    let guild_id = guild_id.get() as i64;
+    let config = sqlx::query_as!(
+        LinkConfigRow,
+        "SELECT invites, masked, mode, log_channel_id FROM link_filter_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let domains = sqlx::query_as!(
+        LinkDomainRow,
+        "SELECT domain, list FROM link_domains WHERE guild_id = ? ORDER BY domain",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let exempt_roles = sqlx::query!(
+        "SELECT role_id FROM link_exempt_roles WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut settings = LinkSettings::default();
+    if let Some(config) = config {
+        settings.invites = config.invites;
+        settings.masked = config.masked;
+        settings.mode = (config.mode != "off").then(|| LinkMode::from_db(&config.mode));
+        settings.log_channel_id = config
+            .log_channel_id
+            .map(|id| serenity::ChannelId::new(id as u64));
+    }
+    for domain in domains {
+        if domain.list == "allow" {
+            settings.allowed.push(domain.domain);
+        } else {
+            settings.denied.push(domain.domain);
+        }
+    }
+    settings.exempt_roles = exempt_roles
+        .into_iter()
+        .map(|row| serenity::RoleId::new(row.role_id as u64))
+        .collect();
+    Ok(settings)
+}
+
+/// Checks a message for forbidden links, returning `true` if it was deleted.
+pub async fn check_message(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    filter: &LinkFilter,
+    msg: &serenity::Message,
+) -> Result<bool, Error> {
+    let Some(guild_id) = msg.guild_id else {
+        return Ok(false);
+    };
+    if msg.author.bot || msg.webhook_id.is_some() || msg.content.is_empty() {
+        return Ok(false);
+    }
+    let settings = filter.settings(pool, guild_id).await?;
+    if !settings.is_active() {
+        return Ok(false);
+    }
+    let exempt_by_role = msg.member.as_ref().is_some_and(|member| {
+        member
+            .roles
+            .iter()
+            .any(|role| settings.exempt_roles.contains(role))
+    });
+    if exempt_by_role || moderation::is_moderator(ctx, guild_id, msg.channel_id, msg.author.id) {
+        return Ok(false);
+    }
+
+    let (mut rule, invites) = scan(&msg.content, &settings);
+    if rule.is_none() {
+        for code in invites {
+            // Invites back to this server are fine.
+            if filter.resolve_invite(&ctx.http, &code).await != Some(guild_id) {
+                rule = Some(LinkRule::Invite(code));
+                break;
+            }
+        }
+    }
+    let Some(rule) = rule else {
+        return Ok(false);
+    };
+
+    // The message might already be gone, that's fine.
+    let _ = msg.delete(ctx).await;
+    let _ = msg
+        .channel_id
+        .send_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .content(format!(
+                    "🔗 {}, that link isn't allowed here.",
+                    msg.author.mention()
+                ))
+                .allowed_mentions(serenity::CreateAllowedMentions::new().users([msg.author.id])),
+        )
+        .await;
+
+    if let Some(log_channel_id) = settings.log_channel_id {
+        let mut content = msg.content.clone();
+        if content.chars().count() > 1000 {
+            content = content.chars().take(1000).collect::<String>() + "…";
+        }
+        log_channel_id
+            .send_message(
+                ctx,
+                serenity::CreateMessage::new().embed(
+                    serenity::CreateEmbed::new()
+                        .title("Link filter")
+                        .field(
+                            "Author",
+                            format!("{} ({})", msg.author.mention(), msg.author.id),
+                            true,
+                        )
+                        .field("Channel", msg.channel_id.mention().to_string(), true)
+                        .field("Rule", moderation::shorten(&rule.to_string(), 1024), false)
+                        .field("Message", content, false)
+                        .timestamp(msg.timestamp)
+                        .color(serenity::Color::DARK_RED),
+                ),
+            )
+            .await?;
+    }
+    Ok(true)
+}
+
+// Loads the current config, applies `change`, and saves it back.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut LinkSettings),
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let mut settings = (*data.link_filter.settings(&data.db_pool, guild_id).await?).clone();
+    change(&mut settings);
+
+    let guild_id_db = guild_id.get() as i64;
+    let mode = settings.mode.map_or("off", LinkMode::as_str);
+    let log_channel_id = settings.log_channel_id.map(|id| id.get() as i64);
+    sqlx::query!(
+        "INSERT INTO link_filter_config (guild_id, invites, masked, mode, log_channel_id)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            invites = excluded.invites,
+            masked = excluded.masked,
+            mode = excluded.mode,
+            log_channel_id = excluded.log_channel_id",
+        guild_id_db,
+        settings.invites,
+        settings.masked,
+        mode,
+        log_channel_id
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.link_filter.invalidate(guild_id);
+    Ok(())
+}
+
+fn describe(settings: &LinkSettings) -> serenity::CreateEmbed {
+    let on_off = |on: bool| if on { "Blocked" } else { "Allowed" };
+    let list = |domains: &[String]| {
+        if domains.is_empty() {
+            "None".to_string()
+        } else {
+            domains.join(", ")
+        }
+    };
+    let exempt = if settings.exempt_roles.is_empty() {
+        "None".to_string()
+    } else {
+        settings
+            .exempt_roles
+            .iter()
+            .map(|role| role.mention().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    serenity::CreateEmbed::new()
+        .title("Link filter")
+        .field("Invites to other servers", on_off(settings.invites), true)
+        .field("Masked links", on_off(settings.masked), true)
+        .field(
+            "Other links",
+            match settings.mode {
+                None => "Not filtered",
+                Some(LinkMode::Denylist) => "Denied domains are blocked",
+                Some(LinkMode::Allowlist) => "Only allowed domains",
+            },
+            true,
+        )
+        .field("Allowed domains", list(&settings.allowed), false)
+        .field("Denied domains", list(&settings.denied), false)
+        .field("Exempt roles", exempt, false)
+        .field(
+            "Log channel",
+            settings
+                .log_channel_id
+                .map(|id| id.mention().to_string())
+                .unwrap_or_else(|| "Not set".to_string()),
+            false,
+        )
+        .color(serenity::Color::DARK_RED)
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let settings = data.link_filter.settings(&data.db_pool, guild_id).await?;
+    let mut reply = poise::CreateReply::default().embed(describe(&settings));
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Filter invites, masked links and links to unwanted domains.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands(
+        "show", "invites", "masked", "mode", "allow", "deny", "forget", "exempt", "unexempt", "log"
+    )
+)]
+pub async fn linkfilter(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the link filter settings.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Block or allow invites to other servers.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn invites(
+    ctx: Context<'_>,
+    #[description = "Block invites to other servers?"] block: bool,
+) -> Result<(), Error> {
+    update_config(ctx, |settings| settings.invites = block).await?;
+    show_inner(ctx, "Invite filter updated.").await
+}
+
+/// Block or allow masked links whose text hides where they go.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn masked(
+    ctx: Context<'_>,
+    #[description = "Block masked links?"] block: bool,
+) -> Result<(), Error> {
+    update_config(ctx, |settings| settings.masked = block).await?;
+    show_inner(ctx, "Masked link filter updated.").await
+}
+
+/// Choose how other links are filtered. Leave it out to stop filtering them.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn mode(
+    ctx: Context<'_>,
+    #[description = "denylist blocks denied domains, allowlist only lets allowed ones through"]
+    mode: Option<LinkMode>,
+) -> Result<(), Error> {
+    update_config(ctx, |settings| settings.mode = mode).await?;
+    show_inner(ctx, "Link mode updated.").await
+}
+
+// Cleans up a domain typed by a moderator, accepting full URLs too.
+fn normalize_domain(text: &str) -> Option<String> {
+    let host = host_of(text.trim())?;
+    let host = host.strip_prefix("www.").unwrap_or(&host).to_string();
+    (host.contains('.') && !host.contains(char::is_whitespace)).then_some(host)
+}
+
+async fn set_domain(ctx: Context<'_>, domain: &str, list: Option<&str>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let Some(domain) = normalize_domain(domain) else {
+        ctx.say("❌ That doesn't look like a domain, e.g. `example.com`.")
+            .await?;
+        return Ok(());
+    };
+    let data = ctx.data();
+    let guild_id_db = guild_id.get() as i64;
+    let message = match list {
+        Some(list) => {
+            sqlx::query!(
+                "INSERT INTO link_domains (guild_id, domain, list) VALUES (?, ?, ?)
+                 ON CONFLICT (guild_id, domain) DO UPDATE SET list = excluded.list",
+                guild_id_db,
+                domain,
+                list
+            )
+            .execute(&data.db_pool)
+            .await?;
+            format!("`{}` is now on the {}list.", domain, list)
+        }
+        None => {
+            let removed = sqlx::query!(
+                "DELETE FROM link_domains WHERE guild_id = ? AND domain = ?",
+                guild_id_db,
+                domain
+            )
+            .execute(&data.db_pool)
+            .await?
+            .rows_affected();
+            if removed == 0 {
+                format!("`{}` isn't on either list.", domain)
+            } else {
+                format!("`{}` was taken off the list.", domain)
+            }
+        }
+    };
+    data.link_filter.invalidate(guild_id);
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// Allow links to a domain and its subdomains.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn allow(
+    ctx: Context<'_>,
+    #[description = "Domain to allow, e.g. youtube.com"] domain: String,
+) -> Result<(), Error> {
+    set_domain(ctx, &domain, Some("allow")).await
+}
+
+/// Block links to a domain and its subdomains.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn deny(
+    ctx: Context<'_>,
+    #[description = "Domain to block, e.g. grabify.link"] domain: String,
+) -> Result<(), Error> {
+    set_domain(ctx, &domain, Some("deny")).await
+}
+
+/// Take a domain off the allow or deny list.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn forget(
+    ctx: Context<'_>,
+    #[description = "Domain to remove"] domain: String,
+) -> Result<(), Error> {
+    set_domain(ctx, &domain, None).await
+}
+
+/// Let a role post any link.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn exempt(
+    ctx: Context<'_>,
+    #[description = "Role to exempt"] role: serenity::Role,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id_db = role.guild_id.get() as i64;
+    let role_id = role.id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO link_exempt_roles (guild_id, role_id) VALUES (?, ?)
+         ON CONFLICT (guild_id, role_id) DO NOTHING",
+        guild_id_db,
+        role_id
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.link_filter.invalidate(role.guild_id);
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!("{} can now post any link.", role.mention()))
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Filter a previously exempt role's links again.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn unexempt(
+    ctx: Context<'_>,
+    #[description = "Role to filter again"] role: serenity::Role,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id_db = role.guild_id.get() as i64;
+    let role_id = role.id.get() as i64;
+    sqlx::query!(
+        "DELETE FROM link_exempt_roles WHERE guild_id = ? AND role_id = ?",
+        guild_id_db,
+        role_id
+    )
+    .execute(&data.db_pool)
+    .await?;
+    data.link_filter.invalidate(role.guild_id);
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!(
+                "{}'s links will be filtered again.",
+                role.mention()
+            ))
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Choose where deleted links are logged. Leave it out to stop logging.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn log(
+    ctx: Context<'_>,
+    #[description = "Channel for the link filter log"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let channel_id = channel.map(|channel| channel.id);
+    update_config(ctx, |settings| settings.log_channel_id = channel_id).await?;
+    show_inner(ctx, "Log channel updated.").await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> LinkSettings {
+        LinkSettings {
+            invites: true,
+            masked: true,
+            mode: Some(LinkMode::Denylist),
+            denied: vec!["grabify.link".to_string()],
+            allowed: vec!["youtube.com".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn extracts_hosts() {
+        assert_eq!(
+            host_of("https://User@WWW.Example.com:8080/x?y"),
+            Some("www.example.com".into())
+        );
+        assert_eq!(host_of("http://example.com."), Some("example.com".into()));
+        assert!(domain_matches("m.youtube.com", "youtube.com"));
+        assert!(!domain_matches("notyoutube.com", "youtube.com"));
+    }
+
+    #[test]
+    fn finds_invites_in_every_form() {
+        let (rule, invites) = scan(
+            "join discord.gg/abc and https://discord.com/invite/Vanity or discordapp.com/invite/xyz",
+            &settings(),
+        );
+        assert_eq!(rule, None);
+        assert_eq!(invites, vec!["abc", "Vanity", "xyz"]);
+    }
+
+    #[test]
+    fn catches_denied_and_unlisted_domains() {
+        let (rule, _) = scan("look https://sub.grabify.link/abc", &settings());
+        assert_eq!(rule, Some(LinkRule::DeniedDomain("grabify.link".into())));
+
+        let allowlist = LinkSettings {
+            mode: Some(LinkMode::Allowlist),
+            ..settings()
+        };
+        assert_eq!(scan("https://youtube.com/watch", &allowlist).0, None);
+        assert_eq!(
+            scan("https://example.org", &allowlist).0,
+            Some(LinkRule::NotAllowed("example.org".into()))
+        );
+    }
+
+    #[test]
+    fn catches_masked_links_that_hide_their_target() {
+        let (rule, _) = scan("[free nitro](https://evil.example/x)", &settings());
+        assert_eq!(rule, Some(LinkRule::MaskedLink("evil.example".into())));
+        let (rule, _) = scan("[youtube.com video](https://youtube.com/x)", &settings());
+        assert_eq!(rule, None);
+        let (rule, _) = scan("[google.com](https://www.google.com/x)", &settings());
+        assert_eq!(rule, None);
+    }
+
+    #[test]
+    fn masked_links_must_show_the_real_host() {
+        let (rule, _) = scan(
+            "[https://google.com/evil.com](https://evil.com)",
+            &settings(),
+        );
+        assert_eq!(rule, Some(LinkRule::MaskedLink("evil.com".into())));
+        let (rule, _) = scan("[notevil.com](https://evil.com)", &settings());
+        assert_eq!(rule, Some(LinkRule::MaskedLink("evil.com".into())));
+        let (rule, _) = scan("[https://cdn.evil.com/x](https://evil.com)", &settings());
+        assert_eq!(rule, None);
+    }
+}
@@ -7,140 +7,88 @@ use poise::serenity_prelude::{
 };
 use sqlx::Pool;
 use sqlx::sqlite::SqlitePool;
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, sync::Arc};
+
+mod altdetect;
+mod antinuke;
+mod antiraid;
+mod appeals;
+mod attachments;
+mod automod;
+mod autoslowmode;
+mod channels;
+mod db;
+mod dehoist;
+mod linkfilter;
+mod moderation;
+mod modmail;
+mod notes;
+mod prefixes;
+mod purge;
+mod reports;
+mod settings;
+mod verification;
+mod wordfilter;
 
 struct Data {
     pub db_pool: Pool<sqlx::Sqlite>,
     pub start_time: std::time::Instant,
+    pub automod: Arc<automod::Automod>,
+    pub word_filter: Arc<wordfilter::WordFilter>,
+    pub antiraid: Arc<antiraid::AntiRaid>,
+    pub autoslowmode: Arc<autoslowmode::AutoSlowmode>,
+    pub link_filter: Arc<linkfilter::LinkFilter>,
+    pub attachment_filter: Arc<attachments::AttachmentFilter>,
+    pub altdetect: Arc<altdetect::AltDetect>,
+    pub antinuke: Arc<antinuke::AntiNuke>,
+    pub dehoist: Arc<dehoist::Dehoist>,
+    pub settings: settings::GuildSettings,
+    pub dm_prefixes: Arc<prefixes::DmPrefixCache>,
 } // User data, which is stored and accessible in all command invocations
 const SHARDS: u32 = 32;
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
-// This is the function that Poise will call to get the prefix for a guild
-async fn dynamic_prefix_resolver(
-    ctx: poise::PartialContext<'_, Data, Error>,
-) -> Result<Option<String>, Error> {
-    // Get the guild ID from the context. If not in a guild (e.g., a DM),
-    // return None to signify using the default prefix.
-    let guild_id = match ctx.guild_id {
-        Some(id) => id,
-        _none => return Ok(None), // Use default prefix in DMs
-    };
-
-    // Gotta make it first dawg
-    // Query the database for the prefix associated with this guild ID
-    // We use query_as! for type-safe fetching.
-    // SQLite uses '?' for placeholders
-
-    let guild_id = guild_id.get() as i64;
-    let prefix_row = sqlx::query_as!(
-        PrefixRow,
-        "SELECT prefix FROM guild_prefixes WHERE guild_id = ?", // Use ? for placeholder
-        guild_id // Cast Discord's Uid to i64 for database storage
-    )
-    .fetch_optional(&ctx.data.db_pool) // fetch_optional returns Option<Row>
-    .await
-    .map_err(|e| {
-        // Log the error but don't crash the bot.
-        // In a real bot, you might want more sophisticated error handling.
-        eprintln!(
-            "Database error fetching prefix for guild {}: {}",
-            guild_id, e
-        );
-        // Return the error to the framework to be handled by the on_error hook
-        e
-    })?;
-
-    // If a prefix was found, return it. Otherwise, return None to use the default.
-    Ok(prefix_row.map(|row| row.prefix))
-}
-
-// Helper struct to map the query result
-struct PrefixRow {
-    prefix: String,
-}
-
-/// Sets the command prefix forguild.
+/// Sets the command prefix for this guild. Same as `prefix set`.
 /// Requires Administrator permissions.
 #[poise::command(guild_only, prefix_command, required_permissions = "ADMINISTRATOR")] // This command can only be used in a guild
 async fn writepre(
     ctx: Context<'_>,
     #[description = "The new prefix to use (max 10 characters)"] new_prefix: String,
 ) -> Result<(), Error> {
-    // Ensure the command is run in a guild (guild_only attribute already helps, but good practice)
     let guild_id = ctx
         .guild_id()
         .ok_or("This command can only be used in a guild.")?;
-
-    // Check if the user has Administrator permissions
-    // Basic validation for the new prefix length
-    if new_prefix.len() > 10 {
-        return Err("The prefix cannot be longer than 10 characters.".into());
-    }
-
-    // Use INSERT ... ON CONFLICT syntax for SQLite (requires SQLite 3.24.0+)
-    // Or you could use INSERT OR REPLACE INTO ... for simpler cases
-    let guild_id = guild_id.get() as i64;
-    sqlx::query!(
-        "INSERT INTO guild_prefixes (guild_id, prefix) VALUES (?, ?)
-         ON CONFLICT (guild_id) DO UPDATE SET prefix = excluded.prefix", // Use ? for placeholders
-        guild_id, // Cast Discord Uid to i64
-        new_prefix
-    )
-    .execute(&ctx.data().db_pool)
-    .await?; // Execute the query
+    let new_prefixes = prefixes::parse_list(&new_prefix)?;
+    prefixes::set_guild(ctx, guild_id, &new_prefixes).await?;
 
     // Respond to the user confirming the prefix change
     ctx.say(format!(
-        "Command prefix for this guild has been set to `{}`.",
-        new_prefix
+        "Command prefix for this guild has been set to {}.",
+        prefixes::format_list(&new_prefixes)
     ))
     .await?;
 
     Ok(())
 }
-/// Sets the command prefix forguild.
+/// Shows the command prefixes for this guild. Same as `prefix show`.
 /// Requires Administrator permissions.
 #[poise::command(guild_only, prefix_command, required_permissions = "ADMINISTRATOR")] // This command can only be used in a guild
 async fn readpre(ctx: Context<'_>) -> Result<(), Error> {
-    // Ensure the command is run in a guild (guild_only attribute already helps, but good practice)
     let guild_id = ctx
         .guild_id()
         .ok_or("This command can only be used in a guild.")?;
-
-    let guild_id = guild_id.get() as i64;
-    let prefix_row = sqlx::query_as!(
-        PrefixRow,
-        "SELECT prefix FROM guild_prefixes WHERE guild_id = ?", // Use ? for placeholder
-        guild_id // Cast Discord's Uid to i64 for database storage
-    )
-    .fetch_optional(&ctx.data().db_pool) // fetch_optional returns Option<Row>
-    .await
-    .map_err(|e| {
-        // Log the error but don't crash the bot.
-        // In a real bot, you might want more sophisticated error handling.
-        eprintln!(
-            "Database error fetching prefix for guild {}: {}",
-            guild_id, e
-        );
-        // Return the error to the framework to be handled by the on_error hook
-        e
-    })?;
-
-    // If a prefix was found, return it. Otherwise, return None to use the default.
-    let new_prefix = prefix_row.map(|r| r.prefix);
-    // Respond to the user confirming the prefix change
+    let prefixes = prefixes::guild(ctx.data(), guild_id).await?;
     ctx.say(format!(
-        "Command prefix for this guild has been set to `{:#?}`.",
-        new_prefix.unwrap()
+        "Command prefixes for this guild: {}",
+        prefixes::format_list(&prefixes)
     ))
     .await?;
 
     Ok(())
 }
 mod commands {
-    use ::serenity::all::{CreateAllowedMentions, GetMessages};
+    use ::serenity::all::CreateAllowedMentions;
     use poise::CreateReply;
 
     use super::*;
@@ -252,13 +200,8 @@ mod commands {
         Ok(())
     }
 
-    /// Show help menu with all available commands
-    #[poise::command(slash_command, prefix_command)]
-    pub async fn help(ctx: Context<'_>) -> Result<(), Error> {
-        let prefix: &str = "td!";
-        let ctx_id = ctx.id();
-        let prev_button_id = format!("{}prev", ctx_id);
-        let next_button_id = format!("{}next", ctx_id);
+    /// The pages of the help menu. Discord allows 25 fields and 6000 characters per embed.
+    pub fn help_pages(prefix: &str) -> [serenity::CreateEmbed; 4] {
         let embed1 = serenity::CreateEmbed::new()
             .title("Bot Commands Help")
             .description(format!(
@@ -292,13 +235,23 @@ mod commands {
                 false,
             )
             .field(
-                format!("{prefix}ban <user> <reason>"),
-                "Ban a user with the specified reason.",
+                format!("{prefix}ban <user> [days] <reason>"),
+                "Ban a user with the specified reason, optionally deleting up to 7 days of their messages.",
+                false,
+            )
+            .field(
+                format!("{prefix}massban <ids> [file] [days] [reason] [preview]"),
+                "Ban a list of user IDs at once, pasted or attached as a text file. Add `preview` to confirm first.",
                 false,
             )
             .field(
-                format!("{prefix}unban <user> <reason>"),
-                "Unban a user with the specified reason.",
+                format!("{prefix}unban <user id or name>"),
+                "Unban a user after showing their original ban reason.",
+                false,
+            )
+            .field(
+                format!("{prefix}bans [search]"),
+                "List the server's current bans.",
                 false,
             )
             .field(
@@ -306,7 +259,174 @@ mod commands {
                 "Kick a user with the specified reason.",
                 false,
             )
+            .field(
+                format!("{prefix}softban <user> [days] [reason]"),
+                "Ban and immediately unban a user to clear their recent messages.",
+                false,
+            )
+            .field(
+                "/note [add|delete] / /notes <user>",
+                "Keep private notes about members that only moderators can see. (Timeout Members)",
+                false,
+            )
+            .field(
+                "/history <user>",
+                "Show a member's moderation cases and notes. (Timeout Members)",
+                false,
+            )
+            .field(
+                "/report <user> <reason>",
+                "Report a member to the moderators. You can also right-click a message and use Apps → Report message.",
+                false,
+            )
+            .field(
+                format!("{prefix}reports [show|channel|timeout|open]"),
+                "Choose where reports go and see the ones still open. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}modmail [show|setup|transcripts|disable]"),
+                "Let members DM me to open a private ticket thread with the moderators. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}reply / {prefix}areply / {prefix}close"),
+                "Answer a modmail ticket with your name or anonymously, or close it and save a transcript. (Manage Threads)",
+                false,
+            )
+            .field(
+                format!("{prefix}appeals [show|enable|disable]"),
+                "DM banned members a button to appeal, and accept or deny appeals from a channel. (Manage Server)",
+                false,
+            )
             .color(serenity::Color::DARK_RED);
+        let embed3 = serenity::CreateEmbed::new()
+            .title("Bot Commands Help")
+            .description(format!(
+                "Use `{}` before commands or `/` for slash commands\n\
+                [Support Server](https://discord.gg/D3WEJ46QrQ)",
+                prefix
+            ))
+            .field(
+                "# PROTECTION COMMANDS",
+                "Commands that keep raids, spam and unwanted content out of the server.",
+                false,
+            )
+            .field(
+                format!("{prefix}verification [show|enable|disable|roles|challenge|kickafter|panel]"),
+                "Hold new members in an unverified role until they press a verify button, optionally answering a sum, and kick them if they take too long. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}automod [show|enable|disable|thresholds|action]"),
+                "Configure flood, duplicate message and mass mention detection. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}filter [add|remove|list|exempt|unexempt]"),
+                "Block words or regex patterns, with a per-rule action and exempt roles or channels. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}linkfilter [show|invites|masked|mode|allow|deny|forget|exempt|unexempt|log]"),
+                "Delete invites to other servers, masked links and links to unwanted domains. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}attachments [block|allow|list|action] <kind> [channel]"),
+                "Block kinds of file per channel, checked by their contents rather than their names. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}antiraid [show|enable|disable|trigger|newaccounts|slowmode|alerts]"),
+                "Lock the server down automatically when too many members join at once. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}altdetect [show|enable|disable|alerts|age|avatar|timeout|addpattern|removepattern]"),
+                "Flag young accounts, default avatars and raider names on join, with kick/ban/timeout buttons. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}antinuke [show|enable|disable|limits|trust|untrust]"),
+                "Strip the roles of anyone mass-deleting channels or roles, mass-banning or creating webhooks, and alert the owner. (Server Owner)",
+                false,
+            )
+            .field(
+                format!("{prefix}nickpolicy [show|enable|disable|hoisting|replacement|addword|removeword|log]"),
+                "Rename members whose names start with characters like `!` or contain disallowed words. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}dehoist"),
+                "Apply the nickname policy to every member now. (Manage Nicknames)",
+                false,
+            )
+            .color(serenity::Color::DARK_RED);
+        let embed4 = serenity::CreateEmbed::new()
+            .title("Bot Commands Help")
+            .description(format!(
+                "Use `{}` before commands or `/` for slash commands\n\
+                [Support Server](https://discord.gg/D3WEJ46QrQ)",
+                prefix
+            ))
+            .field(
+                "# CHANNEL & SERVER COMMANDS",
+                "Commands that manage channels and this server's settings.",
+                false,
+            )
+            .field(
+                format!("{prefix}config [get|set|reset|list] [key] [value]"),
+                "View and change this server's settings, like the command prefix. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}prefix [show|set|add|remove|reset] [prefixes]"),
+                "Show or change the command prefixes. A server can have several (Manage Server), and in DMs you can set your own.",
+                false,
+            )
+            .field(
+                format!("{prefix}lock [channel] [reason]"),
+                "Stop @everyone from sending messages in a channel. (Manage Channels)",
+                false,
+            )
+            .field(
+                format!("{prefix}unlock [channel]"),
+                "Restore a locked channel's permissions exactly as they were. (Manage Channels)",
+                false,
+            )
+            .field(
+                format!("{prefix}slowmode <duration|off> [channel]"),
+                "Set a channel's slowmode, up to 6 hours. (Manage Channels)",
+                false,
+            )
+            .field(
+                format!("{prefix}autoslowmode [enable|disable|list] [channel] [target] [min] [max]"),
+                "Raise and lower a channel's slowmode automatically with how busy it is. (Manage Channels)",
+                false,
+            )
+            .field(
+                format!("{prefix}lockdown [on|off|status] [reason]"),
+                "Raise verification and slow every channel down during a raid, then restore it all. (Manage Server)",
+                false,
+            )
+            .field(
+                format!("{prefix}purge <amount> [user] [bots|attachments|links|embeds] [filters] [preview]"),
+                "Delete up to 5000 messages, filtered by user, bots, attachments, links, embeds, text, regex or age. Add `preview` to confirm first.",
+                false,
+            )
+            .color(serenity::Color::DARK_RED);
+        [embed1, embed2, embed3, embed4]
+    }
+
+    /// Show help menu with all available commands
+    #[poise::command(slash_command, prefix_command)]
+    pub async fn help(ctx: Context<'_>) -> Result<(), Error> {
+        let prefix: &str = crate::prefixes::DEFAULT;
+        let ctx_id = ctx.id();
+        let prev_button_id = format!("{}prev", ctx_id);
+        let next_button_id = format!("{}next", ctx_id);
+        let pages = help_pages(prefix);
         let reply = {
             let components = serenity::CreateActionRow::Buttons(vec![
                 serenity::CreateButton::new(&prev_button_id).emoji('◀'),
@@ -314,12 +434,11 @@ mod commands {
             ]);
 
             poise::CreateReply::default()
-                .embed(embed1.clone())
+                .embed(pages[0].clone())
                 .ephemeral(true)
                 .components(vec![components])
         };
 
-        let pages: &[serenity::CreateEmbed] = &[embed1.clone(), embed2.clone()];
         ctx.send(reply).await?;
 
         // Loop through incoming interactions with the navigation buttons
@@ -416,10 +535,14 @@ mod commands {
             "Pong!\n\
             • Shard ID: {}\n\
             • API latency: {} ms\n\
-            • Uptime: {}",
+            • Uptime: {}\n\
+            • Settings cache: {}\n\
+            • DM prefix cache: {}",
             shard,
             api_latency.as_millis(),
-            format_durationu(uptime)
+            format_durationu(uptime),
+            ctx.data().settings.stats(),
+            ctx.data().dm_prefixes.stats()
         );
 
         // 5. Edit the original reply with result.
@@ -709,31 +832,415 @@ mod commands {
     pub async fn ban(
         ctx: Context<'_>,
         #[description = "User to ban"] user: serenity::User,
+        #[description = "Days of their messages to delete (0-7)"]
+        #[min = 0]
+        #[max = 7]
+        delete_days: Option<u8>,
         #[description = "Reason for ban"] reason: Option<String>,
     ) -> Result<(), Error> {
         let guild_id = ctx.guild_id().expect("Must be used in guild");
         let reason = reason.unwrap_or_else(|| "No reason provided".to_string());
+        let delete_days = delete_days.unwrap_or(0);
 
-        guild_id
-            .ban_with_reason(&ctx.serenity_context(), user.id, 0, &reason)
+        // Slash commands enforce the range, prefix commands don't.
+        if delete_days > MAX_DELETE_DAYS {
+            ctx.say("❌ You can only delete up to 7 days of messages.")
+                .await?;
+            return Ok(());
+        }
+
+        if !moderation::ensure_actionable(ctx, user.id).await? {
+            return Ok(());
+        }
+
+        // Once banned they may share no server with us, so the DM has to go out first.
+        let appeal = appeals::offer_appeal(
+            ctx.serenity_context(),
+            &ctx.data().db_pool,
+            guild_id,
+            &user,
+            &reason,
+        )
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to offer {} an appeal: {}", user.id, e);
+            None
+        });
+
+        let banned = guild_id
+            .ban_with_reason(&ctx.serenity_context(), user.id, delete_days, &reason)
+            .await;
+        if let Err(e) = banned {
+            // They weren't banned after all, so take back the DM saying they were.
+            if let Some(dm) = appeal {
+                let _ = dm.delete(ctx).await;
+            }
+            return Err(e.into());
+        }
+        moderation::record_case(
+            &ctx.data().db_pool,
+            guild_id,
+            user.id,
+            ctx.author().id,
+            moderation::ModAction::Ban,
+            &reason,
+        )
+        .await?;
+        count_ban(ctx, guild_id).await;
+
+        let mut response = format!("Banned {} | Reason: {}", user.tag(), reason);
+        if delete_days > 0 {
+            response.push_str(&format!(" | Deleted {} day(s) of messages", delete_days));
+        }
+        if appeal.is_some() {
+            response.push_str(" | Appeal offered by DM");
+        }
+        ctx.say(response).await?;
+        Ok(())
+    }
+
+    // Counts a ban the bot made for the command's author towards their anti-nuke limit,
+    // returning whether they went over it.
+    async fn count_ban(ctx: Context<'_>, guild_id: serenity::GuildId) -> bool {
+        let data = ctx.data();
+        antinuke::record_ban(
+            ctx.serenity_context(),
+            &data.db_pool,
+            &data.antinuke,
+            guild_id,
+            ctx.author().id,
+        )
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Anti-nuke error in guild {}: {}", guild_id, e);
+            false
+        })
+    }
+
+    const MAX_DELETE_DAYS: u8 = 7;
+    const MASSBAN_LIMIT: usize = 500;
+    const MASSBAN_ATTACHMENT_LIMIT: u32 = 1024 * 1024;
+
+    /// Ban many users at once by ID, for cleaning up raids.
+    #[poise::command(
+        slash_command,
+        prefix_command,
+        guild_only,
+        required_permissions = "BAN_MEMBERS"
+    )]
+    pub async fn massban(
+        ctx: Context<'_>,
+        #[description = "User IDs or mentions, separated by spaces, commas or new lines"]
+        ids: Option<String>,
+        #[description = "Text file with user IDs"] attachment: Option<serenity::Attachment>,
+        #[description = "Days of their messages to delete (0-7)"]
+        #[min = 0]
+        #[max = 7]
+        delete_days: Option<u8>,
+        #[description = "Reason for ban"] reason: Option<String>,
+        #[description = "Preview who would be banned before doing it"]
+        #[flag]
+        preview: bool,
+    ) -> Result<(), Error> {
+        let guild_id = ctx.guild_id().expect("Must be used in guild");
+        let reason = reason.unwrap_or_else(|| "Mass ban".to_string());
+        let delete_days = delete_days.unwrap_or(0);
+
+        if delete_days > MAX_DELETE_DAYS {
+            ctx.say("❌ You can only delete up to 7 days of messages.")
+                .await?;
+            return Ok(());
+        }
+
+        // Collect the IDs from the text and the attachment, if any.
+        let mut text = ids.unwrap_or_default();
+        if let Some(attachment) = attachment {
+            if attachment.size > MASSBAN_ATTACHMENT_LIMIT {
+                ctx.say("❌ That file is too large, keep it under 1 MB.")
+                    .await?;
+                return Ok(());
+            }
+            let bytes = attachment.download().await?;
+            text.push('\n');
+            text.push_str(&String::from_utf8_lossy(&bytes));
+        }
+
+        let (targets, invalid) = moderation::parse_user_ids(&text);
+        if targets.is_empty() {
+            ctx.say("❌ No user IDs found. Paste them or attach a text file.")
+                .await?;
+            return Ok(());
+        }
+        if targets.len() > MASSBAN_LIMIT {
+            ctx.say(format!(
+                "❌ That's {} users, I can only mass ban {} at a time.",
+                targets.len(),
+                MASSBAN_LIMIT
+            ))
             .await?;
-        ctx.say(format!("Banned {} | Reason: {}", user.tag(), reason))
+            return Ok(());
+        }
+
+        if preview {
+            // Show names where we already know them, looking up hundreds of users isn't worth it.
+            let describe = |id: &serenity::UserId| match ctx.cache().user(*id) {
+                Some(user) => format!("{} ({})", user.tag(), id),
+                _none => id.to_string(),
+            };
+            let sample = targets
+                .iter()
+                .take(10)
+                .map(|id| format!("• <@{}> {}", id, describe(id)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let export = targets.iter().map(describe).collect::<Vec<_>>().join("\n");
+            let mut summary = format!(
+                "**{} users** would be banned | Reason: {}\n{}",
+                targets.len(),
+                reason,
+                sample
+            );
+            if targets.len() > 10 {
+                summary.push_str(&format!("\n...and {} more", targets.len() - 10));
+            }
+            if !invalid.is_empty() {
+                summary.push_str(&format!(
+                    "\n{} invalid entries will be skipped.",
+                    invalid.len()
+                ));
+            }
+            if !moderation::confirm(
+                ctx,
+                summary,
+                Some(CreateAttachment::bytes(export, "massban_preview.txt")),
+            )
+            .await?
+            {
+                return Ok(());
+            }
+        } else {
+            ctx.defer().await?;
+        }
+        let hierarchy = moderation::Hierarchy::fetch(ctx).await?;
+        let progress = ctx
+            .say(format!("Banning {} users...", targets.len()))
+            .await?;
+
+        let mut banned = 0;
+        let mut stopped = false;
+        let mut failed: Vec<(serenity::UserId, String)> = Vec::new();
+        let mut last_update = std::time::Instant::now();
+
+        for (done, target) in targets.iter().enumerate() {
+            match hierarchy.check(ctx, *target).await? {
+                Err(why) => failed.push((*target, why.to_string())),
+                Ok(()) => {
+                    match guild_id
+                        .ban_with_reason(ctx.http(), *target, delete_days, &reason)
+                        .await
+                    {
+                        Ok(()) => {
+                            moderation::record_case(
+                                &ctx.data().db_pool,
+                                guild_id,
+                                *target,
+                                ctx.author().id,
+                                moderation::ModAction::Ban,
+                                &reason,
+                            )
+                            .await?;
+                            banned += 1;
+                            if count_ban(ctx, guild_id).await {
+                                stopped = true;
+                            }
+                        }
+                        Err(e) => failed.push((*target, e.to_string())),
+                    }
+                }
+            }
+
+            // Don't edit on every ban, that would just burn through the rate limit.
+            if last_update.elapsed() >= std::time::Duration::from_secs(3) {
+                progress
+                    .edit(
+                        ctx,
+                        CreateReply::default().content(format!(
+                            "Banning... {}/{} done",
+                            done + 1,
+                            targets.len()
+                        )),
+                    )
+                    .await?;
+                last_update = std::time::Instant::now();
+            }
+            if stopped {
+                break;
+            }
+        }
+
+        let mut summary = format!(
+            "Mass ban finished | Banned: {} | Failed: {} | Reason: {}",
+            banned,
+            failed.len(),
+            reason
+        );
+        if !invalid.is_empty() {
+            summary.push_str(&format!(" | Skipped {} invalid entries", invalid.len()));
+        }
+        if stopped {
+            summary.push_str(" | Stopped early: you went over the anti-nuke ban limit");
+        }
+        progress
+            .edit(ctx, CreateReply::default().content(summary))
             .await?;
+
+        if !failed.is_empty() || !invalid.is_empty() {
+            let mut report = String::new();
+            for (id, why) in &failed {
+                report.push_str(&format!("{}: {}\n", id, why));
+            }
+            for token in &invalid {
+                report.push_str(&format!("{}: not a user ID\n", token));
+            }
+            ctx.send(
+                CreateReply::default()
+                    .content("Entries that weren't banned:")
+                    .attachment(CreateAttachment::bytes(report, "massban_failures.txt")),
+            )
+            .await?;
+        }
         Ok(())
     }
 
+    // Suggests banned users matching what has been typed so far.
+    async fn autocomplete_banned(
+        ctx: Context<'_>,
+        partial: &str,
+    ) -> Vec<serenity::AutocompleteChoice> {
+        let Some(guild_id) = ctx.guild_id() else {
+            return Vec::new();
+        };
+        // One page is plenty for suggestions, the command itself searches every ban.
+        let bans = guild_id
+            .bans(ctx.http(), None, None)
+            .await
+            .unwrap_or_default();
+        bans.iter()
+            .filter(|ban| moderation::ban_matches(ban, partial))
+            .take(25)
+            .map(|ban| {
+                serenity::AutocompleteChoice::new(
+                    format!("{} ({})", ban.user.tag(), ban.user.id),
+                    ban.user.id.to_string(),
+                )
+            })
+            .collect()
+    }
+
     /// Unban a previously banned user
     #[poise::command(slash_command, prefix_command, required_permissions = "BAN_MEMBERS")]
     pub async fn unban(
         ctx: Context<'_>,
-        #[description = "User to unban"] user: serenity::User,
+        #[description = "User ID or name to unban"]
+        #[autocomplete = "autocomplete_banned"]
+        #[rest]
+        user: String,
     ) -> Result<(), Error> {
         let guild_id = ctx.guild_id().expect("Must be used in guild");
-        guild_id.unban(&ctx.serenity_context(), user.id).await?;
-        ctx.say(format!("Unbanned {}", user.tag())).await?;
+        let bans = moderation::fetch_bans(ctx.http(), guild_id).await?;
+
+        let Some(ban) = moderation::find_ban(&bans, &user) else {
+            ctx.say(format!("❌ No ban found for `{}`.", user)).await?;
+            return Ok(());
+        };
+
+        let preview = format!(
+            "Unban **{}** ({})?\nOriginal ban reason: {}",
+            ban.user.tag(),
+            ban.user.id,
+            ban.reason.as_deref().unwrap_or("No reason provided")
+        );
+        if !moderation::confirm(ctx, preview, None).await? {
+            return Ok(());
+        }
+
+        guild_id.unban(&ctx.serenity_context(), ban.user.id).await?;
+        moderation::record_case(
+            &ctx.data().db_pool,
+            guild_id,
+            ban.user.id,
+            ctx.author().id,
+            moderation::ModAction::Unban,
+            "",
+        )
+        .await?;
+        ctx.say(format!("Unbanned {}", ban.user.tag())).await?;
         Ok(())
     }
 
+    /// List the server's current bans
+    #[poise::command(
+        slash_command,
+        prefix_command,
+        guild_only,
+        required_permissions = "BAN_MEMBERS"
+    )]
+    pub async fn bans(
+        ctx: Context<'_>,
+        #[description = "Only bans matching this name or ID"]
+        #[rest]
+        search: Option<String>,
+    ) -> Result<(), Error> {
+        let guild_id = ctx.guild_id().expect("Must be used in guild");
+        ctx.defer().await?;
+
+        let search = search.unwrap_or_default();
+        let bans: Vec<_> = moderation::fetch_bans(ctx.http(), guild_id)
+            .await?
+            .into_iter()
+            .filter(|ban| moderation::ban_matches(ban, &search))
+            .collect();
+
+        if bans.is_empty() {
+            ctx.say("No bans found.").await?;
+            return Ok(());
+        }
+
+        let page_count = bans.len().div_ceil(10);
+        let pages = bans
+            .chunks(10)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let list = chunk
+                    .iter()
+                    .map(|ban| {
+                        format!(
+                            "**{}** ({})\n└ {}",
+                            ban.user.tag(),
+                            ban.user.id,
+                            moderation::shorten(
+                                ban.reason.as_deref().unwrap_or("No reason provided"),
+                                200
+                            )
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                serenity::CreateEmbed::new()
+                    .title(format!("Bans ({})", bans.len()))
+                    .description(list)
+                    .footer(serenity::CreateEmbedFooter::new(format!(
+                        "Page {}/{}",
+                        i + 1,
+                        page_count
+                    )))
+                    .color(serenity::Color::DARK_RED)
+            })
+            .collect();
+
+        moderation::paginate(ctx, pages).await
+    }
+
     /// Kick a user from the server
     #[poise::command(slash_command, prefix_command, required_permissions = "KICK_MEMBERS")]
     pub async fn kick(
@@ -744,80 +1251,428 @@ mod commands {
         let guild_id = ctx.guild_id().expect("Must be used in guild");
         let reason = reason.unwrap_or_else(|| "No reason provided".to_string());
 
+        if !moderation::ensure_actionable(ctx, user.id).await? {
+            return Ok(());
+        }
+
         guild_id
             .kick_with_reason(&ctx.serenity_context(), user.id, &reason)
             .await?;
+        moderation::record_case(
+            &ctx.data().db_pool,
+            guild_id,
+            user.id,
+            ctx.author().id,
+            moderation::ModAction::Kick,
+            &reason,
+        )
+        .await?;
         ctx.say(format!(" Kicked {} | Reason: {}", user.tag(), reason))
             .await?;
         Ok(())
     }
 
-    /// Deletes a specified amount of messages.
+    /// Ban and immediately unban a user, clearing their recent messages
+    #[poise::command(
+        slash_command,
+        prefix_command,
+        required_permissions = "BAN_MEMBERS | KICK_MEMBERS"
+    )]
+    pub async fn softban(
+        ctx: Context<'_>,
+        #[description = "User to softban"] user: serenity::User,
+        #[description = "Days of their messages to delete (0-7, default 1)"]
+        #[min = 0]
+        #[max = 7]
+        days: Option<u8>,
+        #[description = "Reason for softban"] reason: Option<String>,
+    ) -> Result<(), Error> {
+        let guild_id = ctx.guild_id().expect("Must be used in guild");
+        let reason = reason.unwrap_or_else(|| "No reason provided".to_string());
+        let days = days.unwrap_or(1);
+
+        if days > MAX_DELETE_DAYS {
+            ctx.say("❌ You can only delete up to 7 days of messages.")
+                .await?;
+            return Ok(());
+        }
+
+        if !moderation::ensure_actionable(ctx, user.id).await? {
+            return Ok(());
+        }
+
+        guild_id
+            .ban_with_reason(&ctx.serenity_context(), user.id, days, &reason)
+            .await?;
+        count_ban(ctx, guild_id).await;
+
+        // The ban went through, so from here on the user must not be left banned silently.
+        let mut unbanned = guild_id.unban(ctx.http(), user.id).await;
+        if unbanned.is_err() {
+            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+            unbanned = guild_id.unban(ctx.http(), user.id).await;
+        }
+
+        if let Err(e) = unbanned {
+            eprintln!(
+                "Softban of {} in guild {} left them banned: {}",
+                user.id, guild_id, e
+            );
+            moderation::record_case(
+                &ctx.data().db_pool,
+                guild_id,
+                user.id,
+                ctx.author().id,
+                moderation::ModAction::Ban,
+                &format!("Failed softban: {}", reason),
+            )
+            .await?;
+            ctx.say(format!(
+                "🚨 **Softban failed halfway: {} is still BANNED.** \
+                The unban step failed twice ({}). Unban them manually with `unban {}`.",
+                user.tag(),
+                e,
+                user.id
+            ))
+            .await?;
+            return Ok(());
+        }
+
+        moderation::record_case(
+            &ctx.data().db_pool,
+            guild_id,
+            user.id,
+            ctx.author().id,
+            moderation::ModAction::Softban,
+            &reason,
+        )
+        .await?;
+        ctx.say(format!(
+            "Softbanned {} | Deleted {} day(s) of messages | Reason: {}",
+            user.tag(),
+            days,
+            reason
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes a specified amount of messages, optionally filtered.
     #[poise::command(
         slash_command,
         prefix_command,
         required_permissions = "MANAGE_MESSAGES",
         aliases("clean", "clear", "bulkdel")
     )]
+    #[allow(clippy::too_many_arguments)]
     pub async fn purge(
         ctx: Context<'_>,
-        #[description = "Target user"] user: serenity::User,
-        #[description = "Number of messages to delete"] mut amount: u8,
+        #[description = "Number of messages to delete"]
+        #[min = 1]
+        #[max = 5000]
+        amount: u16,
+        #[description = "Only messages from this user"] user: Option<serenity::User>,
+        #[description = "Only messages from bots, or with attachments, links or embeds"]
+        kind: Option<purge::PurgeKind>,
+        #[description = "Only messages containing this text"] contains: Option<String>,
+        #[description = "Only messages matching this regular expression"] regex: Option<String>,
+        #[description = "Only messages sent within this long ago (e.g. 30m, 2h, 1d)"]
+        within: Option<String>,
+        #[description = "Only messages older than this (e.g. 2h, 1d)"] older_than: Option<String>,
+        #[description = "Preview what would be deleted before doing it"]
+        #[flag]
+        preview: bool,
     ) -> Result<(), Error> {
-        let channel_id = ctx.channel_id();
-        let mut total_deleted = 0;
-        let mut last_message_id = ctx.id();
-
-        while amount > 0 {
-            // Fetch up to 100 messages before last_message_id
-            let fetch_limit = std::cmp::min(100, amount);
-            let retriever = GetMessages::new()
-                .limit(fetch_limit)
-                .before(last_message_id);
-            let messages = channel_id.messages(ctx.http(), retriever).await?;
-
-            // Filter messages from the target user
-            let filtered_messages: Vec<_> = messages
-                .iter()
-                .filter(|msg| msg.author.id == user.id)
-                .map(|msg| msg.id)
-                .collect();
+        if amount == 0 || amount > purge::PURGE_LIMIT {
+            ctx.say(format!(
+                "❌ You can purge between 1 and {} messages.",
+                purge::PURGE_LIMIT
+            ))
+            .await?;
+            return Ok(());
+        }
+
+        let pattern = match regex {
+            Some(regex) => match regex::RegexBuilder::new(&regex).size_limit(1 << 20).build() {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    ctx.say(format!("❌ That regex is invalid: {}", e)).await?;
+                    return Ok(());
+                }
+            },
+            _none => None,
+        };
 
-            if filtered_messages.is_empty() {
-                break; // No more messages from user found
+        // Turn the relative durations into absolute unix times.
+        let now = serenity::Timestamp::now().unix_timestamp();
+        let mut window = [None, None];
+        for (slot, text) in window.iter_mut().zip([&within, &older_than]) {
+            if let Some(text) = text {
+                let start = moderation::parse_duration(text)
+                    .and_then(|duration| i64::try_from(duration.as_secs()).ok())
+                    .and_then(|secs| now.checked_sub(secs));
+                match start {
+                    Some(start) => *slot = Some(start),
+                    _none => {
+                        ctx.say(format!(
+                            "❌ `{}` isn't a duration, try something like `30m`, `2h` or `1d`.",
+                            text
+                        ))
+                        .await?;
+                        return Ok(());
+                    }
+                }
             }
+        }
+        let [after, before] = window;
+
+        let filter = purge::PurgeFilter {
+            user: user.as_ref().map(|u| u.id),
+            kind,
+            pattern,
+            contains: contains.map(|c| c.to_lowercase()),
+            after,
+            before,
+        };
 
-            // Delete filtered messages in bulk
-            channel_id.delete_messages(&ctx, &filtered_messages).await?;
+        if preview {
+            ctx.defer_ephemeral().await?;
+        } else {
+            ctx.defer().await?;
+        }
+        let channel_id = ctx.channel_id();
+        let messages = purge::collect(
+            ctx.http(),
+            channel_id,
+            serenity::MessageId::new(ctx.id()),
+            &filter,
+            amount as usize,
+        )
+        .await?;
 
-            let deleted_count = filtered_messages.len() as u8;
-            total_deleted += deleted_count;
-            amount = amount.saturating_sub(deleted_count);
+        if messages.is_empty() {
+            ctx.say("No messages matched those filters.").await?;
+            return Ok(());
+        }
 
-            // Update last_message_id for pagination
-            last_message_id = messages
-                .last()
-                .map(|msg| u64::from(msg.id))
-                .unwrap_or(last_message_id);
+        if preview {
+            let (sample, export) = purge::describe(&messages, 10);
+            let summary = format!(
+                "**{} messages** would be deleted. First {}:\n```\n{}\n```",
+                messages.len(),
+                messages.len().min(10),
+                // Keep message content from closing the code block early.
+                sample.replace('`', "'")
+            );
+            if !moderation::confirm(
+                ctx,
+                summary,
+                Some(CreateAttachment::bytes(export, "purge_preview.txt")),
+            )
+            .await?
+            {
+                return Ok(());
+            }
         }
 
-        ctx.say(format!(
-            "Deleted {} messages from {}",
-            total_deleted, user.name
-        ))
-        .await?
-        .delete(ctx)
-        .await?;
+        let stats = purge::delete(ctx.http(), channel_id, &messages).await?;
+
+        let mut response = format!("Deleted {} messages", stats.deleted());
+        if let Some(user) = &user {
+            response.push_str(&format!(" from {}", user.name));
+        }
+        if stats.single > 0 {
+            response.push_str(&format!(
+                " ({} were older than two weeks and deleted one by one)",
+                stats.single
+            ));
+        }
+        if stats.failed > 0 {
+            response.push_str(&format!(", {} could not be deleted", stats.failed));
+        }
+        ctx.say(response).await?;
 
         Ok(())
     }
 }
 
-struct Handler;
+struct Handler {
+    db_pool: Pool<sqlx::Sqlite>,
+    automod: Arc<automod::Automod>,
+    word_filter: Arc<wordfilter::WordFilter>,
+    antiraid: Arc<antiraid::AntiRaid>,
+    autoslowmode: Arc<autoslowmode::AutoSlowmode>,
+    link_filter: Arc<linkfilter::LinkFilter>,
+    attachment_filter: Arc<attachments::AttachmentFilter>,
+    altdetect: Arc<altdetect::AltDetect>,
+    antinuke: Arc<antinuke::AntiNuke>,
+    dehoist: Arc<dehoist::Dehoist>,
+    verification: Arc<verification::Verification>,
+    modmail: Arc<modmail::Modmail>,
+    dm_prefixes: Arc<prefixes::DmPrefixCache>,
+}
 
 #[serenity::async_trait]
 impl serenity::EventHandler for Handler {
+    async fn message(&self, context: poise::serenity_prelude::Context, msg: serenity::Message) {
+        // DMs only ever go to modmail, none of the guild filters apply.
+        if msg.guild_id.is_none() {
+            if let Err(e) = modmail::dm_received(
+                &context,
+                &self.db_pool,
+                &self.modmail,
+                &self.dm_prefixes,
+                &msg,
+            )
+            .await
+            {
+                eprintln!("Modmail error for {}: {}", msg.author.id, e);
+            }
+            return;
+        }
+        self.autoslowmode.record_message(msg.channel_id);
+        // A message one of the filters already dealt with doesn't need automod as well.
+        match wordfilter::check_message(
+            &context,
+            &self.db_pool,
+            &self.word_filter,
+            &self.automod,
+            &msg,
+        )
+        .await
+        {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(e) => eprintln!("Word filter error in channel {}: {}", msg.channel_id, e),
+        }
+        match linkfilter::check_message(&context, &self.db_pool, &self.link_filter, &msg).await {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(e) => eprintln!("Link filter error in channel {}: {}", msg.channel_id, e),
+        }
+        match attachments::check_message(
+            &context,
+            &self.db_pool,
+            &self.attachment_filter,
+            &self.automod,
+            &msg,
+        )
+        .await
+        {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(e) => eprintln!(
+                "Attachment filter error in channel {}: {}",
+                msg.channel_id, e
+            ),
+        }
+        if let Err(e) = automod::check_message(&context, &self.db_pool, &self.automod, &msg).await {
+            eprintln!("Automod error in channel {}: {}", msg.channel_id, e);
+        }
+    }
+
+    async fn guild_member_addition(
+        &self,
+        context: poise::serenity_prelude::Context,
+        new_member: serenity::Member,
+    ) {
+        if let Err(e) =
+            antiraid::member_joined(&context, &self.db_pool, &self.antiraid, &new_member).await
+        {
+            eprintln!("Anti-raid error in guild {}: {}", new_member.guild_id, e);
+        }
+        if let Err(e) =
+            altdetect::member_joined(&context, &self.db_pool, &self.altdetect, &new_member).await
+        {
+            eprintln!(
+                "Alt detection error in guild {}: {}",
+                new_member.guild_id, e
+            );
+        }
+        if let Err(e) =
+            dehoist::member_changed(&context, &self.db_pool, &self.dehoist, &new_member).await
+        {
+            eprintln!(
+                "Nickname policy error in guild {}: {}",
+                new_member.guild_id, e
+            );
+        }
+        if let Err(e) = verification::member_joined(&context, &self.db_pool, &new_member).await {
+            eprintln!("Verification error in guild {}: {}", new_member.guild_id, e);
+        }
+    }
+
+    async fn guild_member_update(
+        &self,
+        context: poise::serenity_prelude::Context,
+        _old: Option<serenity::Member>,
+        new: Option<serenity::Member>,
+        _event: serenity::GuildMemberUpdateEvent,
+    ) {
+        let Some(member) = new else {
+            return;
+        };
+        if let Err(e) =
+            dehoist::member_changed(&context, &self.db_pool, &self.dehoist, &member).await
+        {
+            eprintln!("Nickname policy error in guild {}: {}", member.guild_id, e);
+        }
+    }
+
+    async fn guild_audit_log_entry_create(
+        &self,
+        context: poise::serenity_prelude::Context,
+        entry: serenity::AuditLogEntry,
+        guild_id: serenity::GuildId,
+    ) {
+        if let Err(e) =
+            antinuke::audit_log_entry(&context, &self.db_pool, &self.antinuke, guild_id, &entry)
+                .await
+        {
+            eprintln!("Anti-nuke error in guild {}: {}", guild_id, e);
+        }
+    }
+
+    // Buttons on messages that outlive a single command, like moderator alerts.
+    async fn interaction_create(
+        &self,
+        context: poise::serenity_prelude::Context,
+        interaction: serenity::Interaction,
+    ) {
+        let serenity::Interaction::Component(component) = interaction else {
+            return;
+        };
+        let custom_id = component.data.custom_id.as_str();
+        let result = if custom_id.starts_with(altdetect::BUTTON_PREFIX) {
+            altdetect::handle_button(
+                &context,
+                &self.db_pool,
+                &self.altdetect,
+                &self.antinuke,
+                &component,
+            )
+            .await
+        } else if custom_id.starts_with(reports::BUTTON_PREFIX) {
+            reports::handle_button(&context, &self.db_pool, &self.antinuke, &component).await
+        } else if custom_id.starts_with(appeals::BUTTON_PREFIX) {
+            appeals::handle_button(&context, &self.db_pool, &component).await
+        } else if custom_id.starts_with(verification::BUTTON_PREFIX) {
+            verification::handle_button(&context, &self.db_pool, &component).await
+        } else if custom_id.starts_with(modmail::SELECT_PREFIX) {
+            modmail::handle_select(&context, &self.db_pool, &self.modmail, &component).await
+        } else {
+            return;
+        };
+        if let Err(e) = result {
+            eprintln!("Error handling button {}: {}", custom_id, e);
+        }
+    }
+
     async fn ready(&self, context: poise::serenity_prelude::Context, _: Ready) {
+        self.autoslowmode
+            .start(context.http.clone(), self.db_pool.clone());
+        self.verification
+            .start(context.http.clone(), self.db_pool.clone());
         use serenity::gateway::ActivityData;
         use serenity::model::user::OnlineStatus;
         static MESSAGES: &[&str] = &[
@@ -890,24 +1745,48 @@ async fn main() {
     // For SQLite, the DATABASE_URL is typically a file path, e.g., "sqlite:database.db"
     let database_url = std::env::var("DATABASE_URL").expect("No database url found.");
     // Set up the SQLx database connection pool for SQLite
-    let pool = SqlitePool::connect(&database_url).await; // Use SqlitePool
-
-    // Run database migrations (optional but recommended for managing schema changes)
-    // Ensure you have a 'migrations' directory with your SQL migration files.
-    // You'll need the sqlx-cli installed (`cargo install sqlx-cli`).
-    // To create a migration: `sqlx migrate add create_guild_prefixes_table`
-    // To run migrations: `sqlx migrate run`
-    // --- Inline Database Table Creation ---
-    sqlx::query!(
-        "CREATE TABLE IF NOT EXISTS guild_prefixes (
-                guild_id INTEGER PRIMARY KEY,
-                prefix VARCHAR(10) NOT NULL DEFAULT 'td!'
-            )"
-    )
-    .execute(&pool.unwrap())
-    .await
-    .expect("ERROR Creating Database");
-    // --- End Inline Database Table Creation ---
+    let pool = SqlitePool::connect(&database_url)
+        .await
+        .expect("ERROR Connecting to Database"); // Use SqlitePool
+
+    // Creates or updates the schema from the migrations in `migrations/`.
+    db::migrate(&pool).await.expect("ERROR Migrating Database");
+
+    // State shared between the commands and the event handler.
+    let automod = Arc::new(automod::Automod::default());
+    let data_pool = pool.clone();
+    let data_automod = automod.clone();
+    let word_filter = Arc::new(wordfilter::WordFilter::default());
+    let data_word_filter = word_filter.clone();
+    let antiraid = Arc::new(antiraid::AntiRaid::default());
+    let data_antiraid = antiraid.clone();
+    let autoslowmode = Arc::new(
+        autoslowmode::AutoSlowmode::load(&pool)
+            .await
+            .expect("ERROR Loading adaptive slowmode channels"),
+    );
+    let data_autoslowmode = autoslowmode.clone();
+    let link_filter = Arc::new(linkfilter::LinkFilter::default());
+    let data_link_filter = link_filter.clone();
+    let attachment_filter = Arc::new(attachments::AttachmentFilter::default());
+    let data_attachment_filter = attachment_filter.clone();
+    let altdetect = Arc::new(altdetect::AltDetect::default());
+    let data_altdetect = altdetect.clone();
+    let antinuke = Arc::new(antinuke::AntiNuke::default());
+    let data_antinuke = antinuke.clone();
+    let dehoist = Arc::new(dehoist::Dehoist::default());
+    let data_dehoist = dehoist.clone();
+    let verification = Arc::new(verification::Verification::default());
+    let modmail = Arc::new(modmail::Modmail::default());
+    let settings = settings::GuildSettings::load(&pool)
+        .await
+        .expect("ERROR Loading guild settings");
+    let dm_prefixes = Arc::new(
+        prefixes::DmPrefixCache::load(&pool)
+            .await
+            .expect("ERROR Loading prefixes"),
+    );
+    let data_dm_prefixes = dm_prefixes.clone();
     let framework: poise::Framework<_, _> = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands: vec![
@@ -918,9 +1797,12 @@ async fn main() {
                 commands::ping(),
                 commands::echo(),
                 commands::ban(),
+                commands::massban(),
                 commands::unban(),
+                commands::bans(),
                 commands::say(),
                 commands::kick(),
+                commands::softban(),
                 commands::facts(),
                 commands::roll(),
                 commands::solve(),
@@ -929,13 +1811,44 @@ async fn main() {
                 commands::fly(),
                 commands::meme(),
                 commands::purge(),
+                automod::automod(),
+                wordfilter::filter(),
+                linkfilter::linkfilter(),
+                attachments::attachments(),
+                antiraid::antiraid(),
+                antiraid::lockdown(),
+                altdetect::altdetect(),
+                antinuke::antinuke(),
+                dehoist::dehoist(),
+                dehoist::nickpolicy(),
+                notes::note(),
+                notes::notes(),
+                notes::history(),
+                reports::report(),
+                reports::report_message(),
+                reports::reports(),
+                modmail::modmail(),
+                modmail::reply(),
+                modmail::areply(),
+                modmail::close(),
+                appeals::appeals(),
+                verification::verification(),
+                settings::config(),
+                prefixes::prefix(),
+                channels::lock(),
+                channels::unlock(),
+                channels::slowmode(),
+                autoslowmode::autoslowmode(),
                 commands::sync(),
             ],
             prefix_options: poise::PrefixFrameworkOptions {
-                prefix: Some("td!".to_string()),
+                // Every prefix is dynamic: several per guild, and each user's own in DMs.
+                prefix: None,
                 case_insensitive_commands: false,
                 mention_as_prefix: true,
-                dynamic_prefix: Some(|ctx| Box::pin(dynamic_prefix_resolver(ctx))), // Use our custom resolver
+                stripped_dynamic_prefix: Some(|ctx, msg, data| {
+                    Box::pin(prefixes::strip(ctx, msg, data))
+                }),
                 /*
                 dynamic_prefix: Some(
                     |ctx| {
@@ -951,18 +1864,42 @@ async fn main() {
             Box::pin(async move {
                 println!("Registering commands...");
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                let dbpool = SqlitePool::connect(&database_url).await?; // Use SqlitePool
                 println!("Registered commands.");
                 Ok(Data {
                     start_time: std::time::Instant::now(),
-                    db_pool: dbpool,
+                    db_pool: data_pool,
+                    automod: data_automod,
+                    word_filter: data_word_filter,
+                    antiraid: data_antiraid,
+                    autoslowmode: data_autoslowmode,
+                    link_filter: data_link_filter,
+                    attachment_filter: data_attachment_filter,
+                    altdetect: data_altdetect,
+                    antinuke: data_antinuke,
+                    dehoist: data_dehoist,
+                    settings,
+                    dm_prefixes: data_dm_prefixes,
                 })
             })
         })
         .build();
 
     let mut client = ClientBuilder::new(token, GatewayIntents::all())
-        .event_handler(Handler)
+        .event_handler(Handler {
+            db_pool: pool,
+            automod,
+            word_filter,
+            antiraid,
+            autoslowmode,
+            link_filter,
+            attachment_filter,
+            altdetect,
+            antinuke,
+            dehoist,
+            verification,
+            modmail,
+            dm_prefixes,
+        })
         .framework(framework)
         .await
         .expect("The client has unexpectedly crashed.");
@@ -973,3 +1910,26 @@ async fn main() {
         .await
         .expect("Sharding has failed")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn help_pages_fit_in_an_embed() {
+        for page in commands::help_pages(prefixes::DEFAULT) {
+            let page = ::serenity::json::to_value(page).unwrap();
+            let fields = page["fields"].as_array().unwrap();
+            assert!(fields.len() <= 25, "{} fields", fields.len());
+            let text =
+                |value: &::serenity::json::Value| value.as_str().map_or(0, |s| s.chars().count());
+            let chars = text(&page["title"])
+                + text(&page["description"])
+                + fields
+                    .iter()
+                    .map(|field| text(&field["name"]) + text(&field["value"]))
+                    .sum::<usize>();
+            assert!(chars <= 6000, "{} characters", chars);
+        }
+    }
+}
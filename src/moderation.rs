@@ -0,0 +1,747 @@
+use crate::{Context, Error};
+use poise::CreateReply;
+use poise::serenity_prelude as serenity;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The kinds of moderation action recorded in `mod_cases`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModAction {
+    Ban,
+    Unban,
+    Kick,
+    Softban,
+    Warn,
+    Timeout,
+}
+
+impl ModAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ModAction::Ban => "ban",
+            ModAction::Unban => "unban",
+            ModAction::Kick => "kick",
+            ModAction::Softban => "softban",
+            ModAction::Warn => "warn",
+            ModAction::Timeout => "timeout",
+        }
+    }
+}
+
+/// Records a moderation action against `user_id`, returning the new case number.
+pub async fn record_case(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+    moderator_id: serenity::UserId,
+    action: ModAction,
+    reason: &str,
+) -> Result<i64, Error> {
+    let guild_id = guild_id.get() as i64;
+    let user_id = user_id.get() as i64;
+    let moderator_id = moderator_id.get() as i64;
+    let action = action.as_str();
+    let case = sqlx::query!(
+        "INSERT INTO mod_cases (guild_id, user_id, moderator_id, action, reason)
+         VALUES (?, ?, ?, ?, ?)",
+        guild_id,
+        user_id,
+        moderator_id,
+        action,
+        reason
+    )
+    .execute(pool)
+    .await?;
+    Ok(case.last_insert_rowid())
+}
+
+/// Whether a member can manage messages in a channel, going by the cache. Used to keep
+/// automatic moderation off the moderators themselves.
+pub fn is_moderator(
+    ctx: &serenity::Context,
+    guild_id: serenity::GuildId,
+    channel_id: serenity::ChannelId,
+    user_id: serenity::UserId,
+) -> bool {
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return false;
+    };
+    if guild.owner_id == user_id {
+        return true;
+    }
+    // Threads aren't in `channels` and take their permissions from their parent.
+    let channel = guild.channels.get(&channel_id).or_else(|| {
+        let thread = guild
+            .threads
+            .iter()
+            .find(|thread| thread.id == channel_id)?;
+        guild.channels.get(&thread.parent_id?)
+    });
+    let (Some(member), Some(channel)) = (guild.members.get(&user_id), channel) else {
+        return false;
+    };
+    let permissions = guild.user_permissions_in(channel, member);
+    permissions.administrator() || permissions.manage_messages()
+}
+
+/// A member taking part in a moderation action, reduced to what the hierarchy check needs.
+#[derive(Debug, Clone, Copy)]
+pub struct Actor {
+    pub id: serenity::UserId,
+    /// Position of the member's highest role (0 means only @everyone).
+    pub top_role: u16,
+}
+
+/// Reasons a moderation action would be refused before it reaches Discord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HierarchyError {
+    SelfTarget,
+    TargetIsBot,
+    TargetIsOwner,
+    InvokerTooLow,
+    BotTooLow,
+}
+
+impl fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            HierarchyError::SelfTarget => "You can't use this on yourself.",
+            HierarchyError::TargetIsBot => "I'm not going to do that to myself.",
+            HierarchyError::TargetIsOwner => "The server owner can't be moderated.",
+            HierarchyError::InvokerTooLow => {
+                "That member's highest role is equal to or above yours."
+            }
+            HierarchyError::BotTooLow => {
+                "That member's highest role is equal to or above mine. Move my role higher and try again."
+            }
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Decides whether `invoker` may act on `target_id` through the bot.
+///
+/// `target_top_role` is `None` when the target isn't a member of the guild, in which
+/// case only the identity checks apply (you can still ban someone who already left).
+pub fn check_hierarchy(
+    owner_id: serenity::UserId,
+    invoker: Actor,
+    bot: Actor,
+    target_id: serenity::UserId,
+    target_top_role: Option<u16>,
+) -> Result<(), HierarchyError> {
+    if target_id == invoker.id {
+        return Err(HierarchyError::SelfTarget);
+    }
+    if target_id == bot.id {
+        return Err(HierarchyError::TargetIsBot);
+    }
+    if target_id == owner_id {
+        return Err(HierarchyError::TargetIsOwner);
+    }
+
+    let Some(target_top_role) = target_top_role else {
+        return Ok(());
+    };
+
+    // The owner outranks everyone regardless of roles.
+    if invoker.id != owner_id && invoker.top_role <= target_top_role {
+        return Err(HierarchyError::InvokerTooLow);
+    }
+    if bot.top_role <= target_top_role {
+        return Err(HierarchyError::BotTooLow);
+    }
+    Ok(())
+}
+
+// Highest position among the given roles, 0 if the member only has @everyone.
+fn top_position(
+    roles: &HashMap<serenity::RoleId, serenity::Role>,
+    member: &serenity::Member,
+) -> u16 {
+    member
+        .roles
+        .iter()
+        .filter_map(|id| roles.get(id))
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Snapshot of the guild's roles and the two members acting, so many targets can be
+/// checked without refetching everything.
+pub struct Hierarchy {
+    guild_id: serenity::GuildId,
+    owner_id: serenity::UserId,
+    invoker: Actor,
+    bot: Actor,
+    roles: HashMap<serenity::RoleId, serenity::Role>,
+}
+
+impl Hierarchy {
+    /// Gathers the owner, role positions, invoker and bot for the current guild.
+    pub async fn fetch(ctx: Context<'_>) -> Result<Self, Error> {
+        let guild_id = ctx
+            .guild_id()
+            .ok_or("This command can only be used in a guild.")?;
+        Self::load(ctx.serenity_context(), guild_id, ctx.author().id).await
+    }
+
+    /// Like [`Hierarchy::fetch`], for code running outside a command (e.g. a button).
+    pub async fn load(
+        ctx: &serenity::Context,
+        guild_id: serenity::GuildId,
+        invoker_id: serenity::UserId,
+    ) -> Result<Self, Error> {
+        let invoker = guild_id.member(ctx, invoker_id).await?;
+        let bot_id = ctx.cache.current_user().id;
+        let bot = guild_id.member(ctx, bot_id).await?;
+
+        // Copy what we need out of the cache so the guard isn't held across an await.
+        let cached = ctx
+            .cache
+            .guild(guild_id)
+            .map(|g| (g.owner_id, g.roles.clone()));
+        let (owner_id, roles) = match cached {
+            Some(cached) => cached,
+            None => {
+                let guild = ctx.http.get_guild(guild_id).await?;
+                (guild.owner_id, guild.roles)
+            }
+        };
+
+        Ok(Hierarchy {
+            guild_id,
+            owner_id,
+            invoker: Actor {
+                id: invoker.user.id,
+                top_role: top_position(&roles, &invoker),
+            },
+            bot: Actor {
+                id: bot.user.id,
+                top_role: top_position(&roles, &bot),
+            },
+            roles,
+        })
+    }
+
+    /// Checks a single target, looking up its membership in the guild.
+    pub async fn check(
+        &self,
+        ctx: impl serenity::CacheHttp,
+        target_id: serenity::UserId,
+    ) -> Result<Result<(), HierarchyError>, Error> {
+        // A user that isn't in the guild has no roles to compare against.
+        let target = self.guild_id.member(ctx, target_id).await.ok();
+
+        Ok(check_hierarchy(
+            self.owner_id,
+            self.invoker,
+            self.bot,
+            target_id,
+            target.as_ref().map(|m| top_position(&self.roles, m)),
+        ))
+    }
+}
+
+/// Checks the role hierarchy for acting on `target_id` in the current guild.
+pub async fn hierarchy_for(
+    ctx: Context<'_>,
+    target_id: serenity::UserId,
+) -> Result<Result<(), HierarchyError>, Error> {
+    Hierarchy::fetch(ctx).await?.check(ctx, target_id).await
+}
+
+/// Replies with an explanation and returns `false` if the target can't be moderated.
+pub async fn ensure_actionable(
+    ctx: Context<'_>,
+    target_id: serenity::UserId,
+) -> Result<bool, Error> {
+    match hierarchy_for(ctx, target_id).await? {
+        Ok(()) => Ok(true),
+        Err(why) => {
+            ctx.say(format!("❌ {}", why)).await?;
+            Ok(false)
+        }
+    }
+}
+
+/// Shows a preview of a destructive action with Confirm/Cancel buttons and waits for the
+/// invoker to choose. `export`, if any, is attached as a file with the full details.
+///
+/// Returns `true` only if the invoker pressed Confirm before the timeout.
+pub async fn confirm(
+    ctx: Context<'_>,
+    preview: String,
+    export: Option<serenity::CreateAttachment>,
+) -> Result<bool, Error> {
+    let ctx_id = ctx.id();
+    let confirm_id = format!("{}confirm", ctx_id);
+    let cancel_id = format!("{}cancel", ctx_id);
+    let components = serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(&confirm_id)
+            .label("Confirm")
+            .style(serenity::ButtonStyle::Danger),
+        serenity::CreateButton::new(&cancel_id)
+            .label("Cancel")
+            .style(serenity::ButtonStyle::Secondary),
+    ]);
+
+    let mut reply = CreateReply::default()
+        .content(preview)
+        .components(vec![components])
+        // Previews list users, they shouldn't all get pinged.
+        .allowed_mentions(serenity::CreateAllowedMentions::new())
+        .ephemeral(true);
+    if let Some(export) = export {
+        reply = reply.attachment(export);
+    }
+    let reply = ctx.send(reply).await?;
+
+    let author_id = ctx.author().id;
+    let press = serenity::collector::ComponentInteractionCollector::new(ctx)
+        .filter(move |press| {
+            press.data.custom_id.starts_with(&ctx_id.to_string()) && press.user.id == author_id
+        })
+        .timeout(std::time::Duration::from_secs(120))
+        .await;
+
+    let confirmed = press
+        .as_ref()
+        .is_some_and(|press| press.data.custom_id == confirm_id);
+    let outcome = match (&press, confirmed) {
+        (_, true) => "✅ Confirmed, working on it...",
+        (Some(_), false) => "Cancelled, nothing was done.",
+        (None, _) => "Timed out, nothing was done.",
+    };
+
+    match press {
+        Some(press) => {
+            press
+                .create_response(
+                    ctx.serenity_context(),
+                    serenity::CreateInteractionResponse::UpdateMessage(
+                        serenity::CreateInteractionResponseMessage::new()
+                            .content(outcome)
+                            .components(vec![]),
+                    ),
+                )
+                .await?
+        }
+        _none => {
+            reply
+                .edit(
+                    ctx,
+                    CreateReply::default().content(outcome).components(vec![]),
+                )
+                .await?
+        }
+    }
+    Ok(confirmed)
+}
+
+/// Answers a button press with a message only the presser can see.
+pub async fn reply_ephemeral(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    content: impl Into<String>,
+) -> Result<(), Error> {
+    interaction
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::Message(
+                serenity::CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Answers a button press with the form `M` and waits for it to be submitted.
+///
+/// Returns the answers along with the submission, which the caller still has to respond
+/// to (e.g. by updating the message the button was on). `None` if nobody submitted in time.
+pub async fn modal_on_button<M: poise::Modal>(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    timeout: std::time::Duration,
+) -> Result<Option<(M, serenity::ModalInteraction)>, Error> {
+    let custom_id = interaction.id.to_string();
+    interaction
+        .create_response(ctx, M::create(None, custom_id.clone()))
+        .await?;
+    let Some(submission) = serenity::collector::ModalInteractionCollector::new(&ctx.shard)
+        .filter(move |submission| submission.data.custom_id == custom_id)
+        .timeout(timeout)
+        .await
+    else {
+        return Ok(None);
+    };
+    let answers = M::parse(submission.data.clone())?;
+    Ok(Some((answers, submission)))
+}
+
+/// Shows `pages` one at a time with buttons to flip between them, for the invoker only.
+pub async fn paginate(ctx: Context<'_>, pages: Vec<serenity::CreateEmbed>) -> Result<(), Error> {
+    let Some(first) = pages.first() else {
+        return Ok(());
+    };
+    if pages.len() == 1 {
+        ctx.send(CreateReply::default().embed(first.clone()))
+            .await?;
+        return Ok(());
+    }
+
+    let ctx_id = ctx.id();
+    let prev_button_id = format!("{}prev", ctx_id);
+    let next_button_id = format!("{}next", ctx_id);
+    let components = serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(&prev_button_id).emoji('◀'),
+        serenity::CreateButton::new(&next_button_id).emoji('▶'),
+    ]);
+    ctx.send(
+        CreateReply::default()
+            .embed(first.clone())
+            .components(vec![components]),
+    )
+    .await?;
+
+    let author_id = ctx.author().id;
+    let mut current_page = 0;
+    while let Some(press) = serenity::collector::ComponentInteractionCollector::new(ctx)
+        .filter(move |press| {
+            press.data.custom_id.starts_with(&ctx_id.to_string()) && press.user.id == author_id
+        })
+        .timeout(std::time::Duration::from_secs(600))
+        .await
+    {
+        if press.data.custom_id == next_button_id {
+            current_page = (current_page + 1) % pages.len();
+        } else if press.data.custom_id == prev_button_id {
+            current_page = current_page.checked_sub(1).unwrap_or(pages.len() - 1);
+        } else {
+            continue;
+        }
+
+        press
+            .create_response(
+                ctx.serenity_context(),
+                serenity::CreateInteractionResponse::UpdateMessage(
+                    serenity::CreateInteractionResponseMessage::new()
+                        .embed(pages[current_page].clone()),
+                ),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Looks up a single user's ban, if they are banned.
+pub async fn fetch_ban(
+    http: &serenity::Http,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+) -> Result<Option<serenity::Ban>, Error> {
+    // Bans come back sorted by user ID, so the first one after the ID just below theirs
+    // is theirs if it exists.
+    let after = serenity::UserPagination::After(serenity::UserId::new(user_id.get() - 1));
+    let page = guild_id.bans(http, Some(after), Some(1)).await?;
+    Ok(page.into_iter().find(|ban| ban.user.id == user_id))
+}
+
+/// Fetches every ban in the guild, a thousand at a time.
+pub async fn fetch_bans(
+    http: &serenity::Http,
+    guild_id: serenity::GuildId,
+) -> Result<Vec<serenity::Ban>, Error> {
+    let mut bans: Vec<serenity::Ban> = Vec::new();
+    loop {
+        let after = bans
+            .last()
+            .map(|ban| serenity::UserPagination::After(ban.user.id));
+        let page = guild_id.bans(http, after, None).await?;
+        if page.is_empty() {
+            break;
+        }
+        bans.extend(page);
+    }
+    Ok(bans)
+}
+
+/// Cuts `text` down to `max_chars` characters, marking the cut with an ellipsis.
+pub fn shorten(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(max_chars - 1).collect();
+    short.push('…');
+    short
+}
+
+/// Whether a ban's user matches a search by ID, username, display name or tag.
+pub fn ban_matches(ban: &serenity::Ban, search: &str) -> bool {
+    let search = search.trim().to_lowercase();
+    if search.is_empty() {
+        return true;
+    }
+    ban.user.id.to_string().contains(&search)
+        || ban.user.tag().to_lowercase().contains(&search)
+        || ban
+            .user
+            .global_name
+            .as_ref()
+            .is_some_and(|name| name.to_lowercase().contains(&search))
+}
+
+/// Finds the ban for a user given by ID, mention or exact name.
+pub fn find_ban<'a>(bans: &'a [serenity::Ban], query: &str) -> Option<&'a serenity::Ban> {
+    let (ids, _) = parse_user_ids(query);
+    if let [id] = ids[..] {
+        return bans.iter().find(|ban| ban.user.id == id);
+    }
+    let query = query.trim().to_lowercase();
+    bans.iter().find(|ban| {
+        ban.user.name.to_lowercase() == query
+            || ban.user.tag().to_lowercase() == query
+            || ban
+                .user
+                .global_name
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase() == query)
+    })
+}
+
+/// Pulls user IDs out of free-form text (IDs or mentions separated by whitespace or commas).
+///
+/// Returns the unique IDs in the order they appeared, and every token that wasn't one.
+pub fn parse_user_ids(text: &str) -> (Vec<serenity::UserId>, Vec<String>) {
+    let mut ids = Vec::new();
+    let mut invalid = Vec::new();
+
+    for token in text.split(|c: char| c.is_whitespace() || c == ',') {
+        if token.is_empty() {
+            continue;
+        }
+        let raw = token
+            .trim_start_matches("<@")
+            .trim_start_matches('!')
+            .trim_end_matches('>');
+        match raw.parse::<u64>() {
+            Ok(id) if id != 0 => {
+                let id = serenity::UserId::new(id);
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            _ => invalid.push(token.to_string()),
+        }
+    }
+    (ids, invalid)
+}
+
+/// Parses durations like `30s`, `10m`, `1h30m`, `2d` or `1w`. A bare number is seconds.
+/// Anything too long to count in `i64` seconds is rejected, so callers can store it.
+pub fn parse_duration(text: &str) -> Option<std::time::Duration> {
+    let text = text.trim();
+    if let Ok(secs) = text.parse::<u64>() {
+        i64::try_from(secs).ok()?;
+        return Some(std::time::Duration::from_secs(secs));
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            'w' => 60 * 60 * 24 * 7,
+            _ => return None,
+        };
+        let value: u64 = number.parse().ok()?;
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        number.clear();
+    }
+    // Trailing digits without a unit, e.g. `1h30`, are ambiguous.
+    if !number.is_empty() || text.is_empty() {
+        return None;
+    }
+    i64::try_from(total).ok()?;
+    Some(std::time::Duration::from_secs(total))
+}
+
+/// The inverse of [`parse_duration`], e.g. `5400` becomes `1h 30m`.
+pub fn format_duration(secs: u64) -> String {
+    if secs == 0 {
+        return "0s".to_string();
+    }
+    let units = [
+        ("w", 604_800),
+        ("d", 86_400),
+        ("h", 3_600),
+        ("m", 60),
+        ("s", 1),
+    ];
+    let mut rest = secs;
+    let mut parts = Vec::new();
+    for (unit, size) in units {
+        if rest >= size {
+            parts.push(format!("{}{}", rest / size, unit));
+            rest %= size;
+        }
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: serenity::UserId = serenity::UserId::new(1);
+    const MOD: serenity::UserId = serenity::UserId::new(2);
+    const BOT: serenity::UserId = serenity::UserId::new(3);
+    const TARGET: serenity::UserId = serenity::UserId::new(4);
+
+    fn actor(id: serenity::UserId, top_role: u16) -> Actor {
+        Actor { id, top_role }
+    }
+
+    #[test]
+    fn allows_higher_invoker_and_bot() {
+        let res = check_hierarchy(OWNER, actor(MOD, 5), actor(BOT, 10), TARGET, Some(3));
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn rejects_self_bot_and_owner() {
+        let check = |target| check_hierarchy(OWNER, actor(MOD, 5), actor(BOT, 10), target, None);
+        assert_eq!(check(MOD), Err(HierarchyError::SelfTarget));
+        assert_eq!(check(BOT), Err(HierarchyError::TargetIsBot));
+        assert_eq!(check(OWNER), Err(HierarchyError::TargetIsOwner));
+    }
+
+    #[test]
+    fn rejects_equal_or_higher_target() {
+        let equal = check_hierarchy(OWNER, actor(MOD, 5), actor(BOT, 10), TARGET, Some(5));
+        assert_eq!(equal, Err(HierarchyError::InvokerTooLow));
+        let higher = check_hierarchy(OWNER, actor(MOD, 5), actor(BOT, 10), TARGET, Some(7));
+        assert_eq!(higher, Err(HierarchyError::InvokerTooLow));
+    }
+
+    #[test]
+    fn rejects_target_above_bot() {
+        let res = check_hierarchy(OWNER, actor(MOD, 20), actor(BOT, 10), TARGET, Some(10));
+        assert_eq!(res, Err(HierarchyError::BotTooLow));
+    }
+
+    #[test]
+    fn owner_bypasses_role_check_but_not_bot() {
+        let ok = check_hierarchy(OWNER, actor(OWNER, 0), actor(BOT, 10), TARGET, Some(5));
+        assert_eq!(ok, Ok(()));
+        let too_high = check_hierarchy(OWNER, actor(OWNER, 0), actor(BOT, 10), TARGET, Some(12));
+        assert_eq!(too_high, Err(HierarchyError::BotTooLow));
+    }
+
+    #[test]
+    fn non_member_skips_role_checks() {
+        let res = check_hierarchy(OWNER, actor(MOD, 0), actor(BOT, 0), TARGET, None);
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn parses_ids_and_mentions() {
+        let (ids, invalid) = parse_user_ids("123, <@456>\n<@!789> nope 123 0");
+        assert_eq!(
+            ids,
+            vec![
+                serenity::UserId::new(123),
+                serenity::UserId::new(456),
+                serenity::UserId::new(789)
+            ]
+        );
+        assert_eq!(invalid, vec!["nope".to_string(), "0".to_string()]);
+    }
+
+    #[test]
+    fn parses_durations() {
+        let secs = |s| parse_duration(s).map(|d| d.as_secs());
+        assert_eq!(secs("45"), Some(45));
+        assert_eq!(secs("10m"), Some(600));
+        assert_eq!(secs("1h30m"), Some(5400));
+        assert_eq!(secs("2D"), Some(172_800));
+        assert_eq!(secs("1h30"), None);
+        assert_eq!(secs("soon"), None);
+        assert_eq!(secs(""), None);
+        assert_eq!(secs("9223372036854775807"), Some(i64::MAX as u64));
+        assert_eq!(secs("9223372036854775808"), None);
+        assert_eq!(secs("99999999999999999999w"), None);
+        assert_eq!(secs("9223372036854775807m"), None);
+    }
+
+    fn ban(id: u64, name: &str, global_name: Option<&str>) -> serenity::Ban {
+        ::serenity::json::from_value(::serenity::json::json!({
+            "reason": null,
+            "user": {
+                "id": id.to_string(),
+                "username": name,
+                "global_name": global_name,
+                "discriminator": "0",
+                "avatar": null,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn searches_bans() {
+        let spammer = ban(123456, "Spammer", Some("Free Nitro"));
+        assert!(ban_matches(&spammer, ""));
+        assert!(ban_matches(&spammer, "  "));
+        assert!(ban_matches(&spammer, "3456"));
+        assert!(ban_matches(&spammer, "spam"));
+        assert!(ban_matches(&spammer, " NITRO "));
+        assert!(!ban_matches(&spammer, "raider"));
+    }
+
+    #[test]
+    fn finds_bans_by_id_mention_or_exact_name() {
+        let bans = [
+            ban(111, "alice", None),
+            ban(222, "bob", Some("Robert")),
+            ban(333, "bobby", None),
+        ];
+        let found = |query| find_ban(&bans, query).map(|ban| ban.user.id.get());
+        assert_eq!(found("222"), Some(222));
+        assert_eq!(found("<@!333>"), Some(333));
+        assert_eq!(found("444"), None);
+        assert_eq!(found("ALICE"), Some(111));
+        assert_eq!(found("robert"), Some(222));
+        // Names must match exactly, unlike a search.
+        assert_eq!(found("bob"), Some(222));
+        assert_eq!(found("bo"), None);
+    }
+
+    #[test]
+    fn shortens_text() {
+        assert_eq!(shorten("short", 10), "short");
+        assert_eq!(shorten("exactly10!", 10), "exactly10!");
+        assert_eq!(shorten("a bit too long", 10), "a bit too…");
+        assert_eq!(shorten("ééééé", 3), "éé…");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(5400), "1h 30m");
+        assert_eq!(format_duration(691_200), "1w 1d");
+    }
+}
@@ -0,0 +1,968 @@
+use crate::notes;
+use crate::prefixes;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+
+/// Prefix of the custom id on the "which server?" menu, routed here by the event handler.
+pub const SELECT_PREFIX: &str = "modmail:";
+/// Attachments bigger than this are linked instead of uploaded again. It's the most a bot
+/// can upload to a server without boosts.
+const MAX_RELAY_BYTES: u32 = 10 * 1024 * 1024;
+
+/// Modmail state shared between the DM handler and the server picker.
+#[derive(Default)]
+pub struct Modmail {
+    // Held while a user's DM is handled, so several sent at once can't each find no
+    // open ticket and open their own.
+    users: Mutex<HashMap<serenity::UserId, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Modmail {
+    async fn lock_user(&self, user_id: serenity::UserId) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut users = self.users.lock().unwrap();
+            // Nobody holds or waits on these any more.
+            users.retain(|_, lock| Arc::strong_count(lock) > 1);
+            users.entry(user_id).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+// Whether the user is in the guild, only asking Discord when the cache can't tell.
+async fn is_member(
+    ctx: &serenity::Context,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+) -> bool {
+    let cached = match ctx.cache.guild(guild_id) {
+        // The bot isn't in it, so neither is the ticket channel.
+        None => return false,
+        Some(guild) if guild.members.contains_key(&user_id) => return true,
+        // Large guilds aren't fully cached.
+        Some(guild) => guild.members.len() as u64 >= guild.member_count,
+    };
+    !cached && guild_id.member(ctx, user_id).await.is_ok()
+}
+
+// Helper struct to map the query result
+struct ModmailConfigRow {
+    enabled: bool,
+    channel_id: Option<i64>,
+    log_channel_id: Option<i64>,
+}
+
+// Helper struct to map the query result
+struct TicketRow {
+    id: i64,
+    guild_id: i64,
+    user_id: i64,
+    thread_id: i64,
+    created_at: i64,
+}
+
+// Helper struct to map the query result
+struct MessageRow {
+    author_name: String,
+    from_staff: bool,
+    anonymous: bool,
+    content: String,
+    attachments: String,
+    created_at: i64,
+}
+
+async fn load_config(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<ModmailConfigRow, Error> {
+    let guild_id = guild_id.get() as i64;
+    let config = sqlx::query_as!(
+        ModmailConfigRow,
+        "SELECT enabled, channel_id, log_channel_id FROM modmail_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(ModmailConfigRow {
+        enabled: false,
+        channel_id: None,
+        log_channel_id: None,
+    });
+    Ok(config)
+}
+
+async fn open_ticket_for_user(
+    pool: &Pool<Sqlite>,
+    user_id: serenity::UserId,
+) -> Result<Option<TicketRow>, Error> {
+    let user_id = user_id.get() as i64;
+    let ticket = sqlx::query_as!(
+        TicketRow,
+        "SELECT id, guild_id, user_id, thread_id, created_at FROM modmail_tickets
+         WHERE user_id = ? AND status = 'open' ORDER BY id DESC LIMIT 1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(ticket)
+}
+
+async fn ticket_for_thread(
+    pool: &Pool<Sqlite>,
+    thread_id: serenity::ChannelId,
+) -> Result<Option<TicketRow>, Error> {
+    let thread_id = thread_id.get() as i64;
+    let ticket = sqlx::query_as!(
+        TicketRow,
+        "SELECT id, guild_id, user_id, thread_id, created_at FROM modmail_tickets
+         WHERE thread_id = ? AND status = 'open'",
+        thread_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(ticket)
+}
+
+async fn log_message(
+    pool: &Pool<Sqlite>,
+    ticket_id: i64,
+    author: &serenity::User,
+    from_staff: bool,
+    anonymous: bool,
+    content: &str,
+    attachments: &[serenity::Attachment],
+) -> Result<(), Error> {
+    let author_id = author.id.get() as i64;
+    let author_name = author.tag();
+    let attachments = attachments
+        .iter()
+        .map(|attachment| attachment.url.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    sqlx::query!(
+        "INSERT INTO modmail_messages
+            (ticket_id, author_id, author_name, from_staff, anonymous, content, attachments)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        ticket_id,
+        author_id,
+        author_name,
+        from_staff,
+        anonymous,
+        content,
+        attachments
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Downloads attachments so they can be sent on, linking the ones too big to re-upload.
+async fn copy_attachments(
+    attachments: &[serenity::Attachment],
+) -> (Vec<serenity::CreateAttachment>, Vec<String>) {
+    let mut files = Vec::new();
+    let mut links = Vec::new();
+    for attachment in attachments {
+        if attachment.size <= MAX_RELAY_BYTES
+            && let Ok(data) = attachment.download().await
+        {
+            files.push(serenity::CreateAttachment::bytes(
+                data,
+                attachment.filename.clone(),
+            ));
+            continue;
+        }
+        links.push(attachment_link(attachment));
+    }
+    (files, links)
+}
+
+fn attachment_links(attachments: &[serenity::Attachment]) -> Vec<String> {
+    attachments.iter().map(attachment_link).collect()
+}
+
+fn attachment_link(attachment: &serenity::Attachment) -> String {
+    format!("[{}]({})", attachment.filename, attachment.url)
+}
+
+// Discord's answer when the channel is gone, e.g. a ticket thread moderators deleted.
+fn is_unknown_channel(error: &serenity::Error) -> bool {
+    matches!(
+        error,
+        serenity::Error::Http(serenity::HttpError::UnsuccessfulRequest(response))
+            if response.error.code == 10003
+    )
+}
+
+// Marks a ticket closed without a moderator, when its thread can't be reached any more.
+async fn close_abandoned(pool: &Pool<Sqlite>, ticket_id: i64, reason: &str) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE modmail_tickets
+         SET status = 'closed', closed_at = strftime('%s', 'now'), close_reason = ?
+         WHERE id = ?",
+        reason,
+        ticket_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+fn relay_embed(content: &str, links: &[String]) -> serenity::CreateEmbed {
+    let mut description = content.to_string();
+    if !links.is_empty() {
+        if !description.is_empty() {
+            description.push_str("\n\n");
+        }
+        description.push_str(&links.join("\n"));
+    }
+    serenity::CreateEmbed::new()
+        .description(description)
+        .timestamp(serenity::Timestamp::now())
+}
+
+// Bot commands typed in DMs aren't meant for the moderators.
+fn is_command(ctx: &serenity::Context, msg: &serenity::Message, prefixes: &[String]) -> bool {
+    let bot_id = ctx.cache.current_user().id;
+    prefixes
+        .iter()
+        .any(|prefix| msg.content.starts_with(prefix.as_str()))
+        || msg.content.starts_with(&format!("<@{}>", bot_id))
+        || msg.content.starts_with(&format!("<@!{}>", bot_id))
+}
+
+/// Handles a DM to the bot: relays it into the sender's open ticket, or opens one.
+pub async fn dm_received(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    modmail: &Modmail,
+    dm_prefixes: &prefixes::DmPrefixCache,
+    msg: &serenity::Message,
+) -> Result<(), Error> {
+    if msg.author.bot || msg.guild_id.is_some() {
+        return Ok(());
+    }
+    let prefixes = dm_prefixes.get(pool, msg.author.id).await?;
+    if is_command(ctx, msg, &prefixes) {
+        return Ok(());
+    }
+    let _lock = modmail.lock_user(msg.author.id).await;
+    // A ticket whose thread was deleted gets closed, and this message starts a new one.
+    if let Some(ticket) = open_ticket_for_user(pool, msg.author.id).await?
+        && relay_to_thread(ctx, pool, &ticket, msg).await?
+    {
+        return Ok(());
+    }
+
+    // Servers using modmail that the sender is actually in.
+    let configured = sqlx::query!(
+        "SELECT guild_id FROM modmail_config WHERE enabled = 1 AND channel_id IS NOT NULL"
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut guilds = Vec::new();
+    for row in configured {
+        let guild_id = serenity::GuildId::new(row.guild_id as u64);
+        if is_member(ctx, guild_id, msg.author.id).await {
+            let name = guild_id.name(ctx).unwrap_or_else(|| guild_id.to_string());
+            guilds.push((guild_id, name));
+        }
+    }
+
+    match guilds.as_slice() {
+        [] => {
+            msg.channel_id
+                .say(
+                    ctx,
+                    "None of the servers we share take messages through me. Contact their moderators directly.",
+                )
+                .await?;
+        }
+        [(guild_id, _)] => {
+            let ticket = open_ticket(ctx, pool, *guild_id, &msg.author).await?;
+            relay_to_thread(ctx, pool, &ticket, msg).await?;
+        }
+        _ => {
+            let options = guilds
+                .iter()
+                .take(25)
+                .map(|(guild_id, name)| {
+                    serenity::CreateSelectMenuOption::new(name.clone(), guild_id.to_string())
+                })
+                .collect();
+            msg.channel_id
+                .send_message(
+                    ctx,
+                    serenity::CreateMessage::new()
+                        .content("Which server's moderators do you want to reach?")
+                        .select_menu(
+                            serenity::CreateSelectMenu::new(
+                                format!("{}open:{}", SELECT_PREFIX, msg.id),
+                                serenity::CreateSelectMenuKind::String { options },
+                            )
+                            .placeholder("Choose a server"),
+                        ),
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Handles the server picked from the menu sent to someone in several modmail servers.
+pub async fn handle_select(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    modmail: &Modmail,
+    interaction: &serenity::ComponentInteraction,
+) -> Result<(), Error> {
+    let Some(message_id) = interaction
+        .data
+        .custom_id
+        .strip_prefix(SELECT_PREFIX)
+        .and_then(|rest| rest.strip_prefix("open:"))
+        .and_then(|id| id.parse::<serenity::MessageId>().ok())
+    else {
+        return Ok(());
+    };
+    let serenity::ComponentInteractionDataKind::StringSelect { values } = &interaction.data.kind
+    else {
+        return Ok(());
+    };
+    let Some(guild_id) = values
+        .first()
+        .and_then(|id| id.parse::<serenity::GuildId>().ok())
+    else {
+        return Ok(());
+    };
+
+    let respond = |content: String| {
+        serenity::CreateInteractionResponse::UpdateMessage(
+            serenity::CreateInteractionResponseMessage::new()
+                .content(content)
+                .components(vec![]),
+        )
+    };
+    // Someone double-clicking shouldn't end up with two tickets.
+    let _lock = modmail.lock_user(interaction.user.id).await;
+    let ticket = match open_ticket_for_user(pool, interaction.user.id).await? {
+        Some(ticket) => ticket,
+        None => {
+            let config = load_config(pool, guild_id).await?;
+            if !config.enabled || !is_member(ctx, guild_id, interaction.user.id).await {
+                interaction
+                    .create_response(
+                        ctx,
+                        respond("❌ That server doesn't take messages through me.".to_string()),
+                    )
+                    .await?;
+                return Ok(());
+            }
+            open_ticket(ctx, pool, guild_id, &interaction.user).await?
+        }
+    };
+    let guild_name = serenity::GuildId::new(ticket.guild_id as u64)
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+    interaction
+        .create_response(
+            ctx,
+            respond(format!("Sending your message to **{}**.", guild_name)),
+        )
+        .await?;
+    if let Ok(original) = interaction.channel_id.message(ctx, message_id).await
+        && !relay_to_thread(ctx, pool, &ticket, &original).await?
+    {
+        interaction
+            .channel_id
+            .say(
+                ctx,
+                "❌ That conversation was closed by the moderators. Send your message again to start a new one.",
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+// Creates the private thread for a new ticket and records it.
+async fn open_ticket(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    user: &serenity::User,
+) -> Result<TicketRow, Error> {
+    let config = load_config(pool, guild_id).await?;
+    let channel_id = config
+        .channel_id
+        .map(|id| serenity::ChannelId::new(id as u64))
+        .ok_or("Modmail has no channel set")?;
+    let thread = channel_id
+        .create_thread(
+            ctx,
+            serenity::CreateThread::new(format!("modmail-{}", user.name))
+                .kind(serenity::ChannelType::PrivateThread)
+                .invitable(false)
+                .audit_log_reason(&format!("Modmail ticket for {}", user.tag())),
+        )
+        .await?;
+
+    let guild_id_db = guild_id.get() as i64;
+    let user_id = user.id.get() as i64;
+    let thread_id = thread.id.get() as i64;
+    let ticket_id = sqlx::query!(
+        "INSERT INTO modmail_tickets (guild_id, user_id, thread_id) VALUES (?, ?, ?)",
+        guild_id_db,
+        user_id,
+        thread_id
+    )
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+
+    let mut intro = serenity::CreateEmbed::new()
+        .title(format!("📬 Modmail ticket #{}", ticket_id))
+        .thumbnail(user.face())
+        .description(format!(
+            "{} ({})\nAccount created <t:{}:R>",
+            user.mention(),
+            user.tag(),
+            user.id.created_at().unix_timestamp()
+        ))
+        .field(
+            "Answering",
+            "`reply <text>` answers with your name, `areply <text>` answers anonymously. \
+             Anything else said here stays between the moderators. `close` when you're done.",
+            false,
+        )
+        .footer(serenity::CreateEmbedFooter::new(format!(
+            "User ID: {}",
+            user.id
+        )))
+        .timestamp(serenity::Timestamp::now())
+        .color(serenity::Color::BLURPLE);
+    if let Ok(member) = guild_id.member(ctx, user.id).await
+        && let Some(joined_at) = member.joined_at
+    {
+        intro = intro.field(
+            "Joined",
+            format!("<t:{}:R>", joined_at.unix_timestamp()),
+            true,
+        );
+    }
+    let mut embeds = vec![intro];
+    if let Some(history) = notes::history_pages(pool, guild_id, user)
+        .await?
+        .into_iter()
+        .next()
+    {
+        embeds.push(history);
+    }
+    thread
+        .id
+        .send_message(ctx, serenity::CreateMessage::new().embeds(embeds))
+        .await?;
+
+    let guild_name = guild_id
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+    user.direct_message(
+        ctx,
+        serenity::CreateMessage::new().content(format!(
+            "📬 You're now talking to the moderators of **{}**. Everything you send me here goes to them until they close the ticket.",
+            guild_name
+        )),
+    )
+    .await?;
+
+    Ok(TicketRow {
+        id: ticket_id,
+        guild_id: guild_id_db,
+        user_id,
+        thread_id,
+        created_at: serenity::Timestamp::now().unix_timestamp(),
+    })
+}
+
+// Copies a DM into the ticket's thread. Returns `false` if the thread is gone, in which
+// case the ticket has been closed and nothing was sent.
+async fn relay_to_thread(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    ticket: &TicketRow,
+    msg: &serenity::Message,
+) -> Result<bool, Error> {
+    let thread_id = serenity::ChannelId::new(ticket.thread_id as u64);
+    let embed = |links: &[String]| {
+        relay_embed(&msg.content, links)
+            .author(serenity::CreateEmbedAuthor::new(msg.author.tag()).icon_url(msg.author.face()))
+            .color(serenity::Color::BLURPLE)
+    };
+    let (files, links) = copy_attachments(&msg.attachments).await;
+    let uploaded = !files.is_empty();
+    let mut sent = thread_id
+        .send_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .embed(embed(&links))
+                .add_files(files),
+        )
+        .await;
+    // The upload can still be refused, e.g. for size. Links always get through.
+    if uploaded && sent.as_ref().is_err_and(|e| !is_unknown_channel(e)) {
+        sent = thread_id
+            .send_message(
+                ctx,
+                serenity::CreateMessage::new().embed(embed(&attachment_links(&msg.attachments))),
+            )
+            .await;
+    }
+    if let Err(e) = &sent
+        && is_unknown_channel(e)
+    {
+        close_abandoned(pool, ticket.id, "The ticket thread was deleted").await?;
+        return Ok(false);
+    }
+    if let Err(e) = sent {
+        msg.channel_id
+            .say(
+                ctx,
+                "❌ I couldn't pass that on to the moderators, please try again later.",
+            )
+            .await?;
+        return Err(e.into());
+    }
+    log_message(
+        pool,
+        ticket.id,
+        &msg.author,
+        false,
+        false,
+        &msg.content,
+        &msg.attachments,
+    )
+    .await?;
+    // Let them know it arrived.
+    let _ = msg.react(ctx, '✅').await;
+    Ok(true)
+}
+
+// Sends a moderator's answer to the ticket's user and echoes it in the thread.
+async fn send_reply(
+    ctx: Context<'_>,
+    text: Option<String>,
+    attachment: Option<serenity::Attachment>,
+    anonymous: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let Some(ticket) = ticket_for_thread(pool, ctx.channel_id()).await? else {
+        ctx.say("❌ This isn't an open modmail thread.").await?;
+        return Ok(());
+    };
+    ctx.defer().await?;
+
+    // Typed replies can carry any number of files on the command message itself.
+    let attachments: Vec<serenity::Attachment> = match ctx {
+        poise::Context::Prefix(prefix) => prefix.msg.attachments.clone(),
+        poise::Context::Application(_) => attachment.into_iter().collect(),
+    };
+    let text = text.unwrap_or_default().trim().to_string();
+    if text.is_empty() && attachments.is_empty() {
+        ctx.say("❌ Give me something to send.").await?;
+        return Ok(());
+    }
+    let (files, links) = copy_attachments(&attachments).await;
+
+    let guild_name = guild_id
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+    let guild_icon = ctx.guild().and_then(|guild| guild.icon_url());
+    let mut author = if anonymous {
+        serenity::CreateEmbedAuthor::new(format!("{} Staff", guild_name))
+    } else {
+        let name = match ctx.author_member().await {
+            Some(member) => member.display_name().to_string(),
+            None => ctx.author().name.clone(),
+        };
+        serenity::CreateEmbedAuthor::new(format!("{} ({} Staff)", name, guild_name))
+            .icon_url(ctx.author().face())
+    };
+    if anonymous && let Some(icon) = guild_icon {
+        author = author.icon_url(icon);
+    }
+    let mut embed = relay_embed(&text, &links)
+        .author(author.clone())
+        .color(serenity::Color::DARK_GREEN);
+
+    let user_id = serenity::UserId::new(ticket.user_id as u64);
+    let uploaded = !files.is_empty();
+    let mut delivered = user_id
+        .direct_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .embed(embed.clone())
+                .add_files(files),
+        )
+        .await;
+    // The upload can still be refused, e.g. for size. Links always get through.
+    if uploaded && delivered.is_err() {
+        embed = relay_embed(&text, &attachment_links(&attachments))
+            .author(author)
+            .color(serenity::Color::DARK_GREEN);
+        delivered = user_id
+            .direct_message(ctx, serenity::CreateMessage::new().embed(embed.clone()))
+            .await;
+    }
+    if delivered.is_err() {
+        ctx.say(
+            "❌ I couldn't DM them. They may have closed their DMs or left every server we share.",
+        )
+        .await?;
+        return Ok(());
+    }
+    log_message(
+        pool,
+        ticket.id,
+        ctx.author(),
+        true,
+        anonymous,
+        &text,
+        &attachments,
+    )
+    .await?;
+
+    let footer = if anonymous {
+        format!("Sent anonymously by {}", ctx.author().tag())
+    } else {
+        format!("Sent by {}", ctx.author().tag())
+    };
+    ctx.send(
+        poise::CreateReply::default().embed(embed.footer(serenity::CreateEmbedFooter::new(footer))),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Answer the modmail ticket in this thread, with your name shown.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_THREADS"
+)]
+pub async fn reply(
+    ctx: Context<'_>,
+    #[description = "A file to send along"] attachment: Option<serenity::Attachment>,
+    #[description = "Your answer"]
+    #[rest]
+    text: Option<String>,
+) -> Result<(), Error> {
+    send_reply(ctx, text, attachment, false).await
+}
+
+/// Answer the modmail ticket in this thread anonymously.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_THREADS"
+)]
+pub async fn areply(
+    ctx: Context<'_>,
+    #[description = "A file to send along"] attachment: Option<serenity::Attachment>,
+    #[description = "Your answer"]
+    #[rest]
+    text: Option<String>,
+) -> Result<(), Error> {
+    send_reply(ctx, text, attachment, true).await
+}
+
+fn format_time(unix: i64) -> String {
+    serenity::Timestamp::from_unix_timestamp(unix)
+        .map(|ts| ts.to_string())
+        .unwrap_or_else(|_| unix.to_string())
+}
+
+// A plain text record of the whole conversation.
+async fn transcript(
+    pool: &Pool<Sqlite>,
+    ticket: &TicketRow,
+    user: &serenity::User,
+    guild_name: &str,
+    closed_by: &serenity::User,
+    reason: &str,
+) -> Result<String, Error> {
+    let messages = sqlx::query_as!(
+        MessageRow,
+        "SELECT author_name, from_staff, anonymous, content, attachments, created_at
+         FROM modmail_messages WHERE ticket_id = ? ORDER BY id",
+        ticket.id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "Modmail ticket #{} with {} ({}) in {}",
+        ticket.id,
+        user.tag(),
+        user.id,
+        guild_name
+    )?;
+    writeln!(out, "Opened: {}", format_time(ticket.created_at))?;
+    writeln!(
+        out,
+        "Closed: {} by {}",
+        serenity::Timestamp::now(),
+        closed_by.tag()
+    )?;
+    if !reason.is_empty() {
+        writeln!(out, "Reason: {}", reason)?;
+    }
+    writeln!(out)?;
+    for message in messages {
+        let who = match (message.from_staff, message.anonymous) {
+            (false, _) => message.author_name,
+            (true, false) => format!("{} (staff)", message.author_name),
+            (true, true) => format!("{} (staff, anonymous)", message.author_name),
+        };
+        writeln!(
+            out,
+            "[{}] {}: {}",
+            format_time(message.created_at),
+            who,
+            message.content
+        )?;
+        for url in message.attachments.lines() {
+            writeln!(out, "    attachment: {}", url)?;
+        }
+    }
+    Ok(out)
+}
+
+/// Close the modmail ticket in this thread and save a transcript.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_THREADS"
+)]
+pub async fn close(
+    ctx: Context<'_>,
+    #[description = "Why it was closed (kept in the transcript, not sent to them)"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let Some(ticket) = ticket_for_thread(pool, ctx.channel_id()).await? else {
+        ctx.say("❌ This isn't an open modmail thread.").await?;
+        return Ok(());
+    };
+    ctx.defer().await?;
+    let reason = reason.unwrap_or_default();
+    let closed_by = ctx.author().id.get() as i64;
+    sqlx::query!(
+        "UPDATE modmail_tickets
+         SET status = 'closed', closed_at = strftime('%s', 'now'), closed_by = ?, close_reason = ?
+         WHERE id = ?",
+        closed_by,
+        reason,
+        ticket.id
+    )
+    .execute(pool)
+    .await?;
+
+    let user = serenity::UserId::new(ticket.user_id as u64)
+        .to_user(ctx)
+        .await?;
+    let guild_name = guild_id
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+    let text = transcript(pool, &ticket, &user, &guild_name, ctx.author(), &reason).await?;
+    let file = || {
+        serenity::CreateAttachment::bytes(
+            text.as_bytes().to_vec(),
+            format!("modmail-{}.txt", ticket.id),
+        )
+    };
+
+    let config = load_config(pool, guild_id).await?;
+    if let Some(log_channel_id) = config.log_channel_id {
+        serenity::ChannelId::new(log_channel_id as u64)
+            .send_message(
+                ctx,
+                serenity::CreateMessage::new()
+                    .content(format!(
+                        "📪 Modmail ticket #{} with {} ({}) closed by {}.",
+                        ticket.id,
+                        user.tag(),
+                        user.id,
+                        ctx.author().tag()
+                    ))
+                    .add_file(file()),
+            )
+            .await?;
+    }
+    // They may have closed their DMs since, the ticket is closed regardless.
+    let _ = user
+        .direct_message(
+            ctx,
+            serenity::CreateMessage::new().content(format!(
+                "📪 Your conversation with the moderators of **{}** has been closed. Message me again any time to start a new one.",
+                guild_name
+            )),
+        )
+        .await;
+
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!("📪 Ticket closed by {}.", ctx.author().mention()))
+            .attachment(file()),
+    )
+    .await?;
+    serenity::ChannelId::new(ticket.thread_id as u64)
+        .edit_thread(ctx, serenity::EditThread::new().archived(true).locked(true))
+        .await?;
+    Ok(())
+}
+
+// Changes the guild's config row, creating it with the defaults if needed.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut ModmailConfigRow),
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let mut config = load_config(pool, guild_id).await?;
+    change(&mut config);
+
+    let guild_id_db = guild_id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO modmail_config (guild_id, enabled, channel_id, log_channel_id)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            channel_id = excluded.channel_id,
+            log_channel_id = excluded.log_channel_id",
+        guild_id_db,
+        config.enabled,
+        config.channel_id,
+        config.log_channel_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let config = load_config(pool, guild_id).await?;
+    let guild_id_db = guild_id.get() as i64;
+    let open = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM modmail_tickets WHERE guild_id = ? AND status = 'open'",
+        guild_id_db
+    )
+    .fetch_one(pool)
+    .await?
+    .count;
+    let channel = |id: Option<i64>| {
+        id.map(|id| format!("<#{}>", id))
+            .unwrap_or_else(|| "Not set".to_string())
+    };
+    let embed = serenity::CreateEmbed::new()
+        .title("Modmail")
+        .field(
+            "Status",
+            if config.enabled {
+                "Enabled"
+            } else {
+                "Disabled"
+            },
+            true,
+        )
+        .field("Tickets", channel(config.channel_id), true)
+        .field("Transcripts", channel(config.log_channel_id), true)
+        .field("Open tickets", open.to_string(), true)
+        .color(serenity::Color::BLURPLE);
+    let mut reply = poise::CreateReply::default().embed(embed);
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Let members reach the moderators by DMing the bot.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("show", "setup", "transcripts", "disable")
+)]
+pub async fn modmail(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the modmail settings.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Turn modmail on, opening tickets as private threads in a channel.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn setup(
+    ctx: Context<'_>,
+    #[description = "Channel to open ticket threads in"] channel: serenity::GuildChannel,
+) -> Result<(), Error> {
+    if channel.kind != serenity::ChannelType::Text {
+        ctx.say("❌ Tickets need a text channel to open private threads in.")
+            .await?;
+        return Ok(());
+    }
+    let channel_id = channel.id.get() as i64;
+    update_config(ctx, |config| {
+        config.enabled = true;
+        config.channel_id = Some(channel_id);
+    })
+    .await?;
+    show_inner(
+        ctx,
+        "Modmail is now enabled. Moderators need Manage Threads to see the tickets.",
+    )
+    .await
+}
+
+/// Choose where transcripts of closed tickets are saved. Leave it out to stop saving them.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn transcripts(
+    ctx: Context<'_>,
+    #[description = "Channel for transcripts"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let channel_id = channel.map(|channel| channel.id.get() as i64);
+    update_config(ctx, |config| config.log_channel_id = channel_id).await?;
+    show_inner(ctx, "Transcript channel updated.").await
+}
+
+/// Stop opening new tickets. Open ones can still be answered and closed.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = false).await?;
+    show_inner(ctx, "Modmail is now disabled.").await
+}
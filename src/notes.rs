@@ -0,0 +1,274 @@
+use crate::moderation;
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use sqlx::{Pool, Sqlite};
+
+const MAX_NOTE_CHARS: usize = 1000;
+
+// Helper struct to map the query result
+struct NoteRow {
+    id: i64,
+    author_id: i64,
+    content: String,
+    created_at: i64,
+}
+
+// Helper struct to map the query result
+struct CaseRow {
+    id: i64,
+    moderator_id: i64,
+    action: String,
+    reason: String,
+    created_at: i64,
+}
+
+async fn fetch_notes(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+) -> Result<Vec<NoteRow>, Error> {
+    let guild_id = guild_id.get() as i64;
+    let user_id = user_id.get() as i64;
+    let notes = sqlx::query_as!(
+        NoteRow,
+        "SELECT id, author_id, content, created_at FROM mod_notes
+         WHERE guild_id = ? AND user_id = ? ORDER BY id DESC",
+        guild_id,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(notes)
+}
+
+fn note_line(note: &NoteRow, max_chars: usize) -> String {
+    format!(
+        "📝 **Note #{}** by <@{}> <t:{}:R>\n└ {}",
+        note.id,
+        note.author_id,
+        note.created_at,
+        moderation::shorten(&note.content, max_chars)
+    )
+}
+
+// Splits `lines` into embeds of `per_page` entries each, numbered in the footer. Keep
+// `per_page` low enough that a page of full-length entries fits in one embed.
+fn into_pages(title: String, lines: Vec<String>, per_page: usize) -> Vec<serenity::CreateEmbed> {
+    let page_count = lines.len().div_ceil(per_page);
+    lines
+        .chunks(per_page)
+        .enumerate()
+        .map(|(i, chunk)| {
+            serenity::CreateEmbed::new()
+                .title(title.clone())
+                .description(chunk.join("\n"))
+                .footer(serenity::CreateEmbedFooter::new(format!(
+                    "Page {}/{}",
+                    i + 1,
+                    page_count
+                )))
+                .color(serenity::Color::DARK_GOLD)
+        })
+        .collect()
+}
+
+/// A user's moderation cases and notes in this guild, newest first, as pages of embeds.
+/// Empty if there is nothing on record.
+pub async fn history_pages(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    user: &serenity::User,
+) -> Result<Vec<serenity::CreateEmbed>, Error> {
+    let notes = fetch_notes(pool, guild_id, user.id).await?;
+    let guild_id_db = guild_id.get() as i64;
+    let user_id = user.id.get() as i64;
+    let cases = sqlx::query_as!(
+        CaseRow,
+        "SELECT id, moderator_id, action, reason, created_at FROM mod_cases
+         WHERE guild_id = ? AND user_id = ? ORDER BY id DESC",
+        guild_id_db,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut entries: Vec<(i64, String)> = cases
+        .iter()
+        .map(|case| {
+            let reason = if case.reason.is_empty() {
+                "No reason provided"
+            } else {
+                &case.reason
+            };
+            let line = format!(
+                "🔨 **Case #{}** {} by <@{}> <t:{}:R>\n└ {}",
+                case.id,
+                case.action,
+                case.moderator_id,
+                case.created_at,
+                moderation::shorten(reason, 200)
+            );
+            (case.created_at, line)
+        })
+        .collect();
+    entries.extend(
+        notes
+            .iter()
+            .map(|note| (note.created_at, note_line(note, 200))),
+    );
+    // Stable, so a case and a note made in the same second keep cases first.
+    entries.sort_by_key(|(created_at, _)| std::cmp::Reverse(*created_at));
+
+    let title = format!(
+        "History for {} ({} cases, {} notes)",
+        user.tag(),
+        cases.len(),
+        notes.len()
+    );
+    Ok(into_pages(
+        title,
+        entries.into_iter().map(|(_, line)| line).collect(),
+        10,
+    ))
+}
+
+// Notes are about someone, never for them.
+async fn refuse_self(ctx: Context<'_>, user: &serenity::User) -> Result<bool, Error> {
+    if user.id == ctx.author().id {
+        ctx.say("❌ You can't look up notes about yourself.")
+            .await?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+// Everything here is slash only: replies to typed commands can't be hidden, and would
+// show notes to anyone who can read the channel, including the member they're about.
+
+/// Keep private notes about members for the other moderators.
+#[poise::command(
+    slash_command,
+    guild_only,
+    required_permissions = "MODERATE_MEMBERS",
+    subcommands("add", "delete"),
+    subcommand_required
+)]
+pub async fn note(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Add a note about a member. Only moderators can see it.
+#[poise::command(slash_command, guild_only, required_permissions = "MODERATE_MEMBERS")]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Who the note is about"] user: serenity::User,
+    #[description = "The note"] text: String,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    ctx.defer_ephemeral().await?;
+    let text = text.trim();
+    let chars = text.chars().count();
+    if chars == 0 || chars > MAX_NOTE_CHARS {
+        ctx.say(format!(
+            "❌ Notes must be between 1 and {} characters.",
+            MAX_NOTE_CHARS
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let guild_id_db = guild_id.get() as i64;
+    let user_id = user.id.get() as i64;
+    let author_id = ctx.author().id.get() as i64;
+    let note = sqlx::query!(
+        "INSERT INTO mod_notes (guild_id, user_id, author_id, content) VALUES (?, ?, ?, ?)",
+        guild_id_db,
+        user_id,
+        author_id,
+        text
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+    ctx.say(format!(
+        "📝 Added note #{} about {}.",
+        note.last_insert_rowid(),
+        user.tag()
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Delete a note by its number.
+#[poise::command(slash_command, guild_only, required_permissions = "MODERATE_MEMBERS")]
+pub async fn delete(ctx: Context<'_>, #[description = "Note number"] id: i64) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    ctx.defer_ephemeral().await?;
+    let guild_id_db = guild_id.get() as i64;
+    let result = sqlx::query!(
+        "DELETE FROM mod_notes WHERE id = ? AND guild_id = ?",
+        id,
+        guild_id_db
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        ctx.say(format!("❌ There is no note #{} in this server.", id))
+            .await?;
+    } else {
+        ctx.say(format!("🗑️ Deleted note #{}.", id)).await?;
+    }
+    Ok(())
+}
+
+/// Show the moderator notes about a member.
+#[poise::command(slash_command, guild_only, required_permissions = "MODERATE_MEMBERS")]
+pub async fn notes(
+    ctx: Context<'_>,
+    #[description = "Whose notes to show"] user: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    ctx.defer_ephemeral().await?;
+    if refuse_self(ctx, &user).await? {
+        return Ok(());
+    }
+    let notes = fetch_notes(&ctx.data().db_pool, guild_id, user.id).await?;
+    if notes.is_empty() {
+        ctx.say(format!("There are no notes about {}.", user.tag()))
+            .await?;
+        return Ok(());
+    }
+    let title = format!("Notes about {} ({})", user.tag(), notes.len());
+    let lines = notes
+        .iter()
+        .map(|note| note_line(note, MAX_NOTE_CHARS))
+        .collect();
+    moderation::paginate(ctx, into_pages(title, lines, 3)).await
+}
+
+/// Show a member's moderation cases and notes.
+#[poise::command(slash_command, guild_only, required_permissions = "MODERATE_MEMBERS")]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "Whose history to show"] user: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    ctx.defer_ephemeral().await?;
+    if refuse_self(ctx, &user).await? {
+        return Ok(());
+    }
+    let pages = history_pages(&ctx.data().db_pool, guild_id, &user).await?;
+    if pages.is_empty() {
+        ctx.say(format!("{} has a clean record.", user.tag()))
+            .await?;
+        return Ok(());
+    }
+    moderation::paginate(ctx, pages).await
+}
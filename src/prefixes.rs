@@ -0,0 +1,471 @@
+use crate::settings;
+use crate::{Context, Data, Error};
+use poise::serenity_prelude as serenity;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// The prefix used where nobody has set one.
+pub const DEFAULT: &str = "td!";
+/// How many prefixes a guild or user can have at once.
+pub const MAX_PREFIXES: usize = 5;
+/// The longest a prefix can be, in characters.
+pub const MAX_CHARS: usize = 10;
+/// Characters Discord would turn into formatting or a mention.
+const FORBIDDEN: &[char] = &['`', '*', '_', '~', '|', '>', '\\', '<', '@', '#'];
+
+/// Checks a single prefix, explaining what's wrong with it.
+pub fn check(prefix: &str) -> Result<(), String> {
+    let chars = prefix.chars().count();
+    if chars == 0 || chars > MAX_CHARS {
+        return Err(format!(
+            "Prefixes must be between 1 and {} characters long.",
+            MAX_CHARS
+        ));
+    }
+    if prefix.chars().any(char::is_whitespace) {
+        return Err("Prefixes can't contain spaces.".to_string());
+    }
+    if let Some(c) = prefix.chars().find(|c| FORBIDDEN.contains(c)) {
+        return Err(format!(
+            "Prefixes can't contain `{}`, Discord uses it for formatting or mentions.",
+            c
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a space separated list of prefixes, dropping repeats.
+pub fn parse_list(input: &str) -> Result<Vec<String>, String> {
+    let mut prefixes: Vec<String> = Vec::new();
+    for prefix in input.split_whitespace() {
+        check(prefix)?;
+        if !prefixes.iter().any(|known| known == prefix) {
+            prefixes.push(prefix.to_string());
+        }
+    }
+    if prefixes.is_empty() {
+        return Err("Give at least one prefix.".to_string());
+    }
+    if prefixes.len() > MAX_PREFIXES {
+        return Err(format!("You can have at most {} prefixes.", MAX_PREFIXES));
+    }
+    Ok(prefixes)
+}
+
+// Stored lists are space separated, which `check` keeps unambiguous.
+fn split(stored: &str) -> Vec<String> {
+    let prefixes: Vec<String> = stored.split_whitespace().map(str::to_string).collect();
+    if prefixes.is_empty() {
+        vec![DEFAULT.to_string()]
+    } else {
+        prefixes
+    }
+}
+
+// The longest prefix `content` starts with, so `!!` wins over `!`.
+fn longest_match<'a>(content: &'a str, prefixes: &[String]) -> Option<(&'a str, &'a str)> {
+    prefixes
+        .iter()
+        .filter(|prefix| content.starts_with(prefix.as_str()))
+        .max_by_key(|prefix| prefix.len())
+        .map(|prefix| content.split_at(prefix.len()))
+}
+
+/// Where a set of prefixes applies: a guild, or one user's DMs with the bot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Scope {
+    Guild(serenity::GuildId),
+    User(serenity::UserId),
+}
+
+impl Scope {
+    fn of(ctx: Context<'_>) -> Self {
+        match ctx.guild_id() {
+            Some(guild_id) => Scope::Guild(guild_id),
+            None => Scope::User(ctx.author().id),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Scope::Guild(_) => "this server",
+            Scope::User(_) => "your DMs",
+        }
+    }
+}
+
+// The prefixes a user has set for their DMs, straight from the database.
+async fn stored_dm(
+    pool: &Pool<Sqlite>,
+    user_id: serenity::UserId,
+) -> Result<Option<String>, Error> {
+    let user_id = user_id.get() as i64;
+    Ok(sqlx::query!(
+        "SELECT prefixes FROM user_prefixes WHERE user_id = ?",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| row.prefixes))
+}
+
+/// The prefixes that apply in a guild, from its settings.
+pub async fn guild(data: &Data, guild_id: serenity::GuildId) -> Result<Vec<String>, Error> {
+    let stored = data
+        .settings
+        .get_set(&data.db_pool, guild_id, &settings::PREFIXES)
+        .await?;
+    Ok(split(stored.as_deref().unwrap_or(DEFAULT)))
+}
+
+/// Every user's DM prefixes, so DMs don't query the database for each message. Guild
+/// prefixes are cached with the rest of the guild's settings. Users on the default are
+/// cached too, sharing one list.
+pub struct DmPrefixCache {
+    users: RwLock<HashMap<serenity::UserId, Arc<[String]>>>,
+    default: Arc<[String]>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DmPrefixCache {
+    /// Loads every stored prefix list, so only users on the default miss at first.
+    pub async fn load(pool: &Pool<Sqlite>) -> Result<Self, Error> {
+        let cache = DmPrefixCache {
+            users: RwLock::default(),
+            default: Arc::from([DEFAULT.to_string()]),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        };
+        let rows = sqlx::query!("SELECT user_id, prefixes FROM user_prefixes")
+            .fetch_all(pool)
+            .await?;
+        let mut users = cache.users.write().unwrap();
+        for row in rows {
+            let user_id = serenity::UserId::new(row.user_id as u64);
+            users.insert(user_id, cache.list(Some(&row.prefixes)));
+        }
+        drop(users);
+        Ok(cache)
+    }
+
+    fn list(&self, stored: Option<&str>) -> Arc<[String]> {
+        match stored.map(split) {
+            Some(prefixes) if prefixes.as_slice() != &*self.default => Arc::from(prefixes),
+            _ => self.default.clone(),
+        }
+    }
+
+    /// The prefixes a user has set for their DMs, or the default.
+    pub async fn get(
+        &self,
+        pool: &Pool<Sqlite>,
+        user_id: serenity::UserId,
+    ) -> Result<Arc<[String]>, Error> {
+        if let Some(prefixes) = self.users.read().unwrap().get(&user_id) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(prefixes.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let prefixes = self.list(stored_dm(pool, user_id).await?.as_deref());
+        // A change made while this was loading has already been put in, keep it.
+        Ok(self
+            .users
+            .write()
+            .unwrap()
+            .entry(user_id)
+            .or_insert(prefixes)
+            .clone())
+    }
+
+    fn update(&self, user_id: serenity::UserId, prefixes: Option<&[String]>) {
+        let prefixes = match prefixes {
+            Some(prefixes) => self.list(Some(&prefixes.join(" "))),
+            None => self.default.clone(),
+        };
+        self.users.write().unwrap().insert(user_id, prefixes);
+    }
+
+    /// How the cache is doing, for `ping`.
+    pub fn stats(&self) -> String {
+        let users = self.users.read().unwrap();
+        let defaults = users
+            .values()
+            .filter(|prefixes| Arc::ptr_eq(prefixes, &self.default))
+            .count();
+        format!(
+            "{} hits, {} misses, {} cached ({} default)",
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            users.len(),
+            defaults
+        )
+    }
+}
+
+/// Splits the prefix off a message for the framework. Mentions are handled by the
+/// framework itself.
+pub async fn strip<'a>(
+    _ctx: &'a serenity::Context,
+    msg: &'a serenity::Message,
+    data: &'a Data,
+) -> Result<Option<(&'a str, &'a str)>, Error> {
+    let prefixes = match msg.guild_id {
+        Some(guild_id) => guild(data, guild_id).await?,
+        None => data
+            .dm_prefixes
+            .get(&data.db_pool, msg.author.id)
+            .await?
+            .to_vec(),
+    };
+    Ok(longest_match(&msg.content, &prefixes))
+}
+
+// The scope's own prefixes, or `None` if it uses the default.
+async fn load(ctx: Context<'_>, scope: Scope) -> Result<Option<Vec<String>>, Error> {
+    let data = ctx.data();
+    let stored = match scope {
+        Scope::Guild(guild_id) => {
+            data.settings
+                .get_set(&data.db_pool, guild_id, &settings::PREFIXES)
+                .await?
+        }
+        Scope::User(user_id) => stored_dm(&data.db_pool, user_id).await?,
+    };
+    Ok(stored.map(|stored| split(&stored)))
+}
+
+// Replaces the scope's prefixes, or goes back to the default with `None`.
+async fn store(ctx: Context<'_>, scope: Scope, prefixes: Option<&[String]>) -> Result<(), Error> {
+    let pool = &ctx.data().db_pool;
+    let joined = prefixes.map(|prefixes| prefixes.join(" "));
+    match (scope, joined) {
+        (Scope::Guild(guild_id), Some(joined)) => {
+            ctx.data()
+                .settings
+                .set(pool, guild_id, &settings::PREFIXES, &joined)
+                .await?
+        }
+        (Scope::Guild(guild_id), None) => {
+            ctx.data()
+                .settings
+                .reset(pool, guild_id, &settings::PREFIXES)
+                .await?
+        }
+        (Scope::User(user_id), Some(joined)) => {
+            let user_id_db = user_id.get() as i64;
+            sqlx::query!(
+                "INSERT INTO user_prefixes (user_id, prefixes) VALUES (?, ?)
+                 ON CONFLICT (user_id) DO UPDATE SET prefixes = excluded.prefixes",
+                user_id_db,
+                joined
+            )
+            .execute(pool)
+            .await?;
+            ctx.data().dm_prefixes.update(user_id, prefixes);
+        }
+        (Scope::User(user_id), None) => {
+            let user_id_db = user_id.get() as i64;
+            sqlx::query!("DELETE FROM user_prefixes WHERE user_id = ?", user_id_db)
+                .execute(pool)
+                .await?;
+            ctx.data().dm_prefixes.update(user_id, None);
+        }
+    }
+    Ok(())
+}
+
+/// Replaces a guild's prefixes. They must have come from [`parse_list`].
+pub async fn set_guild(
+    ctx: Context<'_>,
+    guild_id: serenity::GuildId,
+    prefixes: &[String],
+) -> Result<(), Error> {
+    store(ctx, Scope::Guild(guild_id), Some(prefixes)).await
+}
+
+/// The prefixes as inline code, separated by commas.
+pub fn format_list(prefixes: &[String]) -> String {
+    prefixes
+        .iter()
+        .map(|prefix| format!("`{}`", prefix))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let scope = Scope::of(ctx);
+    let shown = match load(ctx, scope).await? {
+        Some(prefixes) => format_list(&prefixes),
+        None => format!("`{}` (default)", DEFAULT),
+    };
+    let mut message = format!("Prefixes in {}: {}", scope.name(), shown);
+    if !content.is_empty() {
+        message = format!("{}\n{}", content, message);
+    }
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// Show or change the command prefixes. In DMs these are your own.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("show", "set", "add", "remove", "reset")
+)]
+pub async fn prefix(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the command prefixes.
+#[poise::command(slash_command, prefix_command)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Replace the command prefixes, separated by spaces.
+#[poise::command(slash_command, prefix_command, required_permissions = "MANAGE_GUILD")]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "The new prefixes, separated by spaces"]
+    #[rest]
+    prefixes: String,
+) -> Result<(), Error> {
+    let prefixes = match parse_list(&prefixes) {
+        Ok(prefixes) => prefixes,
+        Err(why) => {
+            ctx.say(format!("❌ {}", why)).await?;
+            return Ok(());
+        }
+    };
+    store(ctx, Scope::of(ctx), Some(&prefixes)).await?;
+    show_inner(ctx, "✅ Prefixes updated.").await
+}
+
+/// Add a command prefix.
+#[poise::command(slash_command, prefix_command, required_permissions = "MANAGE_GUILD")]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "The prefix to add"] prefix: String,
+) -> Result<(), Error> {
+    // Checked on its own first, or a slash argument like `a b` would be split in two below.
+    if let Err(why) = check(&prefix) {
+        ctx.say(format!("❌ {}", why)).await?;
+        return Ok(());
+    }
+    let scope = Scope::of(ctx);
+    let mut prefixes = load(ctx, scope)
+        .await?
+        .unwrap_or_else(|| vec![DEFAULT.to_string()]);
+    if prefixes.contains(&prefix) {
+        ctx.say(format!("`{}` is already a prefix.", prefix))
+            .await?;
+        return Ok(());
+    }
+    prefixes.push(prefix);
+    let prefixes = match parse_list(&prefixes.join(" ")) {
+        Ok(prefixes) => prefixes,
+        Err(why) => {
+            ctx.say(format!("❌ {}", why)).await?;
+            return Ok(());
+        }
+    };
+    store(ctx, scope, Some(&prefixes)).await?;
+    show_inner(ctx, "✅ Prefix added.").await
+}
+
+/// Remove a command prefix.
+#[poise::command(slash_command, prefix_command, required_permissions = "MANAGE_GUILD")]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The prefix to remove"] prefix: String,
+) -> Result<(), Error> {
+    let scope = Scope::of(ctx);
+    let mut prefixes = load(ctx, scope)
+        .await?
+        .unwrap_or_else(|| vec![DEFAULT.to_string()]);
+    if !prefixes.contains(&prefix) {
+        ctx.say(format!("❌ `{}` isn't a prefix.", prefix)).await?;
+        return Ok(());
+    }
+    if prefixes.len() == 1 {
+        ctx.say("❌ That's the only prefix. Use `prefix reset` to go back to the default.")
+            .await?;
+        return Ok(());
+    }
+    prefixes.retain(|known| *known != prefix);
+    store(ctx, scope, Some(&prefixes)).await?;
+    show_inner(ctx, "✅ Prefix removed.").await
+}
+
+/// Go back to the default command prefix.
+#[poise::command(slash_command, prefix_command, required_permissions = "MANAGE_GUILD")]
+pub async fn reset(ctx: Context<'_>) -> Result<(), Error> {
+    store(ctx, Scope::of(ctx), None).await?;
+    show_inner(ctx, "✅ Prefixes reset.").await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_prefixes() {
+        assert!(check("!").is_ok());
+        assert!(check("td!").is_ok());
+        // Characters, not bytes: ten of these are 20 bytes.
+        assert!(check("éééééééééé").is_ok());
+        assert!(check("ééééééééééé").is_err());
+        assert!(check("").is_err());
+        assert!(check("t d").is_err());
+        assert!(check("**").is_err());
+        assert!(check("<@1>").is_err());
+        assert!(check("#").is_err());
+
+        assert_eq!(
+            parse_list(" ! ? ! "),
+            Ok(vec!["!".to_string(), "?".to_string()])
+        );
+        assert!(parse_list("   ").is_err());
+        assert!(parse_list("a b c d e f").is_err());
+    }
+
+    #[test]
+    fn strips_the_longest_prefix() {
+        let prefixes = vec!["!".to_string(), "!!".to_string()];
+        assert_eq!(longest_match("!!ping", &prefixes), Some(("!!", "ping")));
+        assert_eq!(longest_match("!ping", &prefixes), Some(("!", "ping")));
+        assert_eq!(longest_match("ping", &prefixes), None);
+        assert_eq!(split(""), vec![DEFAULT.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn caches_dm_prefixes_and_defaults() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        sqlx::query("INSERT INTO user_prefixes (user_id, prefixes) VALUES (1, '! ?')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let cache = DmPrefixCache::load(&pool).await.unwrap();
+        let custom = serenity::UserId::new(1);
+        let default = serenity::UserId::new(2);
+        assert_eq!(&*cache.get(&pool, custom).await.unwrap(), ["!", "?"]);
+        assert_eq!(&*cache.get(&pool, default).await.unwrap(), [DEFAULT]);
+        // The default is remembered rather than looked up again.
+        assert_eq!(&*cache.get(&pool, default).await.unwrap(), [DEFAULT]);
+        assert_eq!(cache.stats(), "2 hits, 1 misses, 2 cached (1 default)");
+
+        cache.update(default, Some(&["$".to_string()]));
+        assert_eq!(&*cache.get(&pool, default).await.unwrap(), ["$"]);
+        cache.update(custom, None);
+        assert_eq!(&*cache.get(&pool, custom).await.unwrap(), [DEFAULT]);
+    }
+}
@@ -0,0 +1,354 @@
+use crate::Error;
+use poise::serenity_prelude::{self as serenity, GetMessages};
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Most messages a single purge may delete.
+pub const PURGE_LIMIT: u16 = 5000;
+/// Most messages a single purge will look through while searching for matches.
+const SCAN_LIMIT: usize = 10_000;
+/// Discord refuses to bulk delete anything older than two weeks. Leave a little slack
+/// so a message doesn't age past the limit between fetching and deleting it.
+const BULK_DELETE_MAX_AGE: i64 = 14 * 24 * 60 * 60 - 60;
+
+static LINK_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(https?://\S+|discord(\.gg|app\.com/invite|\.com/invite)/\S+)").unwrap()
+});
+
+/// Narrows a purge down to one kind of message.
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum PurgeKind {
+    #[name = "bots"]
+    Bots,
+    #[name = "attachments"]
+    Attachments,
+    #[name = "links"]
+    Links,
+    #[name = "embeds"]
+    Embeds,
+}
+
+/// Which messages a purge should remove. Every filter that is set must match.
+#[derive(Default)]
+pub struct PurgeFilter {
+    pub user: Option<serenity::UserId>,
+    pub kind: Option<PurgeKind>,
+    pub pattern: Option<Regex>,
+    /// Stored lowercased, matched case-insensitively.
+    pub contains: Option<String>,
+    /// Only messages sent at or after this unix time.
+    pub after: Option<i64>,
+    /// Only messages sent at or before this unix time.
+    pub before: Option<i64>,
+}
+
+impl PurgeFilter {
+    pub fn matches(&self, msg: &serenity::Message) -> bool {
+        // Pinned messages are never purged, they were pinned for a reason.
+        if msg.pinned {
+            return false;
+        }
+        if self.user.is_some_and(|user| msg.author.id != user) {
+            return false;
+        }
+        let kind_matches = match self.kind {
+            Some(PurgeKind::Bots) => msg.author.bot,
+            Some(PurgeKind::Attachments) => !msg.attachments.is_empty(),
+            Some(PurgeKind::Links) => LINK_RE.is_match(&msg.content),
+            Some(PurgeKind::Embeds) => !msg.embeds.is_empty(),
+            None => true,
+        };
+        if !kind_matches {
+            return false;
+        }
+        if self
+            .pattern
+            .as_ref()
+            .is_some_and(|pattern| !pattern.is_match(&msg.content))
+        {
+            return false;
+        }
+        if self
+            .contains
+            .as_ref()
+            .is_some_and(|needle| !msg.content.to_lowercase().contains(needle))
+        {
+            return false;
+        }
+        let sent = msg.timestamp.unix_timestamp();
+        if self.after.is_some_and(|after| sent < after) {
+            return false;
+        }
+        if self.before.is_some_and(|before| sent > before) {
+            return false;
+        }
+        true
+    }
+}
+
+/// Walks back through the channel history from `start`, collecting up to `amount`
+/// messages that match `filter`.
+pub async fn collect(
+    http: &serenity::Http,
+    channel_id: serenity::ChannelId,
+    start: serenity::MessageId,
+    filter: &PurgeFilter,
+    amount: usize,
+) -> Result<Vec<serenity::Message>, Error> {
+    let mut matched = Vec::new();
+    let mut scanned = 0;
+    let mut last_message_id = start;
+
+    while matched.len() < amount && scanned < SCAN_LIMIT {
+        let messages = channel_id
+            .messages(http, GetMessages::new().limit(100).before(last_message_id))
+            .await?;
+        let Some(oldest) = messages.last() else {
+            break; // Reached the start of the channel
+        };
+        last_message_id = oldest.id;
+        scanned += messages.len();
+
+        // History comes newest first, so once we're past the window there's nothing left.
+        let past_window = filter
+            .after
+            .is_some_and(|after| oldest.timestamp.unix_timestamp() < after);
+
+        for msg in messages {
+            if matched.len() >= amount {
+                break;
+            }
+            if filter.matches(&msg) {
+                matched.push(msg);
+            }
+        }
+
+        if past_window {
+            break;
+        }
+    }
+
+    Ok(matched)
+}
+
+/// Builds a short sample of `messages` for a preview, and a full listing for export.
+pub fn describe(messages: &[serenity::Message], sample_size: usize) -> (String, String) {
+    let line = |msg: &serenity::Message, max_chars: Option<usize>| {
+        let mut content = msg.content.replace('\n', " ");
+        if let Some(max) = max_chars
+            && content.chars().count() > max
+        {
+            content = content.chars().take(max).collect::<String>() + "…";
+        }
+        let mut line = format!(
+            "[{}] {} ({}): {}",
+            msg.timestamp, msg.author.name, msg.author.id, content
+        );
+        if max_chars.is_some() {
+            // Attachment URLs are long, the preview only needs to know they're there.
+            if !msg.attachments.is_empty() {
+                line.push_str(&format!(" [{} attachment(s)]", msg.attachments.len()));
+            }
+        } else {
+            for attachment in &msg.attachments {
+                line.push_str(&format!(" <{}>", attachment.url));
+            }
+        }
+        line
+    };
+
+    let sample = messages
+        .iter()
+        .take(sample_size)
+        .map(|msg| line(msg, Some(80)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let export = messages
+        .iter()
+        .map(|msg| line(msg, None))
+        .collect::<Vec<_>>()
+        .join("\n");
+    (sample, export)
+}
+
+/// How a purge went, split by the way the messages had to be deleted.
+#[derive(Default)]
+pub struct DeleteStats {
+    pub bulk: usize,
+    pub single: usize,
+    pub failed: usize,
+}
+
+impl DeleteStats {
+    pub fn deleted(&self) -> usize {
+        self.bulk + self.single
+    }
+}
+
+/// Deletes the given messages, bulk deleting what Discord allows and falling back to
+/// one request per message for anything older than two weeks.
+pub async fn delete(
+    http: &serenity::Http,
+    channel_id: serenity::ChannelId,
+    messages: &[serenity::Message],
+) -> Result<DeleteStats, Error> {
+    let now = serenity::Timestamp::now().unix_timestamp();
+    let (recent, old): (Vec<_>, Vec<_>) = messages
+        .iter()
+        .partition(|msg| now - msg.timestamp.unix_timestamp() < BULK_DELETE_MAX_AGE);
+
+    let mut stats = DeleteStats::default();
+
+    for chunk in recent.chunks(100) {
+        let ids: Vec<_> = chunk.iter().map(|msg| msg.id).collect();
+        channel_id.delete_messages(http, &ids).await?;
+        stats.bulk += ids.len();
+    }
+
+    for msg in old {
+        // A message that was already deleted by someone else isn't worth aborting over.
+        match channel_id.delete_message(http, msg.id).await {
+            Ok(()) => stats.single += 1,
+            Err(_) => stats.failed += 1,
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::serenity::json::{from_value, json};
+
+    const ALICE: serenity::UserId = serenity::UserId::new(1);
+    const BOB: serenity::UserId = serenity::UserId::new(2);
+
+    fn message(author: serenity::UserId, content: &str, sent: i64) -> serenity::Message {
+        let mut msg = serenity::Message::default();
+        msg.author.id = author;
+        msg.content = content.to_string();
+        msg.timestamp = serenity::Timestamp::from_unix_timestamp(sent).unwrap();
+        msg
+    }
+
+    fn attachment() -> serenity::Attachment {
+        from_value(json!({
+            "id": "1",
+            "filename": "cat.png",
+            "size": 10,
+            "url": "https://cdn.discordapp.com/cat.png",
+            "proxy_url": "https://media.discordapp.net/cat.png",
+        }))
+        .unwrap()
+    }
+
+    fn kind(kind: PurgeKind) -> PurgeFilter {
+        PurgeFilter {
+            kind: Some(kind),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matches_everything_but_pins_by_default() {
+        let filter = PurgeFilter::default();
+        let mut msg = message(ALICE, "hi", 1000);
+        assert!(filter.matches(&msg));
+        msg.pinned = true;
+        assert!(!filter.matches(&msg));
+    }
+
+    #[test]
+    fn filters_by_user() {
+        let filter = PurgeFilter {
+            user: Some(ALICE),
+            ..Default::default()
+        };
+        assert!(filter.matches(&message(ALICE, "hi", 1000)));
+        assert!(!filter.matches(&message(BOB, "hi", 1000)));
+    }
+
+    #[test]
+    fn filters_by_kind() {
+        let mut bot = message(ALICE, "beep", 1000);
+        bot.author.bot = true;
+        let human = message(BOB, "hello", 1000);
+        assert!(kind(PurgeKind::Bots).matches(&bot));
+        assert!(!kind(PurgeKind::Bots).matches(&human));
+
+        let mut with_file = message(ALICE, "", 1000);
+        with_file.attachments.push(attachment());
+        assert!(kind(PurgeKind::Attachments).matches(&with_file));
+        assert!(!kind(PurgeKind::Attachments).matches(&human));
+
+        for link in [
+            "see https://example.com",
+            "HTTP://EXAMPLE.COM",
+            "join discord.gg/abc",
+            "discord.com/invite/abc",
+        ] {
+            assert!(
+                kind(PurgeKind::Links).matches(&message(ALICE, link, 1000)),
+                "{}",
+                link
+            );
+        }
+        assert!(!kind(PurgeKind::Links).matches(&message(ALICE, "example dot com", 1000)));
+
+        let mut embedded = message(ALICE, "", 1000);
+        embedded.embeds.push(serenity::Embed::default());
+        assert!(kind(PurgeKind::Embeds).matches(&embedded));
+        assert!(!kind(PurgeKind::Embeds).matches(&human));
+    }
+
+    #[test]
+    fn filters_by_regex_and_contains() {
+        let regex = PurgeFilter {
+            pattern: Some(Regex::new(r"^\d+$").unwrap()),
+            ..Default::default()
+        };
+        assert!(regex.matches(&message(ALICE, "12345", 1000)));
+        assert!(!regex.matches(&message(ALICE, "12a45", 1000)));
+
+        // `contains` is stored lowercased and matches any case.
+        let contains = PurgeFilter {
+            contains: Some("spam".to_string()),
+            ..Default::default()
+        };
+        assert!(contains.matches(&message(ALICE, "buy SPAM now", 1000)));
+        assert!(!contains.matches(&message(ALICE, "buy eggs now", 1000)));
+    }
+
+    #[test]
+    fn filters_by_time_window() {
+        let filter = PurgeFilter {
+            after: Some(1000),
+            before: Some(2000),
+            ..Default::default()
+        };
+        assert!(filter.matches(&message(ALICE, "hi", 1000)));
+        assert!(filter.matches(&message(ALICE, "hi", 2000)));
+        assert!(!filter.matches(&message(ALICE, "hi", 999)));
+        assert!(!filter.matches(&message(ALICE, "hi", 2001)));
+    }
+
+    #[test]
+    fn every_filter_must_match() {
+        let filter = PurgeFilter {
+            user: Some(ALICE),
+            kind: Some(PurgeKind::Links),
+            pattern: Some(Regex::new("free").unwrap()),
+            contains: Some("nitro".to_string()),
+            after: Some(1000),
+            before: None,
+        };
+        let spam = "free Nitro at https://example.com";
+        assert!(filter.matches(&message(ALICE, spam, 1500)));
+        assert!(!filter.matches(&message(BOB, spam, 1500)));
+        assert!(!filter.matches(&message(ALICE, "free Nitro, no link", 1500)));
+        assert!(!filter.matches(&message(ALICE, "Nitro at https://example.com", 1500)));
+        assert!(!filter.matches(&message(ALICE, "free stuff at https://example.com", 1500)));
+        assert!(!filter.matches(&message(ALICE, spam, 500)));
+    }
+}
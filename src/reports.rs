@@ -0,0 +1,637 @@
+use crate::antinuke;
+use crate::moderation::{self, Hierarchy, ModAction, reply_ephemeral};
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use sqlx::{Pool, Sqlite};
+
+/// Prefix of the custom ids on report queue buttons, routed here by the event handler.
+pub const BUTTON_PREFIX: &str = "report:";
+const MAX_REASON_CHARS: usize = 500;
+
+// Helper struct to map the query result
+struct ReportConfigRow {
+    channel_id: Option<i64>,
+    timeout_secs: i64,
+}
+
+// Helper struct to map the query result
+struct ReportRow {
+    id: i64,
+    reporter_id: i64,
+    target_id: i64,
+    reason: String,
+}
+
+// Helper struct to map the query result
+struct OpenReportRow {
+    id: i64,
+    target_id: i64,
+    queue_channel_id: Option<i64>,
+    queue_message_id: Option<i64>,
+    created_at: i64,
+}
+
+async fn load_config(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<ReportConfigRow, Error> {
+    let guild_id = guild_id.get() as i64;
+    let config = sqlx::query_as!(
+        ReportConfigRow,
+        "SELECT channel_id, timeout_secs FROM report_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(ReportConfigRow {
+        channel_id: None,
+        timeout_secs: 60 * 60,
+    });
+    Ok(config)
+}
+
+#[derive(Debug, poise::Modal)]
+#[name = "Report message"]
+struct ReportModal {
+    #[name = "What's wrong with this message?"]
+    #[placeholder = "Tell the moderators why you're reporting it"]
+    #[paragraph]
+    #[max_length = 500]
+    reason: String,
+}
+
+// Stores a report and posts it to the moderator queue, replying to the reporter either way.
+async fn file_report(
+    ctx: Context<'_>,
+    target: &serenity::User,
+    reason: &str,
+    message: Option<&serenity::Message>,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let reply = |content: &str| {
+        poise::CreateReply::default()
+            .content(content)
+            .ephemeral(true)
+    };
+    if target.id == ctx.author().id {
+        ctx.send(reply("❌ You can't report yourself.")).await?;
+        return Ok(());
+    }
+    if target.bot {
+        ctx.send(reply("❌ Bots can't be reported.")).await?;
+        return Ok(());
+    }
+    let reason = reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_CHARS {
+        ctx.send(reply(&format!(
+            "❌ The reason must be between 1 and {} characters.",
+            MAX_REASON_CHARS
+        )))
+        .await?;
+        return Ok(());
+    }
+
+    let pool = &ctx.data().db_pool;
+    let config = load_config(pool, guild_id).await?;
+    let Some(queue_channel_id) = config
+        .channel_id
+        .map(|id| serenity::ChannelId::new(id as u64))
+    else {
+        ctx.send(reply(
+            "❌ Reports aren't set up in this server, contact a moderator directly.",
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    let guild_id_db = guild_id.get() as i64;
+    let reporter_id = ctx.author().id.get() as i64;
+    let target_id = target.id.get() as i64;
+    let channel_id = message.map(|msg| msg.channel_id.get() as i64);
+    let message_id = message.map(|msg| msg.id.get() as i64);
+    // One open report per reporter and message (or user) is plenty.
+    let duplicate = sqlx::query!(
+        "SELECT id FROM reports
+         WHERE guild_id = ? AND reporter_id = ? AND target_id = ? AND status = 'open'
+            AND message_id IS ?",
+        guild_id_db,
+        reporter_id,
+        target_id,
+        message_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if duplicate.is_some() {
+        ctx.send(reply(
+            "You've already reported this and the moderators haven't got to it yet.",
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let report_id = sqlx::query!(
+        "INSERT INTO reports (guild_id, reporter_id, target_id, channel_id, message_id, reason)
+         VALUES (?, ?, ?, ?, ?, ?)",
+        guild_id_db,
+        reporter_id,
+        target_id,
+        channel_id,
+        message_id,
+        reason
+    )
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+
+    let mut embed = serenity::CreateEmbed::new()
+        .title(format!("🚩 Report #{}", report_id))
+        .field(
+            "Reported user",
+            format!("{} ({})", target.mention(), target.tag()),
+            true,
+        )
+        .field("Reported by", ctx.author().mention().to_string(), true)
+        .field("Reason", reason, false)
+        .footer(serenity::CreateEmbedFooter::new(format!(
+            "User ID: {}",
+            target.id
+        )))
+        .timestamp(serenity::Timestamp::now())
+        .color(serenity::Color::ORANGE);
+    if let Some(msg) = message {
+        let content = if msg.content.is_empty() {
+            "*No text*".to_string()
+        } else {
+            // Leaves room for the link in a 1024 character field.
+            moderation::shorten(&msg.content, 900)
+        };
+        embed = embed.field(
+            "Message",
+            format!("{}\n[Jump to message]({})", content, msg.link()),
+            false,
+        );
+    }
+    let button = |action: &str, label: &str, style| {
+        serenity::CreateButton::new(format!("{}{}:{}", BUTTON_PREFIX, action, report_id))
+            .label(label)
+            .style(style)
+    };
+    let posted = queue_channel_id
+        .send_message(
+            ctx,
+            serenity::CreateMessage::new().embed(embed).components(vec![
+                serenity::CreateActionRow::Buttons(vec![
+                    button("dismiss", "Dismiss", serenity::ButtonStyle::Secondary),
+                    button("warn", "Warn", serenity::ButtonStyle::Primary),
+                    button("timeout", "Timeout", serenity::ButtonStyle::Primary),
+                    button("ban", "Ban", serenity::ButtonStyle::Danger),
+                ]),
+            ]),
+        )
+        .await;
+    let posted = match posted {
+        Ok(posted) => posted,
+        Err(e) => {
+            sqlx::query!("DELETE FROM reports WHERE id = ?", report_id)
+                .execute(pool)
+                .await?;
+            return Err(e.into());
+        }
+    };
+
+    let queue_channel_db = posted.channel_id.get() as i64;
+    let queue_message_db = posted.id.get() as i64;
+    sqlx::query!(
+        "UPDATE reports SET queue_channel_id = ?, queue_message_id = ? WHERE id = ?",
+        queue_channel_db,
+        queue_message_db,
+        report_id
+    )
+    .execute(pool)
+    .await?;
+    ctx.send(reply(&format!(
+        "✅ Thanks, your report (#{}) has been sent to the moderators. I'll DM you when it's been dealt with.",
+        report_id
+    )))
+    .await?;
+    Ok(())
+}
+
+/// Report a message to the moderators
+#[poise::command(context_menu_command = "Report message", guild_only)]
+pub async fn report_message(
+    ctx: Context<'_>,
+    #[description = "Message to report"] message: serenity::Message,
+) -> Result<(), Error> {
+    let poise::Context::Application(app_ctx) = ctx else {
+        return Ok(());
+    };
+    let Some(form) = poise::execute_modal(app_ctx, None::<ReportModal>, None).await? else {
+        return Ok(());
+    };
+    file_report(ctx, &message.author, &form.reason, Some(&message)).await
+}
+
+/// Report a member to the moderators
+#[poise::command(slash_command, guild_only)]
+pub async fn report(
+    ctx: Context<'_>,
+    #[description = "Who you're reporting"] user: serenity::User,
+    #[description = "What they did"]
+    #[max_length = 500]
+    reason: String,
+) -> Result<(), Error> {
+    file_report(ctx, &user, &reason, None).await
+}
+
+// Undoes a claim on a report when the action behind it failed.
+async fn reopen(pool: &Pool<Sqlite>, report_id: i64) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE reports SET status = 'open', action = NULL, handled_by = NULL, resolved_at = NULL
+         WHERE id = ?",
+        report_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Carries out a moderator's decision on the reported user.
+async fn take_action(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    antinuke: &antinuke::AntiNuke,
+    guild_id: serenity::GuildId,
+    moderator: &serenity::User,
+    report: &ReportRow,
+    action: &str,
+) -> Result<&'static str, Error> {
+    let target_id = serenity::UserId::new(report.target_id as u64);
+    // Audit log reasons are capped at 512 characters.
+    let reason = moderation::shorten(
+        &format!(
+            "Report #{} ({}), actioned by {}",
+            report.id,
+            report.reason,
+            moderator.tag()
+        ),
+        512,
+    );
+    let (case, outcome) = match action {
+        "warn" => {
+            let guild_name = guild_id
+                .name(ctx)
+                .unwrap_or_else(|| "the server".to_string());
+            // They might have DMs closed, the case is still recorded.
+            let _ = target_id
+                .direct_message(
+                    ctx,
+                    serenity::CreateMessage::new().content(format!(
+                        "⚠️ You have been warned in **{}**: {}",
+                        guild_name, report.reason
+                    )),
+                )
+                .await;
+            (ModAction::Warn, "⚠️ Warned")
+        }
+        "timeout" => {
+            let config = load_config(pool, guild_id).await?;
+            let until = serenity::Timestamp::from_unix_timestamp(
+                serenity::Timestamp::now().unix_timestamp() + config.timeout_secs,
+            )?;
+            guild_id
+                .edit_member(
+                    ctx,
+                    target_id,
+                    serenity::EditMember::new()
+                        .disable_communication_until_datetime(until)
+                        .audit_log_reason(&reason),
+                )
+                .await?;
+            (ModAction::Timeout, "🔇 Timed out")
+        }
+        "ban" => {
+            guild_id.ban_with_reason(ctx, target_id, 0, &reason).await?;
+            // The ban went through either way, don't report it as failed.
+            if let Err(e) = antinuke::record_ban(ctx, pool, antinuke, guild_id, moderator.id).await
+            {
+                eprintln!("Anti-nuke error in guild {}: {}", guild_id, e);
+            }
+            (ModAction::Ban, "🔨 Banned")
+        }
+        _ => return Ok("✅ Dismissed"),
+    };
+    moderation::record_case(pool, guild_id, target_id, moderator.id, case, &reason).await?;
+    Ok(outcome)
+}
+
+/// Handles the dismiss/warn/timeout/ban buttons on a queued report.
+pub async fn handle_button(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    antinuke: &antinuke::AntiNuke,
+    interaction: &serenity::ComponentInteraction,
+) -> Result<(), Error> {
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+    let Some((action, report_id)) = interaction
+        .data
+        .custom_id
+        .strip_prefix(BUTTON_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+    else {
+        return Ok(());
+    };
+    let Ok(report_id) = report_id.parse::<i64>() else {
+        return Ok(());
+    };
+
+    let permissions = interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .unwrap_or_default();
+    let allowed = match action {
+        "warn" | "timeout" => permissions.moderate_members(),
+        "ban" => permissions.ban_members(),
+        _ => permissions.moderate_members() || permissions.manage_messages(),
+    };
+    if !allowed {
+        return reply_ephemeral(ctx, interaction, "❌ You don't have permission to do that.").await;
+    }
+
+    let guild_id_db = guild_id.get() as i64;
+    let report = sqlx::query_as!(
+        ReportRow,
+        "SELECT id, reporter_id, target_id, reason FROM reports WHERE id = ? AND guild_id = ?",
+        report_id,
+        guild_id_db
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(report) = report else {
+        return reply_ephemeral(ctx, interaction, "❌ That report no longer exists.").await;
+    };
+
+    let moderator = &interaction.user;
+    let target_id = serenity::UserId::new(report.target_id as u64);
+    if action != "dismiss" {
+        let hierarchy = Hierarchy::load(ctx, guild_id, moderator.id).await?;
+        if let Err(why) = hierarchy.check(ctx, target_id).await? {
+            return reply_ephemeral(ctx, interaction, format!("❌ {}", why)).await;
+        }
+    }
+
+    // Claim the report first so two moderators can't both act on it.
+    let status = if action == "dismiss" {
+        "dismissed"
+    } else {
+        "actioned"
+    };
+    let moderator_id = moderator.id.get() as i64;
+    let claimed = sqlx::query!(
+        "UPDATE reports
+         SET status = ?, action = ?, handled_by = ?, resolved_at = strftime('%s', 'now')
+         WHERE id = ? AND status = 'open'",
+        status,
+        action,
+        moderator_id,
+        report.id
+    )
+    .execute(pool)
+    .await?;
+    if claimed.rows_affected() == 0 {
+        return reply_ephemeral(ctx, interaction, "❌ Someone already handled this report.").await;
+    }
+
+    let outcome = match take_action(ctx, pool, antinuke, guild_id, moderator, &report, action).await
+    {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            reopen(pool, report.id).await?;
+            return reply_ephemeral(ctx, interaction, format!("❌ That didn't work: {}", e)).await;
+        }
+    };
+
+    // Keep the report for the record, but take the buttons away.
+    let mut embed = interaction
+        .message
+        .embeds
+        .first()
+        .cloned()
+        .map(serenity::CreateEmbed::from)
+        .unwrap_or_default();
+    embed = embed
+        .field(
+            "Resolved",
+            format!("{} by {}", outcome, moderator.mention()),
+            false,
+        )
+        .color(if action == "dismiss" {
+            serenity::Color::LIGHT_GREY
+        } else {
+            serenity::Color::DARK_GREEN
+        });
+    interaction
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::UpdateMessage(
+                serenity::CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+
+    // Let the reporter know without saying who handled it or exactly what was done.
+    let verdict = if action == "dismiss" {
+        "The moderators reviewed it and decided no action was needed."
+    } else {
+        "The moderators reviewed it and took action. Thanks for letting us know."
+    };
+    let guild_name = guild_id
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+    let _ = serenity::UserId::new(report.reporter_id as u64)
+        .direct_message(
+            ctx,
+            serenity::CreateMessage::new().content(format!(
+                "Your report #{} in **{}** has been resolved. {}",
+                report.id, guild_name, verdict
+            )),
+        )
+        .await;
+    Ok(())
+}
+
+// Changes the guild's config row, creating it with the defaults if needed.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut ReportConfigRow),
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let mut config = load_config(pool, guild_id).await?;
+    change(&mut config);
+
+    let guild_id_db = guild_id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO report_config (guild_id, channel_id, timeout_secs) VALUES (?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            channel_id = excluded.channel_id,
+            timeout_secs = excluded.timeout_secs",
+        guild_id_db,
+        config.channel_id,
+        config.timeout_secs
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let config = load_config(pool, guild_id).await?;
+    let guild_id_db = guild_id.get() as i64;
+    let open = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM reports WHERE guild_id = ? AND status = 'open'",
+        guild_id_db
+    )
+    .fetch_one(pool)
+    .await?
+    .count;
+    let embed = serenity::CreateEmbed::new()
+        .title("Reports")
+        .field(
+            "Queue",
+            config
+                .channel_id
+                .map(|id| format!("<#{}>", id))
+                .unwrap_or_else(|| "Not set, members can't report".to_string()),
+            true,
+        )
+        .field("Open reports", open.to_string(), true)
+        .field(
+            "Timeout button",
+            moderation::format_duration(config.timeout_secs as u64),
+            true,
+        )
+        .color(serenity::Color::ORANGE);
+    let mut reply = poise::CreateReply::default().embed(embed);
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Configure where member reports go.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("show", "channel", "timeout", "open")
+)]
+pub async fn reports(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the report settings.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Choose the moderator channel reports are sent to. Leave it out to turn reports off.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn channel(
+    ctx: Context<'_>,
+    #[description = "Channel for the report queue"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let channel_id = channel.map(|channel| channel.id.get() as i64);
+    update_config(ctx, |config| config.channel_id = channel_id).await?;
+    show_inner(ctx, "Report queue updated.").await
+}
+
+/// How long the Timeout button times members out for.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn timeout(
+    ctx: Context<'_>,
+    #[description = "Timeout length (e.g. 1h)"] duration: String,
+) -> Result<(), Error> {
+    let secs = match moderation::parse_duration(&duration) {
+        Some(d) if d.as_secs() > 0 && d.as_secs() <= 28 * 24 * 60 * 60 => d.as_secs() as i64,
+        _ => {
+            ctx.say("❌ Timeouts must be between 1 second and 28 days, e.g. `1h`.")
+                .await?;
+            return Ok(());
+        }
+    };
+    update_config(ctx, |config| config.timeout_secs = secs).await?;
+    show_inner(ctx, "Timeout length updated.").await
+}
+
+/// List the reports nobody has handled yet.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn open(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let guild_id_db = guild_id.get() as i64;
+    let reports = sqlx::query_as!(
+        OpenReportRow,
+        "SELECT id, target_id, queue_channel_id, queue_message_id, created_at FROM reports
+         WHERE guild_id = ? AND status = 'open' ORDER BY id",
+        guild_id_db
+    )
+    .fetch_all(&ctx.data().db_pool)
+    .await?;
+    if reports.is_empty() {
+        ctx.say("There are no open reports.").await?;
+        return Ok(());
+    }
+
+    let page_count = reports.len().div_ceil(10);
+    let pages = reports
+        .chunks(10)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let list = chunk
+                .iter()
+                .map(|report| {
+                    let link = match (report.queue_channel_id, report.queue_message_id) {
+                        (Some(channel_id), Some(message_id)) => format!(
+                            " [View](https://discord.com/channels/{}/{}/{})",
+                            guild_id, channel_id, message_id
+                        ),
+                        _ => String::new(),
+                    };
+                    format!(
+                        "**#{}** against <@{}> <t:{}:R>{}",
+                        report.id, report.target_id, report.created_at, link
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            serenity::CreateEmbed::new()
+                .title(format!("Open reports ({})", reports.len()))
+                .description(list)
+                .footer(serenity::CreateEmbedFooter::new(format!(
+                    "Page {}/{}",
+                    i + 1,
+                    page_count
+                )))
+                .color(serenity::Color::ORANGE)
+        })
+        .collect();
+    moderation::paginate(ctx, pages).await
+}
@@ -0,0 +1,450 @@
+use crate::prefixes;
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// What kind of value a setting holds, which decides how `config set` reads it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A space separated list of command prefixes, checked by [`prefixes::parse_list`].
+    Prefixes,
+}
+
+impl Kind {
+    /// Checks what someone typed and turns it into the value to store.
+    pub fn parse(&self, input: &str) -> Result<String, String> {
+        match *self {
+            Kind::Prefixes => Ok(prefixes::parse_list(input)?.join(" ")),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            Kind::Prefixes => format!(
+                "Up to {} prefixes separated by spaces, each at most {} characters",
+                prefixes::MAX_PREFIXES,
+                prefixes::MAX_CHARS
+            ),
+        }
+    }
+}
+
+/// A per-guild setting. Guilds that never set it get `default`.
+#[derive(Debug)]
+pub struct Setting {
+    pub key: &'static str,
+    pub description: &'static str,
+    pub kind: Kind,
+    pub default: &'static str,
+}
+
+/// The prefixes for text commands in the guild.
+pub const PREFIXES: Setting = Setting {
+    key: "prefixes",
+    description: "The prefixes for text commands",
+    kind: Kind::Prefixes,
+    default: prefixes::DEFAULT,
+};
+
+/// Every setting `config` knows about.
+pub const ALL: &[Setting] = &[PREFIXES];
+
+/// Looks up a setting by its key.
+pub fn find(key: &str) -> Option<&'static Setting> {
+    let key = key.trim();
+    ALL.iter()
+        .find(|setting| setting.key.eq_ignore_ascii_case(key))
+}
+
+/// Guild settings, cached per guild. Only values that differ from the default are stored,
+/// and guilds that never changed anything are cached too, as empty.
+#[derive(Default)]
+pub struct GuildSettings {
+    guilds: RwLock<HashMap<serenity::GuildId, Arc<HashMap<String, String>>>>,
+    // Bumped on every write, so a load that raced one knows not to cache what it read.
+    generation: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+// What a guild has stored, straight from the database.
+async fn fetch(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<HashMap<String, String>, Error> {
+    let guild_id = guild_id.get() as i64;
+    Ok(sqlx::query!(
+        "SELECT key, value FROM guild_settings WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.key, row.value))
+    .collect())
+}
+
+impl GuildSettings {
+    /// Loads every guild's settings, so only guilds that never set anything miss at first.
+    pub async fn load(pool: &Pool<Sqlite>) -> Result<Self, Error> {
+        let mut guilds: HashMap<serenity::GuildId, HashMap<String, String>> = HashMap::new();
+        for row in sqlx::query!("SELECT guild_id, key, value FROM guild_settings")
+            .fetch_all(pool)
+            .await?
+        {
+            guilds
+                .entry(serenity::GuildId::new(row.guild_id as u64))
+                .or_default()
+                .insert(row.key, row.value);
+        }
+        Ok(GuildSettings {
+            guilds: RwLock::new(
+                guilds
+                    .into_iter()
+                    .map(|(guild_id, stored)| (guild_id, Arc::new(stored)))
+                    .collect(),
+            ),
+            ..Default::default()
+        })
+    }
+
+    // The values the guild has set, loaded from the database when not cached.
+    async fn stored(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Arc<HashMap<String, String>>, Error> {
+        if let Some(stored) = self.guilds.read().unwrap().get(&guild_id) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(stored.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let generation = self.generation.load(Ordering::Acquire);
+        let stored = fetch(pool, guild_id).await?;
+        Ok(self.remember(guild_id, generation, stored))
+    }
+
+    // Caches what a load read, unless a write since `generation` may have made it stale.
+    // Another load may have got there first, in which case that one is kept.
+    fn remember(
+        &self,
+        guild_id: serenity::GuildId,
+        generation: u64,
+        stored: HashMap<String, String>,
+    ) -> Arc<HashMap<String, String>> {
+        let stored = Arc::new(stored);
+        let mut guilds = self.guilds.write().unwrap();
+        if self.generation.load(Ordering::Acquire) != generation {
+            return stored;
+        }
+        guilds.entry(guild_id).or_insert(stored).clone()
+    }
+
+    // Drops a guild after a write, so the next read loads what was written.
+    fn invalidate(&self, guild_id: serenity::GuildId) {
+        let mut guilds = self.guilds.write().unwrap();
+        self.generation.fetch_add(1, Ordering::Release);
+        guilds.remove(&guild_id);
+    }
+
+    /// The guild's value for `setting`, or `None` if it uses the default.
+    pub async fn get_set(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+        setting: &Setting,
+    ) -> Result<Option<String>, Error> {
+        Ok(self.stored(pool, guild_id).await?.get(setting.key).cloned())
+    }
+
+    /// Stores a value for `setting`. It must have come from the setting's [`Kind::parse`].
+    pub async fn set(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+        setting: &Setting,
+        value: &str,
+    ) -> Result<(), Error> {
+        let guild_id_db = guild_id.get() as i64;
+        sqlx::query!(
+            "INSERT INTO guild_settings (guild_id, key, value) VALUES (?, ?, ?)
+             ON CONFLICT (guild_id, key) DO UPDATE SET value = excluded.value",
+            guild_id_db,
+            setting.key,
+            value
+        )
+        .execute(pool)
+        .await?;
+        self.invalidate(guild_id);
+        Ok(())
+    }
+
+    /// Puts `setting` back to its default.
+    pub async fn reset(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+        setting: &Setting,
+    ) -> Result<(), Error> {
+        let guild_id_db = guild_id.get() as i64;
+        sqlx::query!(
+            "DELETE FROM guild_settings WHERE guild_id = ? AND key = ?",
+            guild_id_db,
+            setting.key
+        )
+        .execute(pool)
+        .await?;
+        self.invalidate(guild_id);
+        Ok(())
+    }
+
+    /// How the cache is doing, for `ping`.
+    pub fn stats(&self) -> String {
+        let guilds = self.guilds.read().unwrap();
+        let defaults = guilds.values().filter(|stored| stored.is_empty()).count();
+        format!(
+            "{} hits, {} misses, {} guilds cached ({} all default)",
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            guilds.len(),
+            defaults
+        )
+    }
+}
+
+// Replies that `key` isn't a setting, returning the setting otherwise.
+async fn find_or_reply(ctx: Context<'_>, key: &str) -> Result<Option<&'static Setting>, Error> {
+    let setting = find(key);
+    if setting.is_none() {
+        let keys = ALL
+            .iter()
+            .map(|setting| format!("`{}`", setting.key))
+            .collect::<Vec<_>>()
+            .join(", ");
+        ctx.say(format!(
+            "❌ There is no setting called `{}`. The settings are: {}",
+            key, keys
+        ))
+        .await?;
+    }
+    Ok(setting)
+}
+
+fn shown(setting: &Setting, value: Option<String>) -> String {
+    match value {
+        Some(value) => format!("`{}`", value),
+        None => format!("`{}` (default)", setting.default),
+    }
+}
+
+async fn autocomplete_key(_ctx: Context<'_>, partial: &str) -> Vec<serenity::AutocompleteChoice> {
+    let partial = partial.to_lowercase();
+    ALL.iter()
+        .filter(|setting| setting.key.contains(&partial))
+        .map(|setting| serenity::AutocompleteChoice::new(setting.key, setting.key))
+        .collect()
+}
+
+/// View and change this server's settings.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("get", "set", "reset", "list")
+)]
+pub async fn config(ctx: Context<'_>) -> Result<(), Error> {
+    list_inner(ctx).await
+}
+
+/// Show the value of one setting.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn get(
+    ctx: Context<'_>,
+    #[description = "The setting"]
+    #[autocomplete = "autocomplete_key"]
+    key: String,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let Some(setting) = find_or_reply(ctx, &key).await? else {
+        return Ok(());
+    };
+    let value = ctx
+        .data()
+        .settings
+        .get_set(&ctx.data().db_pool, guild_id, setting)
+        .await?;
+    ctx.say(format!("**{}** is {}.", setting.key, shown(setting, value)))
+        .await?;
+    Ok(())
+}
+
+/// Change a setting.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "The setting"]
+    #[autocomplete = "autocomplete_key"]
+    key: String,
+    #[description = "The new value"]
+    #[rest]
+    value: String,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let Some(setting) = find_or_reply(ctx, &key).await? else {
+        return Ok(());
+    };
+    let value = match setting.kind.parse(&value) {
+        Ok(value) => value,
+        Err(why) => {
+            ctx.say(format!("❌ {}", why)).await?;
+            return Ok(());
+        }
+    };
+    ctx.data()
+        .settings
+        .set(&ctx.data().db_pool, guild_id, setting, &value)
+        .await?;
+    ctx.say(format!("✅ **{}** is now `{}`.", setting.key, value))
+        .await?;
+    Ok(())
+}
+
+/// Put a setting back to its default.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn reset(
+    ctx: Context<'_>,
+    #[description = "The setting"]
+    #[autocomplete = "autocomplete_key"]
+    key: String,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let Some(setting) = find_or_reply(ctx, &key).await? else {
+        return Ok(());
+    };
+    ctx.data()
+        .settings
+        .reset(&ctx.data().db_pool, guild_id, setting)
+        .await?;
+    ctx.say(format!(
+        "✅ **{}** is back to its default, `{}`.",
+        setting.key, setting.default
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Show every setting and its value.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_inner(ctx).await
+}
+
+async fn list_inner(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let mut embed = serenity::CreateEmbed::new()
+        .title("Server settings")
+        .footer(serenity::CreateEmbedFooter::new(
+            "Change one with config set <key> <value>",
+        ))
+        .color(serenity::Color::BLURPLE);
+    for setting in ALL {
+        let value = ctx
+            .data()
+            .settings
+            .get_set(&ctx.data().db_pool, guild_id, setting)
+            .await?;
+        embed = embed.field(
+            setting.key,
+            format!(
+                "{}\n{}\n*{}*",
+                shown(setting, value),
+                setting.description,
+                setting.kind.describe()
+            ),
+            false,
+        );
+    }
+    ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_settings() {
+        assert_eq!(Kind::Prefixes.parse("  ! ?? "), Ok("! ??".to_string()));
+        assert!(Kind::Prefixes.parse("   ").is_err());
+        assert_eq!(
+            find(" PREFIXES ").map(|setting| setting.key),
+            Some("prefixes")
+        );
+        assert!(find("nope").is_none());
+    }
+
+    #[tokio::test]
+    async fn caches_guilds_and_reloads_after_writes() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO guild_settings (guild_id, key, value) VALUES (1, 'prefixes', '! ?')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let settings = GuildSettings::load(&pool).await.unwrap();
+        let custom = serenity::GuildId::new(1);
+        let default = serenity::GuildId::new(2);
+        let get = |guild_id| settings.get_set(&pool, guild_id, &PREFIXES);
+        assert_eq!(get(custom).await.unwrap().as_deref(), Some("! ?"));
+        assert_eq!(get(default).await.unwrap(), None);
+        // Guilds on the defaults are remembered rather than looked up again.
+        assert_eq!(get(default).await.unwrap(), None);
+        assert_eq!(
+            settings.stats(),
+            "2 hits, 1 misses, 2 guilds cached (1 all default)"
+        );
+
+        settings.set(&pool, default, &PREFIXES, "$").await.unwrap();
+        assert_eq!(get(default).await.unwrap().as_deref(), Some("$"));
+        settings.reset(&pool, custom, &PREFIXES).await.unwrap();
+        assert_eq!(get(custom).await.unwrap(), None);
+    }
+
+    #[test]
+    fn loads_that_race_a_write_are_not_cached() {
+        let settings = GuildSettings::default();
+        let guild_id = serenity::GuildId::new(1);
+        let stored = |value: &str| HashMap::from([(PREFIXES.key.to_string(), value.to_string())]);
+
+        // A load reads the old value, then a write lands before it finishes.
+        let generation = settings.generation.load(Ordering::Acquire);
+        settings.invalidate(guild_id);
+        settings.remember(guild_id, generation, stored("old"));
+        assert!(settings.guilds.read().unwrap().get(&guild_id).is_none());
+
+        // Of two loads with nothing written between them, the first one in is kept.
+        let generation = settings.generation.load(Ordering::Acquire);
+        settings.remember(guild_id, generation, stored("first"));
+        let kept = settings.remember(guild_id, generation, stored("second"));
+        assert_eq!(kept.get(PREFIXES.key).map(String::as_str), Some("first"));
+    }
+}
@@ -0,0 +1,564 @@
+use crate::moderation::{self, reply_ephemeral};
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use rand::Rng;
+use sqlx::{Pool, Sqlite};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Prefix of the custom id on the verify button, routed here by the event handler.
+pub const BUTTON_PREFIX: &str = "verify:";
+const VERIFY_BUTTON: &str = "verify:start";
+/// How often to look for members who ran out of time to verify.
+const TICK: Duration = Duration::from_secs(60);
+/// How long to wait for someone to answer the challenge.
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// Helper struct to map the query result
+struct VerificationConfigRow {
+    enabled: bool,
+    unverified_role_id: Option<i64>,
+    member_role_id: Option<i64>,
+    challenge: bool,
+    kick_after_secs: i64,
+}
+
+// Helper struct to map the query result
+struct ExpiredRow {
+    guild_id: i64,
+    user_id: i64,
+    unverified_role_id: Option<i64>,
+    kick_after_secs: i64,
+}
+
+/// A small sum to answer before being let in, which stops the simplest join bots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Challenge {
+    question: String,
+    answer: i64,
+}
+
+impl Challenge {
+    fn generate(rng: &mut impl Rng) -> Self {
+        let a = rng.random_range(2..=12);
+        let b = rng.random_range(2..=12);
+        // Keep the answer positive so nobody has to type a minus sign.
+        let (question, answer) = match rng.random_range(0..3) {
+            0 => (format!("What is {} + {}?", a, b), a + b),
+            1 => (
+                format!("What is {} - {}?", a.max(b), a.min(b)),
+                a.max(b) - a.min(b),
+            ),
+            _ => (format!("What is {} × {}?", a, b), a * b),
+        };
+        Challenge { question, answer }
+    }
+
+    fn check(&self, input: &str) -> bool {
+        input.trim().parse::<i64>() == Ok(self.answer)
+    }
+}
+
+/// Kicks members who haven't verified in time.
+#[derive(Default)]
+pub struct Verification {
+    ticking: AtomicBool,
+}
+
+impl Verification {
+    /// Starts the background task that kicks unverified members. Only the first call does
+    /// anything, `ready` fires once per shard.
+    pub fn start(self: &Arc<Self>, http: Arc<serenity::Http>, pool: Pool<Sqlite>) {
+        if self.ticking.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TICK);
+            loop {
+                interval.tick().await;
+                if let Err(e) = kick_expired(&http, &pool).await {
+                    eprintln!("Verification timeout error: {}", e);
+                }
+            }
+        });
+    }
+}
+
+async fn load_config(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+) -> Result<VerificationConfigRow, Error> {
+    let guild_id = guild_id.get() as i64;
+    let config = sqlx::query_as!(
+        VerificationConfigRow,
+        "SELECT enabled, unverified_role_id, member_role_id, challenge, kick_after_secs
+         FROM verification_config WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(VerificationConfigRow {
+        enabled: false,
+        unverified_role_id: None,
+        member_role_id: None,
+        challenge: false,
+        kick_after_secs: 0,
+    });
+    Ok(config)
+}
+
+async fn forget_pending(
+    pool: &Pool<Sqlite>,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+) -> Result<(), Error> {
+    let guild_id = guild_id.get() as i64;
+    let user_id = user_id.get() as i64;
+    sqlx::query!(
+        "DELETE FROM verification_pending WHERE guild_id = ? AND user_id = ?",
+        guild_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Gives a new member the unverified role and starts their clock. Called from the
+/// member join handler.
+pub async fn member_joined(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    member: &serenity::Member,
+) -> Result<(), Error> {
+    if member.user.bot {
+        return Ok(());
+    }
+    let config = load_config(pool, member.guild_id).await?;
+    let (true, Some(unverified_role_id), Some(_)) = (
+        config.enabled,
+        config.unverified_role_id,
+        config.member_role_id,
+    ) else {
+        return Ok(());
+    };
+    member
+        .add_role(ctx, serenity::RoleId::new(unverified_role_id as u64))
+        .await?;
+
+    let guild_id = member.guild_id.get() as i64;
+    let user_id = member.user.id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO verification_pending (guild_id, user_id) VALUES (?, ?)
+         ON CONFLICT (guild_id, user_id) DO UPDATE SET joined_at = excluded.joined_at",
+        guild_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Kicks everyone whose time ran out. Members who left or were verified by hand are
+// just forgotten.
+async fn kick_expired(http: &serenity::Http, pool: &Pool<Sqlite>) -> Result<(), Error> {
+    let expired = sqlx::query_as!(
+        ExpiredRow,
+        "SELECT p.guild_id, p.user_id, c.unverified_role_id, c.kick_after_secs
+         FROM verification_pending p
+         JOIN verification_config c ON c.guild_id = p.guild_id
+         WHERE c.enabled AND c.kick_after_secs > 0
+            AND p.joined_at + c.kick_after_secs <= strftime('%s', 'now')"
+    )
+    .fetch_all(pool)
+    .await?;
+    for row in expired {
+        let guild_id = serenity::GuildId::new(row.guild_id as u64);
+        let user_id = serenity::UserId::new(row.user_id as u64);
+        if let Ok(member) = guild_id.member(http, user_id).await
+            && let Some(role_id) = row.unverified_role_id
+            && member
+                .roles
+                .contains(&serenity::RoleId::new(role_id as u64))
+        {
+            let reason = format!(
+                "Didn't verify within {}",
+                moderation::format_duration(row.kick_after_secs as u64)
+            );
+            if let Err(e) = member.kick_with_reason(http, &reason).await {
+                eprintln!(
+                    "Failed to kick unverified member {} from guild {}: {}",
+                    user_id, guild_id, e
+                );
+            }
+        }
+        forget_pending(pool, guild_id, user_id).await?;
+    }
+    Ok(())
+}
+
+/// Handles the verify button: asks the challenge if the guild wants one, then swaps the
+/// unverified role for the member role.
+pub async fn handle_button(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    interaction: &serenity::ComponentInteraction,
+) -> Result<(), Error> {
+    let (Some(guild_id), Some(member)) = (interaction.guild_id, interaction.member.as_ref()) else {
+        return Ok(());
+    };
+    let config = load_config(pool, guild_id).await?;
+    let (true, Some(unverified_role_id), Some(member_role_id)) = (
+        config.enabled,
+        config.unverified_role_id,
+        config.member_role_id,
+    ) else {
+        return reply_ephemeral(ctx, interaction, "❌ Verification isn't turned on here.").await;
+    };
+    let unverified_role = serenity::RoleId::new(unverified_role_id as u64);
+    let member_role = serenity::RoleId::new(member_role_id as u64);
+    if member.roles.contains(&member_role) && !member.roles.contains(&unverified_role) {
+        return reply_ephemeral(ctx, interaction, "✅ You're already verified.").await;
+    }
+
+    // With a challenge the form answers the button, so the result goes to the form.
+    let mut submission = None;
+    if config.challenge {
+        let challenge = Challenge::generate(&mut rand::rng());
+        let modal = serenity::CreateQuickModal::new("Verification")
+            .timeout(CHALLENGE_TIMEOUT)
+            .field(
+                serenity::CreateInputText::new(
+                    serenity::InputTextStyle::Short,
+                    challenge.question.clone(),
+                    "",
+                )
+                .placeholder("Type the number")
+                .max_length(10),
+            );
+        let Some(response) = interaction.quick_modal(ctx, modal).await? else {
+            return Ok(());
+        };
+        let correct = response
+            .inputs
+            .first()
+            .is_some_and(|answer| challenge.check(answer));
+        if !correct {
+            return respond(
+                ctx,
+                interaction,
+                Some(&response.interaction),
+                "❌ That's not right. Press the button to try again.",
+            )
+            .await;
+        }
+        submission = Some(response.interaction);
+    }
+
+    let granted = async {
+        member.add_role(ctx, member_role).await?;
+        member.remove_role(ctx, unverified_role).await
+    }
+    .await;
+    if let Err(e) = granted {
+        eprintln!(
+            "Failed to verify {} in guild {}: {}",
+            member.user.id, guild_id, e
+        );
+        return respond(
+            ctx,
+            interaction,
+            submission.as_ref(),
+            "❌ I couldn't give you the member role. Please ask a moderator for help.",
+        )
+        .await;
+    }
+    forget_pending(pool, guild_id, member.user.id).await?;
+
+    let guild_name = guild_id
+        .name(ctx)
+        .unwrap_or_else(|| "the server".to_string());
+    respond(
+        ctx,
+        interaction,
+        submission.as_ref(),
+        format!("✅ You're verified. Welcome to **{}**!", guild_name),
+    )
+    .await
+}
+
+// Answers whichever interaction is still open, the challenge form if there was one.
+async fn respond(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    submission: Option<&serenity::ModalInteraction>,
+    content: impl Into<String>,
+) -> Result<(), Error> {
+    let Some(submission) = submission else {
+        return reply_ephemeral(ctx, interaction, content).await;
+    };
+    submission
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::Message(
+                serenity::CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+// Changes the guild's config row, creating it with the defaults if needed.
+async fn update_config(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut VerificationConfigRow),
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let mut config = load_config(pool, guild_id).await?;
+    change(&mut config);
+
+    let guild_id_db = guild_id.get() as i64;
+    sqlx::query!(
+        "INSERT INTO verification_config
+            (guild_id, enabled, unverified_role_id, member_role_id, challenge, kick_after_secs)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = excluded.enabled,
+            unverified_role_id = excluded.unverified_role_id,
+            member_role_id = excluded.member_role_id,
+            challenge = excluded.challenge,
+            kick_after_secs = excluded.kick_after_secs",
+        guild_id_db,
+        config.enabled,
+        config.unverified_role_id,
+        config.member_role_id,
+        config.challenge,
+        config.kick_after_secs
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn show_inner(ctx: Context<'_>, content: &str) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let pool = &ctx.data().db_pool;
+    let config = load_config(pool, guild_id).await?;
+    let guild_id_db = guild_id.get() as i64;
+    let pending = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM verification_pending WHERE guild_id = ?",
+        guild_id_db
+    )
+    .fetch_one(pool)
+    .await?
+    .count;
+    let role = |id: Option<i64>| {
+        id.map(|id| format!("<@&{}>", id))
+            .unwrap_or_else(|| "Not set".to_string())
+    };
+    let embed = serenity::CreateEmbed::new()
+        .title("Verification")
+        .field(
+            "Status",
+            if config.enabled {
+                "Enabled"
+            } else {
+                "Disabled"
+            },
+            true,
+        )
+        .field("Unverified role", role(config.unverified_role_id), true)
+        .field("Member role", role(config.member_role_id), true)
+        .field(
+            "Challenge",
+            if config.challenge {
+                "A simple sum"
+            } else {
+                "None, the button is enough"
+            },
+            true,
+        )
+        .field(
+            "Kick unverified after",
+            if config.kick_after_secs > 0 {
+                moderation::format_duration(config.kick_after_secs as u64)
+            } else {
+                "Never".to_string()
+            },
+            true,
+        )
+        .field("Waiting to verify", pending.to_string(), true)
+        .color(serenity::Color::DARK_GREEN);
+    let mut reply = poise::CreateReply::default().embed(embed);
+    if !content.is_empty() {
+        reply = reply.content(content);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Make new members press a button before they can see the server.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands(
+        "show",
+        "enable",
+        "disable",
+        "roles",
+        "challenge",
+        "kickafter",
+        "panel"
+    )
+)]
+pub async fn verification(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Show the verification settings.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    show_inner(ctx, "").await
+}
+
+/// Start giving new members the unverified role.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn enable(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let config = load_config(&ctx.data().db_pool, guild_id).await?;
+    if config.unverified_role_id.is_none() || config.member_role_id.is_none() {
+        ctx.say("❌ Set the roles first with `verification roles`.")
+            .await?;
+        return Ok(());
+    }
+    update_config(ctx, |config| config.enabled = true).await?;
+    show_inner(
+        ctx,
+        "Verification is now enabled. Post the button with `verification panel`.",
+    )
+    .await
+}
+
+/// Let new members straight in again. Nobody is kicked while this is off.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    update_config(ctx, |config| config.enabled = false).await?;
+    show_inner(ctx, "Verification is now disabled.").await
+}
+
+/// Choose the role new members wait in and the role verifying gives them.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn roles(
+    ctx: Context<'_>,
+    #[description = "Role new members get until they verify"] unverified: serenity::Role,
+    #[description = "Role members get once verified"] member: serenity::Role,
+) -> Result<(), Error> {
+    if unverified.id == member.id {
+        ctx.say("❌ The unverified and member roles must be different.")
+            .await?;
+        return Ok(());
+    }
+    let unverified_role_id = unverified.id.get() as i64;
+    let member_role_id = member.id.get() as i64;
+    update_config(ctx, |config| {
+        config.unverified_role_id = Some(unverified_role_id);
+        config.member_role_id = Some(member_role_id);
+    })
+    .await?;
+    show_inner(ctx, "Verification roles updated.").await
+}
+
+/// Ask a simple sum when someone presses the button.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn challenge(
+    ctx: Context<'_>,
+    #[description = "Whether to ask a question"] enabled: bool,
+) -> Result<(), Error> {
+    update_config(ctx, |config| config.challenge = enabled).await?;
+    show_inner(ctx, "Challenge updated.").await
+}
+
+/// Kick members who haven't verified after this long. Leave it out to never kick.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn kickafter(
+    ctx: Context<'_>,
+    #[description = "How long they get (e.g. 1d)"] duration: Option<String>,
+) -> Result<(), Error> {
+    let secs = match duration {
+        None => 0,
+        Some(duration) => match moderation::parse_duration(&duration) {
+            Some(d) if d.as_secs() >= 60 => d.as_secs() as i64,
+            _ => {
+                ctx.say("❌ Give them at least a minute, e.g. `30m` or `1d`.")
+                    .await?;
+                return Ok(());
+            }
+        },
+    };
+    update_config(ctx, |config| config.kick_after_secs = secs).await?;
+    show_inner(ctx, "Verification timeout updated.").await
+}
+
+/// Post the verify button. Defaults to this channel.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn panel(
+    ctx: Context<'_>,
+    #[description = "Channel to post the button in"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let channel_id = channel.map_or(ctx.channel_id(), |channel| channel.id);
+    let guild_name = ctx
+        .guild()
+        .map(|guild| guild.name.clone())
+        .unwrap_or_else(|| "the server".to_string());
+    channel_id
+        .send_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .embed(
+                    serenity::CreateEmbed::new()
+                        .title(format!("Welcome to {}", guild_name))
+                        .description("Press the button below to verify and unlock the server.")
+                        .color(serenity::Color::DARK_GREEN),
+                )
+                .components(vec![serenity::CreateActionRow::Buttons(vec![
+                    serenity::CreateButton::new(VERIFY_BUTTON)
+                        .label("Verify")
+                        .emoji('✅')
+                        .style(serenity::ButtonStyle::Success),
+                ])]),
+        )
+        .await?;
+    ctx.say(format!("Posted the verify button in <#{}>.", channel_id))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenge_answers_are_checked() {
+        let mut rng = rand::rng();
+        for _ in 0..100 {
+            let challenge = Challenge::generate(&mut rng);
+            assert!(challenge.answer >= 0, "{:?}", challenge);
+            assert!(challenge.check(&challenge.answer.to_string()));
+            assert!(challenge.check(&format!("  {} ", challenge.answer)));
+            assert!(!challenge.check(&(challenge.answer + 1).to_string()));
+            assert!(!challenge.check(""));
+        }
+    }
+}
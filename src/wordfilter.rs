@@ -0,0 +1,615 @@
+use crate::automod::{self, AutomodAction};
+use crate::moderation;
+use crate::{Context, Error};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use regex::{RegexBuilder, RegexSet, RegexSetBuilder};
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// Keeps user supplied patterns from compiling into something huge.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// The limit for all of a guild's rules compiled together.
+const SET_SIZE_LIMIT: usize = REGEX_SIZE_LIMIT * 4;
+const MAX_RULES: usize = 200;
+
+/// Whether a rule is a plain word or a regular expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum RuleKind {
+    #[name = "word"]
+    Word,
+    #[name = "regex"]
+    Regex,
+}
+
+impl RuleKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RuleKind::Word => "word",
+            RuleKind::Regex => "regex",
+        }
+    }
+}
+
+// Helper struct to map the query result
+struct RuleRow {
+    id: i64,
+    kind: String,
+    pattern: String,
+    action: String,
+}
+
+// Helper struct to map the query result
+struct ExemptionRow {
+    target_id: i64,
+    kind: String,
+}
+
+/// A single blocked word or pattern.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub id: i64,
+    pub kind: RuleKind,
+    pub pattern: String,
+    pub action: AutomodAction,
+}
+
+impl From<RuleRow> for Rule {
+    fn from(row: RuleRow) -> Self {
+        Rule {
+            id: row.id,
+            kind: if row.kind == "regex" {
+                RuleKind::Regex
+            } else {
+                RuleKind::Word
+            },
+            pattern: row.pattern,
+            action: AutomodAction::from_db(&row.action),
+        }
+    }
+}
+
+/// A guild's rules compiled into one set, along with who is exempt from them.
+pub struct CompiledRules {
+    set: RegexSet,
+    rules: Vec<Rule>,
+    exempt_roles: HashSet<serenity::RoleId>,
+    exempt_channels: HashSet<serenity::ChannelId>,
+}
+
+impl CompiledRules {
+    /// The most severe rule matching `content`, checked both as typed and normalised.
+    pub fn find(&self, content: &str) -> Option<&Rule> {
+        let normalized = normalize(content);
+        let mut matches: Vec<usize> = self.set.matches(content).into_iter().collect();
+        matches.extend(self.set.matches(&normalized));
+        matches
+            .into_iter()
+            .map(|i| &self.rules[i])
+            .max_by_key(|rule| severity(rule.action))
+    }
+}
+
+fn severity(action: AutomodAction) -> u8 {
+    match action {
+        AutomodAction::Delete => 0,
+        AutomodAction::Warn => 1,
+        AutomodAction::Timeout => 2,
+        AutomodAction::Kick => 3,
+    }
+}
+
+/// Undoes the usual tricks for getting around a word filter: case, leetspeak and
+/// invisible characters.
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| {
+            !matches!(
+                c,
+                '\u{200B}'..='\u{200F}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' | '\u{00AD}'
+            )
+        })
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | '!' | '|' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            '8' => 'b',
+            '9' => 'g',
+            c => c,
+        })
+        .collect()
+}
+
+// The regex a rule compiles to. Words are normalised the same way messages are and
+// only match as whole words.
+fn rule_regex(kind: RuleKind, pattern: &str) -> String {
+    match kind {
+        RuleKind::Word => format!(r"(?i)\b{}\b", regex::escape(&normalize(pattern))),
+        RuleKind::Regex => format!("(?i){}", pattern),
+    }
+}
+
+// Compiles rules into one set, the way messages are checked against them.
+fn build_set<'a>(
+    rules: impl IntoIterator<Item = (RuleKind, &'a str)>,
+) -> Result<RegexSet, regex::Error> {
+    RegexSetBuilder::new(
+        rules
+            .into_iter()
+            .map(|(kind, pattern)| rule_regex(kind, pattern)),
+    )
+    .size_limit(SET_SIZE_LIMIT)
+    .build()
+}
+
+// Why a new rule can't be added alongside the guild's existing ones, if it can't.
+fn validate(kind: RuleKind, pattern: &str, existing: &[Rule]) -> Result<(), String> {
+    if pattern.is_empty() {
+        return Err("The pattern can't be empty.".to_string());
+    }
+    // A lone character like `!` would normalise to `i` and match every "I".
+    if kind == RuleKind::Word && normalize(pattern).chars().count() < 2 {
+        return Err("A blocked word needs at least two characters.".to_string());
+    }
+    if let Err(e) = RegexBuilder::new(&rule_regex(kind, pattern))
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+    {
+        return Err(format!("That pattern is invalid: {}", e));
+    }
+    if existing.len() >= MAX_RULES {
+        return Err(format!(
+            "This server already has {} filter rules.",
+            MAX_RULES
+        ));
+    }
+    let all = existing
+        .iter()
+        .map(|rule| (rule.kind, rule.pattern.as_str()))
+        .chain([(kind, pattern)]);
+    if build_set(all).is_err() {
+        return Err(
+            "That pattern is too large to add alongside this server's other rules.".to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Per-guild compiled rules, shared between the event handler and the commands.
+#[derive(Default)]
+pub struct WordFilter {
+    guilds: RwLock<HashMap<serenity::GuildId, Arc<CompiledRules>>>,
+}
+
+impl WordFilter {
+    /// The guild's compiled rules, building them from the database when not cached.
+    pub async fn rules(
+        &self,
+        pool: &Pool<Sqlite>,
+        guild_id: serenity::GuildId,
+    ) -> Result<Arc<CompiledRules>, Error> {
+        if let Some(rules) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(rules.clone());
+        }
+        let compiled = Arc::new(compile(pool, guild_id).await?);
+        self.guilds
+            .write()
+            .unwrap()
+            .insert(guild_id, compiled.clone());
+        Ok(compiled)
+    }
+
+    /// Drops the cached rules so the next message rebuilds them.
+    pub fn invalidate(&self, guild_id: serenity::GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+}
+
+async fn load_rules(pool: &Pool<Sqlite>, guild_id: serenity::GuildId) -> Result<Vec<Rule>, Error> {
+    let guild_id = guild_id.get() as i64;
+    let rows = sqlx::query_as!(
+        RuleRow,
+        "SELECT id, kind, pattern, action FROM filter_rules WHERE guild_id = ? ORDER BY id",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(Rule::from).collect())
+}
+
+async fn compile(pool: &Pool<Sqlite>, guild_id: serenity::GuildId) -> Result<CompiledRules, Error> {
+    let rules = load_rules(pool, guild_id).await?;
+    let set = build_set(rules.iter().map(|rule| (rule.kind, rule.pattern.as_str())))?;
+
+    let guild_id = guild_id.get() as i64;
+    let exemptions = sqlx::query_as!(
+        ExemptionRow,
+        "SELECT target_id, kind FROM filter_exemptions WHERE guild_id = ?",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut exempt_roles = HashSet::new();
+    let mut exempt_channels = HashSet::new();
+    for exemption in exemptions {
+        let id = exemption.target_id as u64;
+        if exemption.kind == "role" {
+            exempt_roles.insert(serenity::RoleId::new(id));
+        } else {
+            exempt_channels.insert(serenity::ChannelId::new(id));
+        }
+    }
+
+    Ok(CompiledRules {
+        set,
+        rules,
+        exempt_roles,
+        exempt_channels,
+    })
+}
+
+/// Checks a message against the guild's filter, returning `true` if it was acted on.
+pub async fn check_message(
+    ctx: &serenity::Context,
+    pool: &Pool<Sqlite>,
+    filter: &WordFilter,
+    automod: &automod::Automod,
+    msg: &serenity::Message,
+) -> Result<bool, Error> {
+    let Some(guild_id) = msg.guild_id else {
+        return Ok(false);
+    };
+    if msg.author.bot || msg.webhook_id.is_some() || msg.content.is_empty() {
+        return Ok(false);
+    }
+
+    let rules = filter.rules(pool, guild_id).await?;
+    if rules.rules.is_empty() || rules.exempt_channels.contains(&msg.channel_id) {
+        return Ok(false);
+    }
+    let exempt_by_role = msg.member.as_ref().is_some_and(|member| {
+        member
+            .roles
+            .iter()
+            .any(|role| rules.exempt_roles.contains(role))
+    });
+    if exempt_by_role || moderation::is_moderator(ctx, guild_id, msg.channel_id, msg.author.id) {
+        return Ok(false);
+    }
+
+    let Some(rule) = rules.find(&msg.content) else {
+        return Ok(false);
+    };
+    // Timeouts last as long as the guild's automod timeouts do.
+    let timeout_secs = automod
+        .config(pool, guild_id)
+        .await?
+        .unwrap_or_default()
+        .timeout_secs;
+    automod::punish(
+        ctx,
+        pool,
+        msg,
+        rule.action,
+        timeout_secs,
+        "Word filter",
+        &format!("posting blocked content (rule #{})", rule.id),
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Manage blocked words and patterns.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("add", "remove", "list", "exempt", "unexempt")
+)]
+pub async fn filter(ctx: Context<'_>) -> Result<(), Error> {
+    list_inner(ctx).await
+}
+
+/// Block a word or regular expression.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "A plain word, or a regular expression"] kind: RuleKind,
+    #[description = "The word or pattern to block"] pattern: String,
+    #[description = "What to do on top of deleting the message (default: delete)"] action: Option<
+        AutomodAction,
+    >,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let action = action.unwrap_or(AutomodAction::Delete);
+
+    let pattern = pattern.trim().to_string();
+    let data = ctx.data();
+    let existing = load_rules(&data.db_pool, guild_id).await?;
+    if let Err(reason) = validate(kind, &pattern, &existing) {
+        ctx.say(format!("❌ {}", reason)).await?;
+        return Ok(());
+    }
+
+    let guild_id_db = guild_id.get() as i64;
+    let kind_str = kind.as_str();
+    let action_str = action.as_str();
+    let id = sqlx::query!(
+        "INSERT INTO filter_rules (guild_id, kind, pattern, action) VALUES (?, ?, ?, ?)",
+        guild_id_db,
+        kind_str,
+        pattern,
+        action_str
+    )
+    .execute(&data.db_pool)
+    .await?
+    .last_insert_rowid();
+    data.word_filter.invalidate(guild_id);
+
+    ctx.say(format!(
+        "Added filter rule #{}: {} `{}` → {}",
+        id,
+        kind_str,
+        pattern.replace('`', "'"),
+        action_str
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Remove a filter rule by its number.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Rule number, see the filter list"] id: i64,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let guild_id_db = guild_id.get() as i64;
+    let removed = sqlx::query!(
+        "DELETE FROM filter_rules WHERE id = ? AND guild_id = ?",
+        id,
+        guild_id_db
+    )
+    .execute(&data.db_pool)
+    .await?
+    .rows_affected();
+    data.word_filter.invalidate(guild_id);
+
+    if removed == 0 {
+        ctx.say(format!("❌ There's no filter rule #{}.", id))
+            .await?;
+    } else {
+        ctx.say(format!("Removed filter rule #{}.", id)).await?;
+    }
+    Ok(())
+}
+
+async fn list_inner(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let data = ctx.data();
+    let rules = data.word_filter.rules(&data.db_pool, guild_id).await?;
+
+    if rules.rules.is_empty() {
+        ctx.say("No filter rules yet, add one with `filter add`.")
+            .await?;
+        return Ok(());
+    }
+
+    let mut exemptions: Vec<String> = rules
+        .exempt_roles
+        .iter()
+        .map(|role| role.mention().to_string())
+        .collect();
+    exemptions.extend(
+        rules
+            .exempt_channels
+            .iter()
+            .map(|channel| channel.mention().to_string()),
+    );
+    let exemptions = if exemptions.is_empty() {
+        "None".to_string()
+    } else {
+        exemptions.join(", ")
+    };
+
+    let pages = rules
+        .rules
+        .chunks(15)
+        .map(|chunk| {
+            let list = chunk
+                .iter()
+                .map(|rule| {
+                    format!(
+                        "**#{}** {} `{}` → {}",
+                        rule.id,
+                        rule.kind.as_str(),
+                        rule.pattern.replace('`', "'"),
+                        rule.action.as_str()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            serenity::CreateEmbed::new()
+                .title(format!("Filter rules ({})", rules.rules.len()))
+                .description(list)
+                .field("Exempt", exemptions.clone(), false)
+                .color(serenity::Color::DARK_RED)
+        })
+        .collect();
+    moderation::paginate(ctx, pages).await
+}
+
+/// List the filter rules and exemptions.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_inner(ctx).await
+}
+
+// Adds or removes an exemption for a role and/or a channel.
+async fn set_exemption(
+    ctx: Context<'_>,
+    role: Option<serenity::Role>,
+    channel: Option<serenity::GuildChannel>,
+    exempt: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or("This command can only be used in a guild.")?;
+    let targets: Vec<(i64, &str, String)> = role
+        .map(|r| (r.id.get() as i64, "role", r.mention().to_string()))
+        .into_iter()
+        .chain(channel.map(|c| (c.id.get() as i64, "channel", c.mention().to_string())))
+        .collect();
+    if targets.is_empty() {
+        ctx.say("❌ Give me a role, a channel, or both.").await?;
+        return Ok(());
+    }
+
+    let data = ctx.data();
+    let guild_id_db = guild_id.get() as i64;
+    for (target_id, kind, _) in &targets {
+        if exempt {
+            sqlx::query!(
+                "INSERT INTO filter_exemptions (guild_id, target_id, kind) VALUES (?, ?, ?)
+                 ON CONFLICT (guild_id, target_id) DO NOTHING",
+                guild_id_db,
+                target_id,
+                kind
+            )
+            .execute(&data.db_pool)
+            .await?;
+        } else {
+            sqlx::query!(
+                "DELETE FROM filter_exemptions WHERE guild_id = ? AND target_id = ?",
+                guild_id_db,
+                target_id
+            )
+            .execute(&data.db_pool)
+            .await?;
+        }
+    }
+    data.word_filter.invalidate(guild_id);
+
+    let names = targets
+        .iter()
+        .map(|(_, _, mention)| mention.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    ctx.send(
+        poise::CreateReply::default()
+            .content(if exempt {
+                format!("{} will now be ignored by the filter.", names)
+            } else {
+                format!("{} will be filtered again.", names)
+            })
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Stop filtering a role or channel.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn exempt(
+    ctx: Context<'_>,
+    #[description = "Role to exempt"] role: Option<serenity::Role>,
+    #[description = "Channel to exempt"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    set_exemption(ctx, role, channel, true).await
+}
+
+/// Start filtering a previously exempt role or channel again.
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn unexempt(
+    ctx: Context<'_>,
+    #[description = "Role to filter again"] role: Option<serenity::Role>,
+    #[description = "Channel to filter again"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    set_exemption(ctx, role, channel, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiled(rules: &[(RuleKind, &str, AutomodAction)]) -> CompiledRules {
+        let rules: Vec<Rule> = rules
+            .iter()
+            .enumerate()
+            .map(|(i, (kind, pattern, action))| Rule {
+                id: i as i64 + 1,
+                kind: *kind,
+                pattern: pattern.to_string(),
+                action: *action,
+            })
+            .collect();
+        CompiledRules {
+            set: RegexSet::new(rules.iter().map(|r| rule_regex(r.kind, &r.pattern))).unwrap(),
+            rules,
+            exempt_roles: HashSet::new(),
+            exempt_channels: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn normalize_undoes_evasion() {
+        assert_eq!(normalize("H3LL0"), "hello");
+        assert_eq!(normalize("b\u{200B}a\u{00AD}d"), "bad");
+        assert_eq!(normalize("$p@m"), "spam");
+    }
+
+    #[test]
+    fn words_match_whole_words_only() {
+        let rules = compiled(&[(RuleKind::Word, "ass", AutomodAction::Delete)]);
+        assert!(rules.find("you A$$").is_some());
+        assert!(rules.find("a\u{200B}ss").is_some());
+        assert!(rules.find("class assignment").is_none());
+    }
+
+    #[test]
+    fn most_severe_rule_wins() {
+        let rules = compiled(&[
+            (RuleKind::Word, "spam", AutomodAction::Warn),
+            (RuleKind::Regex, r"free\s+nitro", AutomodAction::Kick),
+        ]);
+        assert_eq!(rules.find("spam FREE  nitro").unwrap().id, 2);
+        assert_eq!(rules.find("spam").unwrap().action, AutomodAction::Warn);
+    }
+
+    #[test]
+    fn rejects_rules_that_would_break_the_set() {
+        assert!(validate(RuleKind::Word, "spam", &[]).is_ok());
+        assert!(validate(RuleKind::Word, "", &[]).is_err());
+        assert!(validate(RuleKind::Word, "!", &[]).is_err());
+        assert!(validate(RuleKind::Word, "1", &[]).is_err());
+        assert!(validate(RuleKind::Regex, "(", &[]).is_err());
+
+        // Each of these fits on its own, but not all together.
+        let big = r"\w{15}";
+        assert!(validate(RuleKind::Regex, big, &[]).is_ok());
+        let mut existing = Vec::new();
+        while validate(RuleKind::Regex, big, &existing).is_ok() {
+            existing.push(Rule {
+                id: existing.len() as i64 + 1,
+                kind: RuleKind::Regex,
+                pattern: big.to_string(),
+                action: AutomodAction::Delete,
+            });
+            assert!(existing.len() < MAX_RULES, "the set never got too large");
+        }
+        assert!(build_set(existing.iter().map(|r| (r.kind, r.pattern.as_str()))).is_ok());
+    }
+}